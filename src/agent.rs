@@ -0,0 +1,386 @@
+mod protocol;
+
+use std::{
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use ssh_key::HashAlg;
+
+use crate::{fs::Fs, Config};
+
+use self::protocol::{Reader, Writer};
+
+/// An SSH agent serving the SSH Key items of the mounted vaults.
+///
+/// The agent listens on a Unix socket and answers identity list and sign
+/// requests. Every other request is refused, as keys are managed in 1Password.
+///
+/// The keys are read from the filesystem, so that they share its cache of the
+/// items, and are listed from the same vaults.
+pub struct Agent {
+    /// The socket listener
+    listener: UnixListener,
+
+    /// The filesystem serving the keys, shared by every connection
+    fs: Arc<Fs>,
+}
+
+impl Agent {
+    /// Creates the agent socket at the given path.
+    ///
+    /// A stale socket left at the path is replaced. The socket is only
+    /// accessible by the configured owner of the filesystem.
+    pub fn bind(config: &Config, socket: &Path, fs: Arc<Fs>) -> Result<Agent> {
+        let context = || format!("failed to create agent socket {}", socket.display());
+
+        match fs::symlink_metadata(socket) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                fs::remove_file(socket).with_context(context)?;
+            }
+            Ok(_) => bail!("{} exists and is not a socket", socket.display()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(context),
+        }
+
+        let listener = UnixListener::bind(socket).with_context(context)?;
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o600)).with_context(context)?;
+        std::os::unix::fs::chown(socket, Some(config.uid), Some(config.gid))
+            .with_context(context)?;
+
+        Ok(Agent { listener, fs })
+    }
+
+    /// Serves the clients of the agent on a background thread.
+    /// Each connection is handled on its own thread.
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let fs = Arc::clone(&self.fs);
+                        thread::spawn(move || {
+                            if let Err(err) = serve(stream, &fs) {
+                                debug!(err = %err, "SSH agent connection closed");
+                            }
+                        });
+                    }
+                    Err(err) => error!(err = %err, "Failed to accept SSH agent connection"),
+                }
+            }
+        })
+    }
+}
+
+/// Serves the requests of a client until it disconnects.
+fn serve(mut stream: UnixStream, fs: &Fs) -> io::Result<()> {
+    while let Some(request) = protocol::read_message(&mut stream)? {
+        let response = handle(&request, fs).unwrap_or_else(|err| {
+            debug!(err = %err, "SSH agent request failed");
+            Writer::new(protocol::SSH_AGENT_FAILURE).finish()
+        });
+        protocol::write_message(&mut stream, &response)?;
+    }
+    Ok(())
+}
+
+/// Handles a single request and returns the response.
+fn handle(request: &[u8], fs: &Fs) -> Result<Vec<u8>> {
+    let mut request = Reader::new(request);
+
+    match request.byte()? {
+        protocol::SSH_AGENTC_REQUEST_IDENTITIES => {
+            let keys = fs.ssh_keys()?;
+            let mut response = Writer::new(protocol::SSH_AGENT_IDENTITIES_ANSWER);
+            response.u32(u32::try_from(keys.len())?);
+            for key in keys.iter() {
+                response
+                    .string(&key.public_blob()?)
+                    .string(key.comment().as_bytes());
+            }
+            Ok(response.finish())
+        }
+        protocol::SSH_AGENTC_SIGN_REQUEST => {
+            let blob = request.string()?;
+            let data = request.string()?;
+            let flags = request.u32()?;
+
+            let hash = if flags & protocol::SSH_AGENT_RSA_SHA2_512 != 0 {
+                Some(HashAlg::Sha512)
+            } else if flags & protocol::SSH_AGENT_RSA_SHA2_256 != 0 {
+                Some(HashAlg::Sha256)
+            } else {
+                None
+            };
+
+            let keys = fs.ssh_keys()?;
+            let mut key = None;
+            for candidate in keys.iter() {
+                if candidate.public_blob()? == blob {
+                    key = Some(candidate);
+                    break;
+                }
+            }
+            let Some(key) = key else {
+                bail!("unknown key");
+            };
+
+            // The client only reports a failure, e.g. for refused ssh-rsa
+            // (SHA-1) signatures, so the reason is logged here
+            let signature = key.sign(data, hash).inspect_err(|err| {
+                warn!(err = %err, key = key.comment(), "Refused to sign SSH agent request");
+            })?;
+            let signature = Writer::default()
+                .string(signature.algorithm().as_str().as_bytes())
+                .string(signature.as_bytes())
+                .finish();
+
+            Ok(Writer::new(protocol::SSH_AGENT_SIGN_RESPONSE)
+                .string(&signature)
+                .finish())
+        }
+        kind => bail!("unsupported request {kind}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::{pkcs1v15, pkcs8::DecodePrivateKey, signature::Verifier};
+
+    use super::*;
+    use crate::{
+        fs::harness::{private_vault, ssh_item, Harness, FIXTURES},
+        onepassword::{
+            id,
+            sshkey::{SshKey, SSH_KEY_CATEGORY, TEST_ED25519_KEY, TEST_RSA_KEY},
+            types, Fixtures,
+        },
+    };
+
+    /// Returns an item of the given category holding the given private key
+    fn item(id: &str, category: &str, pem: &str) -> types::Secret {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "title": format!("{id} key"),
+            "version": 1,
+            "category": category,
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "fields": [{
+                "id": "private_key",
+                "type": "SSHKEY",
+                "label": "private key",
+                "reference": format!("op://private/{id}/private key"),
+                "value": pem,
+            }],
+        }))
+        .unwrap()
+    }
+
+    /// Returns fixtures with a vault holding an Ed25519 key, an RSA key and a
+    /// key that is not in an SSH Key item
+    fn fixtures() -> Fixtures {
+        let vault = id::Vault::new(&id::Account::new("personal"), "private");
+        let fixtures = Fixtures::from_json("{}").unwrap();
+        fixtures.put_item(&vault, item("ed25519", SSH_KEY_CATEGORY, TEST_ED25519_KEY));
+        fixtures.put_item(&vault, item("rsa", SSH_KEY_CATEGORY, TEST_RSA_KEY));
+        fixtures.put_item(&vault, item("note", "SECURE_NOTE", TEST_RSA_KEY));
+        fixtures
+    }
+
+    /// Returns a filesystem serving the given fixtures, with the given vault
+    /// configuration
+    fn filesystem(fixtures: &Fixtures, vault: &str) -> Fs {
+        let config: Config = toml::from_str(&format!(
+            r#"
+            mountpoint = "/"
+            cache_duration = "60s"
+            [accounts.personal]
+            id = "personal"
+            [accounts.personal.vaults.private]
+            id = "private"
+            {vault}
+            "#
+        ))
+        .unwrap();
+        Fs::new(&config, Box::new(fixtures.clone()))
+    }
+
+    /// Returns a filesystem serving the keys of [`fixtures`]
+    fn keys() -> Fs {
+        filesystem(&fixtures(), "")
+    }
+
+    /// Returns the public key blob of the given private key
+    fn blob(pem: &str) -> Vec<u8> {
+        SshKey::parse(pem, "").unwrap().public_blob().unwrap()
+    }
+
+    /// Sends a sign request and returns the algorithm and signature of the
+    /// response
+    fn sign(keys: &Fs, blob: &[u8], data: &[u8], flags: u32) -> Result<(String, Vec<u8>)> {
+        let request = Writer::new(protocol::SSH_AGENTC_SIGN_REQUEST)
+            .string(blob)
+            .string(data)
+            .u32(flags)
+            .finish();
+        let response = handle(&request, keys)?;
+
+        let mut response = Reader::new(&response);
+        assert_eq!(response.byte()?, protocol::SSH_AGENT_SIGN_RESPONSE);
+        let mut signature = Reader::new(response.string()?);
+        let algorithm = String::from_utf8(signature.string()?.to_vec())?;
+        Ok((algorithm, signature.string()?.to_vec()))
+    }
+
+    #[test]
+    fn lists_identities() {
+        let keys = keys();
+        let response = handle(&[protocol::SSH_AGENTC_REQUEST_IDENTITIES], &keys).unwrap();
+
+        let mut response = Reader::new(&response);
+        assert_eq!(
+            response.byte().unwrap(),
+            protocol::SSH_AGENT_IDENTITIES_ANSWER
+        );
+        assert_eq!(response.u32().unwrap(), 2);
+        let mut identities = Vec::new();
+        for _ in 0..2 {
+            let blob = response.string().unwrap().to_vec();
+            let comment = String::from_utf8(response.string().unwrap().to_vec()).unwrap();
+            identities.push((blob, comment));
+        }
+        identities.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            identities,
+            [
+                (blob(TEST_ED25519_KEY), "ed25519 key".to_string()),
+                (blob(TEST_RSA_KEY), "rsa key".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn signs_with_rsa_sha2() {
+        let keys = keys();
+        let public = rsa::RsaPrivateKey::from_pkcs8_pem(TEST_RSA_KEY)
+            .unwrap()
+            .to_public_key();
+        let blob = blob(TEST_RSA_KEY);
+
+        let (algorithm, signature) =
+            sign(&keys, &blob, b"data", protocol::SSH_AGENT_RSA_SHA2_256).unwrap();
+        assert_eq!(algorithm, "rsa-sha2-256");
+        let signature = pkcs1v15::Signature::try_from(signature.as_slice()).unwrap();
+        let verifier = pkcs1v15::VerifyingKey::<sha2::Sha256>::new(public.clone());
+        assert!(verifier.verify(b"data", &signature).is_ok());
+
+        // SHA-512 is preferred when both are accepted
+        let flags = protocol::SSH_AGENT_RSA_SHA2_256 | protocol::SSH_AGENT_RSA_SHA2_512;
+        let (algorithm, signature) = sign(&keys, &blob, b"data", flags).unwrap();
+        assert_eq!(algorithm, "rsa-sha2-512");
+        let signature = pkcs1v15::Signature::try_from(signature.as_slice()).unwrap();
+        let verifier = pkcs1v15::VerifyingKey::<sha2::Sha512>::new(public);
+        assert!(verifier.verify(b"data", &signature).is_ok());
+
+        // ssh-rsa signatures are refused
+        let err = sign(&keys, &blob, b"data", 0).unwrap_err();
+        assert!(err.to_string().starts_with("ssh-rsa (SHA-1) signatures"));
+    }
+
+    #[test]
+    fn signs_with_ed25519() {
+        let keys = keys();
+        let (algorithm, signature) = sign(&keys, &blob(TEST_ED25519_KEY), b"data", 0).unwrap();
+        assert_eq!(algorithm, "ssh-ed25519");
+        assert_eq!(signature.len(), 64);
+    }
+
+    #[test]
+    fn refuses_unknown_keys() {
+        let keys = keys();
+        let mut unknown = blob(TEST_ED25519_KEY);
+        *unknown.last_mut().unwrap() ^= 1;
+        let err = sign(&keys, &unknown, b"data", 0).unwrap_err();
+        assert_eq!(err.to_string(), "unknown key");
+    }
+
+    #[test]
+    fn refuses_unsupported_requests() {
+        let keys = keys();
+        // SSH_AGENTC_REMOVE_ALL_IDENTITIES
+        let err = handle(&[19], &keys).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported request 19");
+        assert!(handle(&[], &keys).is_err());
+        assert!(handle(&[protocol::SSH_AGENTC_SIGN_REQUEST, 0, 0], &keys).is_err());
+    }
+
+    #[test]
+    fn loads_keys_through_the_filesystem() {
+        // Only the SSH Key items are fetched, and then served from the cache
+        let op = fixtures();
+        let keys = filesystem(&op, "");
+        let first = keys.ssh_keys().unwrap();
+        assert_eq!(first.len(), 2);
+        assert!(Arc::ptr_eq(&first[0], &keys.ssh_keys().unwrap()[0]));
+        assert_eq!(op.calls("list_secrets"), 1);
+        assert_eq!(op.calls("get_secret"), 2);
+
+        // Prefetched vaults are fetched at once
+        let op = fixtures();
+        let keys = filesystem(&op, "prefetch = true");
+        assert_eq!(keys.ssh_keys().unwrap().len(), 2);
+        assert_eq!(op.calls("get_secrets"), 1);
+        assert_eq!(op.calls("get_secret"), 0);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn serves_ssh_agent() {
+        let sockets = tempfile::tempdir().unwrap();
+        let socket = sockets.path().join("agent.sock");
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.ssh_agent = toml::from_str(&format!("socket = {socket:?}")).unwrap();
+        });
+        h.fixtures().put_item(&private_vault(), ssh_item());
+
+        let mut agent = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+        let mut request = |message: &[u8]| {
+            use std::io::{Read, Write};
+            let len = u32::try_from(message.len()).unwrap();
+            agent.write_all(&len.to_be_bytes()).unwrap();
+            agent.write_all(message).unwrap();
+            let mut len = [0u8; 4];
+            agent.read_exact(&mut len).unwrap();
+            let mut response = vec![0u8; u32::from_be_bytes(len) as usize];
+            agent.read_exact(&mut response).unwrap();
+            response
+        };
+        let string = |bytes: &[u8]| {
+            let len = u32::try_from(bytes.len()).unwrap();
+            [&len.to_be_bytes(), bytes].concat()
+        };
+
+        // A single identity: the public key blob, then the item title
+        let identities = request(&[11]);
+        assert_eq!(identities[..5], [12, 0, 0, 0, 1]);
+        let blob = identities[9..60].to_vec();
+        assert_eq!(identities[60..], string(b"Deploy key"));
+
+        let signed = request(&[&[13][..], &string(&blob), &string(b"data"), &[0; 4]].concat());
+        assert_eq!(signed[0], 14);
+        let key = ed25519_dalek::VerifyingKey::from_bytes(blob[19..].try_into().unwrap()).unwrap();
+        let signature = ed25519_dalek::Signature::from_slice(&signed[signed.len() - 64..]).unwrap();
+        key.verify_strict(b"data", &signature).unwrap();
+
+        let unknown = request(&[&[13][..], &string(b"nope"), &string(b"data"), &[0; 4]].concat());
+        assert_eq!(unknown, [5]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Encoding of the messages of the SSH agent protocol, as described in
+//! draft-miller-ssh-agent.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{bail, Result};
+
+/// Failure reply.
+pub const SSH_AGENT_FAILURE: u8 = 5;
+
+/// Request for the list of identities.
+pub const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+
+/// Reply with the list of identities.
+pub const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+
+/// Request for a signature.
+pub const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+
+/// Reply with a signature.
+pub const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+/// Sign request flag asking for a `rsa-sha2-256` signature.
+pub const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+
+/// Sign request flag asking for a `rsa-sha2-512` signature.
+pub const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// The maximum size of a message, as recommended by the protocol.
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// Reads a message from the given stream.
+/// Returns `None` if the stream was closed by the client.
+pub fn read_message<R: Read>(stream: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid message length {len}"),
+        ));
+    }
+
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Writes a message to the given stream.
+pub fn write_message<W: Write>(stream: &mut W, message: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(message.len()).expect("message should fit u32");
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(message)?;
+    stream.flush()
+}
+
+/// A reader for the fields of a message.
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Creates a reader over the given message.
+    pub fn new(message: &'a [u8]) -> Reader<'a> {
+        Reader(message)
+    }
+
+    /// Reads a single byte.
+    pub fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a big-endian 32-bit integer.
+    pub fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a length-prefixed string.
+    pub fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads the given number of bytes.
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("truncated message");
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+}
+
+/// A writer for the fields of a message.
+#[derive(Default)]
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    /// Creates a message of the given type.
+    pub fn new(kind: u8) -> Writer {
+        Writer(vec![kind])
+    }
+
+    /// Writes a big-endian 32-bit integer.
+    pub fn u32(&mut self, value: u32) -> &mut Writer {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// Writes a length-prefixed string.
+    pub fn string(&mut self, value: &[u8]) -> &mut Writer {
+        let len = u32::try_from(value.len()).expect("string should fit u32");
+        self.u32(len);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    /// Returns the encoded message.
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Reads a message from the given bytes
+    fn read(bytes: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        read_message(&mut Cursor::new(bytes))
+    }
+
+    #[test]
+    fn round_trips_messages() {
+        let message = Writer::new(SSH_AGENTC_SIGN_REQUEST)
+            .string(b"key")
+            .string(b"")
+            .u32(SSH_AGENT_RSA_SHA2_256)
+            .finish();
+
+        let mut stream = Vec::new();
+        write_message(&mut stream, &message).unwrap();
+        write_message(&mut stream, &[SSH_AGENTC_REQUEST_IDENTITIES]).unwrap();
+        assert_eq!(stream[..4], [0, 0, 0, 16]);
+
+        let mut stream = Cursor::new(stream);
+        let first = read_message(&mut stream).unwrap().unwrap();
+        assert_eq!(first, message);
+        let second = read_message(&mut stream).unwrap().unwrap();
+        assert_eq!(second, [SSH_AGENTC_REQUEST_IDENTITIES]);
+        assert!(read_message(&mut stream).unwrap().is_none());
+
+        let mut reader = Reader::new(&first);
+        assert_eq!(reader.byte().unwrap(), SSH_AGENTC_SIGN_REQUEST);
+        assert_eq!(reader.string().unwrap(), b"key");
+        assert_eq!(reader.string().unwrap(), b"");
+        assert_eq!(reader.u32().unwrap(), SSH_AGENT_RSA_SHA2_256);
+        assert!(reader.byte().is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let err = read(&[0, 0, 0, 4, 11, 0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A string longer than the rest of the message
+        let message = [SSH_AGENTC_SIGN_REQUEST, 0, 0, 0, 8, 1, 2];
+        let mut reader = Reader::new(&message);
+        reader.byte().unwrap();
+        assert!(reader.string().is_err());
+        assert!(Reader::new(&[0, 0]).u32().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        let err = read(&[0, 0, 0, 0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let oversized = u32::try_from(MAX_MESSAGE_SIZE + 1).unwrap();
+        let err = read(&oversized.to_be_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut largest = u32::try_from(MAX_MESSAGE_SIZE)
+            .unwrap()
+            .to_be_bytes()
+            .to_vec();
+        largest.resize(4 + MAX_MESSAGE_SIZE, 0);
+        assert_eq!(read(&largest).unwrap().unwrap().len(), MAX_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn reports_closed_streams() {
+        assert!(read(&[]).unwrap().is_none());
+    }
+}
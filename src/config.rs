@@ -5,7 +5,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 /// The 1Password-Fuse configuration object
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,17 +30,55 @@ pub struct Config {
     #[serde(default = "default_file_mode")]
     pub file_mode: u16,
 
-    /// The duration to cache 1Password data for
+    /// The duration to cache 1Password data for, unless overridden for an
+    /// account, a vault or an item
     #[serde(default = "default_cache_duration", with = "humantime_serde")]
     pub cache_duration: Duration,
 
+    /// Whether to fetch the fields of every secret when a vault is listed,
+    /// unless overridden for an account or a vault
+    #[serde(default)]
+    pub prefetch: bool,
+
+    /// The duration the kernel caches the entries of directories for.
+    /// The attributes of the entries are cached for the same duration.
+    #[serde(default, with = "humantime_serde")]
+    pub entry_ttl: Duration,
+
+    /// The duration the kernel caches the attributes of files for
+    #[serde(default, with = "humantime_serde")]
+    pub attr_ttl: Duration,
+
+    /// The number of threads serving filesystem requests
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+
+    /// Refreshing of vaults and secrets outside of the requests needing them
+    #[serde(default)]
+    pub refresh: Refresh,
+
+    /// Handling of failed refreshes
+    #[serde(default)]
+    pub failures: Failures,
+
     /// The 1Password accounts to use, and their configuration
     #[serde(default)]
     pub accounts: HashMap<String, Account>,
 
+    /// Automatic discovery of accounts and vaults
+    #[serde(default)]
+    pub discovery: Discovery,
+
+    /// Virtual directories listing secrets by tag, category or favorite status
+    #[serde(default)]
+    pub indexes: Indexes,
+
     /// 1Password-related configuration
     #[serde(default, rename = "onepassword")]
     pub op: OnePassword,
+
+    /// The built-in SSH agent, disabled if not present
+    pub ssh_agent: Option<SshAgent>,
 }
 
 fn default_uid_gid() -> u32 {
@@ -59,11 +97,66 @@ fn default_cache_duration() -> Duration {
     Duration::from_secs(5)
 }
 
+fn default_workers() -> usize {
+    8
+}
+
 impl Config {
     /// Read a configuration file from the given path
     pub fn read(path: &Path) -> Result<Config> {
         Ok(toml::from_str(&fs::read_to_string(path)?)?)
     }
+
+    /// Returns the configuration of the account with the given 1Password ID
+    pub fn account(&self, id: &str) -> Option<&Account> {
+        self.accounts.values().find(|account| account.id == id)
+    }
+
+    /// Returns the duration to cache the listing of a vault for, given the
+    /// 1Password IDs of its account and of the vault
+    pub fn listing_cache(&self, account: &str, vault: &str) -> Duration {
+        let account = self.account(account);
+        let vault = account.and_then(|account| account.vault(vault));
+        vault
+            .and_then(|vault| vault.cache.listing)
+            .or_else(|| account.and_then(|account| account.cache.listing))
+            .unwrap_or(self.cache_duration)
+    }
+
+    /// Returns the duration to cache the fields of an item for, given the
+    /// 1Password IDs of its account and vault, and its ID and title
+    pub fn fields_cache(&self, account: &str, vault: &str, id: &str, title: &str) -> Duration {
+        let account = self.account(account);
+        let vault = account.and_then(|account| account.vault(vault));
+        vault
+            .and_then(|vault| vault.items.get(id).or_else(|| vault.items.get(title)))
+            .and_then(|item| item.cache)
+            .or_else(|| vault.and_then(|vault| vault.cache.fields))
+            .or_else(|| account.and_then(|account| account.cache.fields))
+            .unwrap_or(self.cache_duration)
+    }
+
+    /// Returns whether to fetch the fields of every secret when listing a
+    /// vault, given the 1Password IDs of its account and of the vault
+    pub fn prefetch(&self, account: &str, vault: &str) -> bool {
+        let account = self.account(account);
+        let vault = account.and_then(|account| account.vault(vault));
+        vault
+            .and_then(|vault| vault.prefetch)
+            .or_else(|| account.and_then(|account| account.prefetch))
+            .unwrap_or(self.prefetch)
+    }
+}
+
+/// SSH agent configuration
+///
+/// The agent serves the SSH Key items of the configured vaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SshAgent {
+    /// The path of the Unix socket to listen on.
+    /// The socket is owned by `uid` and `gid` and only accessible by its owner.
+    pub socket: PathBuf,
 }
 
 /// 1Password account configuration
@@ -76,6 +169,34 @@ pub struct Account {
     /// The vaults to mount from this account
     #[serde(default)]
     pub vaults: HashMap<String, Vault>,
+
+    /// The service account token to use for this account.
+    /// Passed to the 1Password CLI as `OP_SERVICE_ACCOUNT_TOKEN`.
+    pub service_account_token: Option<Token>,
+
+    /// The 1Password CLI configuration directory to use for this account.
+    /// Passed to the 1Password CLI as `OP_CONFIG_DIR`.
+    pub config_dir: Option<PathBuf>,
+
+    /// Additional environment variables to set when calling the 1Password CLI
+    /// for this account
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// The cache durations of the vaults of this account
+    #[serde(default)]
+    pub cache: Cache,
+
+    /// Whether to prefetch the vaults of this account, overriding the global
+    /// setting
+    pub prefetch: Option<bool>,
+}
+
+impl Account {
+    /// Returns the configuration of the vault with the given 1Password ID
+    pub fn vault(&self, id: &str) -> Option<&Vault> {
+        self.vaults.values().find(|vault| vault.id == id)
+    }
 }
 
 /// 1Password vault configuration
@@ -84,24 +205,418 @@ pub struct Account {
 pub struct Vault {
     /// The 1Password vault ID
     pub id: String,
+
+    /// Whether to fetch the fields of every secret when the vault is listed,
+    /// overriding the setting of its account.
+    ///
+    /// This trades a bigger call when listing the vault for not having to
+    /// fetch each secret separately when reading its fields.
+    pub prefetch: Option<bool>,
+
+    /// The cache durations of this vault, overriding the ones of its account
+    #[serde(default)]
+    pub cache: Cache,
+
+    /// The items of this vault with their own configuration, by ID or title
+    #[serde(default)]
+    pub items: HashMap<String, Item>,
+}
+
+/// 1Password item configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Item {
+    /// The duration to cache the fields of this item for, overriding the one
+    /// of its vault. Zero fetches the item whenever it is opened.
+    #[serde(default, with = "humantime_serde")]
+    pub cache: Option<Duration>,
+}
+
+/// Cache durations, overriding the global `cache_duration`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cache {
+    /// The duration to cache vault listings for
+    #[serde(default, with = "humantime_serde")]
+    pub listing: Option<Duration>,
+
+    /// The duration to cache the fields of items for. Zero fetches the items
+    /// whenever they are opened.
+    #[serde(default, with = "humantime_serde")]
+    pub fields: Option<Duration>,
+}
+
+/// Automatic discovery configuration
+///
+/// Discovered accounts and vaults are named after their 1Password names.
+/// Configured accounts and vaults take precedence over discovered ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Discovery {
+    /// Whether to discover accounts with `op account list`.
+    /// The vaults of discovered accounts are always discovered.
+    #[serde(default)]
+    pub accounts: bool,
+
+    /// Whether to discover the vaults of configured accounts with
+    /// `op vault list`
+    #[serde(default)]
+    pub vaults: bool,
+
+    /// Glob patterns of the account and vault names to discover.
+    /// Every name is included if empty.
+    #[serde(default)]
+    pub include: Vec<Glob>,
+
+    /// Glob patterns of the account and vault names not to discover
+    #[serde(default)]
+    pub exclude: Vec<Glob>,
+}
+
+impl Discovery {
+    /// Returns whether a discovered account or vault with the given name
+    /// should be mounted
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.0.matches(name)))
+            && !self.exclude.iter().any(|glob| glob.0.matches(name))
+    }
+}
+
+/// Refresh configuration
+///
+/// By default, expired data is refreshed by the request needing it, which
+/// then waits for 1Password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Refresh {
+    /// Whether to refresh recently used vaults and secrets in the background
+    /// before they expire
+    #[serde(default)]
+    pub background: bool,
+
+    /// The duration a vault or secret is refreshed in the background for,
+    /// after it was last used
+    #[serde(default = "default_hot_duration", with = "humantime_serde")]
+    pub hot_duration: Duration,
+
+    /// The duration expired vaults and secrets are still served for, while
+    /// they are refreshed in the background. Disabled if zero.
+    #[serde(default, with = "humantime_serde")]
+    pub max_stale: Duration,
+}
+
+fn default_hot_duration() -> Duration {
+    Duration::from_secs(300)
+}
+
+impl Refresh {
+    /// Returns whether vaults and secrets are ever refreshed in the
+    /// background.
+    pub fn enabled(&self) -> bool {
+        self.background || !self.max_stale.is_zero()
+    }
+}
+
+impl Default for Refresh {
+    fn default() -> Self {
+        toml::from_str("").expect("empty object should be valid")
+    }
+}
+
+/// Failed refreshes configuration
+///
+/// Failed refreshes are retried after a delay doubling with each consecutive
+/// failure, from `min_backoff` to `max_backoff`, minus up to a quarter of
+/// random jitter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Failures {
+    /// Whether to keep serving the last known data while refreshes fail,
+    /// instead of failing the requests. Data that is never cached is never
+    /// served either.
+    #[serde(default = "default_true")]
+    pub serve_stale: bool,
+
+    /// The delay before retrying after a first failure
+    #[serde(default = "default_min_backoff", with = "humantime_serde")]
+    pub min_backoff: Duration,
+
+    /// The maximum delay before retrying
+    #[serde(default = "default_max_backoff", with = "humantime_serde")]
+    pub max_backoff: Duration,
+}
+
+fn default_min_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_max_backoff() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl Failures {
+    /// Returns the delay before retrying after the given number of
+    /// consecutive failures, without jitter.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.min_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for Failures {
+    fn default() -> Self {
+        toml::from_str("").expect("empty object should be valid")
+    }
+}
+
+/// Index directories configuration
+///
+/// Index directories contain symlinks to the secrets with a given tag or
+/// category, or marked as favorites.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Indexes {
+    /// Whether to add index directories to each vault
+    #[serde(default = "default_true")]
+    pub vaults: bool,
+
+    /// Whether to add index directories to the root, across all vaults.
+    /// Listing them requires listing every vault.
+    #[serde(default)]
+    pub global: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Indexes {
+    fn default() -> Self {
+        toml::from_str("").expect("empty object should be valid")
+    }
+}
+
+/// A glob pattern
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Glob(glob::Pattern);
+
+impl TryFrom<String> for Glob {
+    type Error = glob::PatternError;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Ok(Glob(glob::Pattern::new(&pattern)?))
+    }
+}
+
+impl From<Glob> for String {
+    fn from(glob: Glob) -> String {
+        glob.0.as_str().to_string()
+    }
 }
 
 /// 1Password configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct OnePassword {
+    /// The backend used to access 1Password data
+    #[serde(default)]
+    pub backend: Backend,
+
     /// The 1Password command to run
     #[serde(default = "default_op_cmd")]
     pub cmd: String,
+
+    /// The maximum duration of a single call to 1Password.
+    /// Calls that take longer, e.g. while waiting for an approval in the
+    /// desktop app, are aborted.
+    #[serde(default = "default_op_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+
+    /// Whether removed item directories move their items to the archive,
+    /// rather than deleting them permanently
+    #[serde(default = "default_true")]
+    pub archive: bool,
+
+    /// The 1Password Connect server configuration.
+    /// Required when using the `connect` backend.
+    pub connect: Option<Connect>,
+
+    /// The JSON file to read fixtures from.
+    /// Required when using the `fixtures` backend.
+    pub fixtures: Option<PathBuf>,
+}
+
+/// The backend used to access 1Password data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// The 1Password CLI
+    #[default]
+    Cli,
+
+    /// A 1Password Connect server
+    Connect,
+
+    /// Static fixtures read from a JSON file, for testing
+    Fixtures,
+}
+
+/// 1Password Connect server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Connect {
+    /// The URL of the Connect server
+    pub host: String,
+
+    /// The access token of the Connect server
+    pub token: Token,
+}
+
+/// A token read from outside of the configuration file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum Token {
+    /// The token is read from the given file
+    File(PathBuf),
+
+    /// The token is read from the given environment variable
+    Env(String),
+}
+
+impl Token {
+    /// Reads the token value
+    pub fn read(&self) -> Result<String> {
+        let token = match self {
+            Token::File(path) => fs::read_to_string(path)
+                .with_context(|| format!("failed to read token file {}", path.display()))?,
+            Token::Env(name) => std::env::var(name)
+                .with_context(|| format!("failed to read token variable {name}"))?,
+        };
+        Ok(token.trim().to_string())
+    }
 }
 
 fn default_op_cmd() -> String {
     "op".to_string()
 }
 
+fn default_op_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
 impl Default for OnePassword {
     /// The default 1Password-Fuse configuration
     fn default() -> Self {
         toml::from_str("").expect("empty object should be valid")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        mountpoint = "/mnt/op"
+        cache_duration = "10s"
+
+        [accounts.personal]
+        id = "ACCOUNTID"
+        cache = { listing = "1m", fields = "2m" }
+
+        [accounts.personal.vaults.private]
+        id = "VAULTID"
+        cache = { fields = "0s" }
+        items = { Database = { cache = "1h" }, itemid = { cache = "2h" } }
+    "#;
+
+    #[test]
+    fn applies_defaults() {
+        let config: Config = toml::from_str(r#"mountpoint = "/mnt/op""#).unwrap();
+        assert_eq!(config.cache_duration, Duration::from_secs(5));
+        assert_eq!(config.file_mode, 0o400);
+        assert_eq!(config.op.backend, Backend::Cli);
+        assert_eq!(config.op.timeout, Duration::from_secs(60));
+        assert!(config.op.archive && config.failures.serve_stale);
+        assert!(!config.refresh.enabled());
+        assert!(config.ssh_agent.is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let config = toml::from_str::<Config>("mountpoint = \"/mnt/op\"\ncache = \"1s\"");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn resolves_cache_durations() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let secs = Duration::from_secs;
+
+        assert_eq!(config.listing_cache("ACCOUNTID", "VAULTID"), secs(60));
+        assert_eq!(config.listing_cache("OTHER", "VAULTID"), secs(10));
+
+        // Items are configured by ID or title, the ID taking precedence
+        let fields = |id, title| config.fields_cache("ACCOUNTID", "VAULTID", id, title);
+        assert_eq!(fields("other", "Other"), Duration::ZERO);
+        assert_eq!(fields("dbid", "Database"), secs(3600));
+        assert_eq!(fields("itemid", "Database"), secs(7200));
+        assert_eq!(
+            config.fields_cache("ACCOUNTID", "OTHER", "dbid", "Database"),
+            secs(120)
+        );
+    }
+
+    #[test]
+    fn resolves_prefetch() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        assert!(!config.prefetch("ACCOUNTID", "VAULTID"));
+
+        // Discovered vaults and accounts get the settings of their account,
+        // or the global ones
+        config.prefetch = true;
+        assert!(config.prefetch("ACCOUNTID", "OTHER"));
+        assert!(config.prefetch("OTHER", "OTHER"));
+        let account = config.accounts.get_mut("personal").unwrap();
+        account.prefetch = Some(false);
+        account.vaults.get_mut("private").unwrap().prefetch = Some(true);
+        assert!(!config.prefetch("ACCOUNTID", "OTHER"));
+        assert!(config.prefetch("ACCOUNTID", "VAULTID"));
+    }
+
+    #[test]
+    fn doubles_backoff_up_to_maximum() {
+        let failures: Failures = toml::from_str(r#"max_backoff = "5s""#).unwrap();
+        let backoffs = (1..=5)
+            .map(|attempts| failures.backoff(attempts).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(backoffs, [1, 2, 4, 5, 5]);
+        assert_eq!(failures.backoff(u32::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn matches_discovered_names() {
+        let discovery: Discovery = toml::from_str(
+            r#"include = ["team-*"]
+exclude = ["*-archive"]"#,
+        )
+        .unwrap();
+        assert!(discovery.matches("team-infra"));
+        assert!(!discovery.matches("team-archive"));
+        assert!(!discovery.matches("personal"));
+        assert!(Discovery::default().matches("personal"));
+    }
+
+    #[test]
+    fn reads_trimmed_tokens() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "secret-token\n").unwrap();
+        let token = Token::File(file.path().to_path_buf());
+        assert_eq!(token.read().unwrap(), "secret-token");
+
+        let missing = Token::Env("OP_FUSE_TEST_MISSING_TOKEN".to_string());
+        assert!(missing.read().is_err());
+    }
+}
@@ -1,14 +1,24 @@
-use std::{ffi::OsStr, rc::Rc};
+use std::{ffi::OsStr, io, path::Path, sync::Arc, time::Duration};
 
 #[allow(clippy::wildcard_imports)]
 use fuser::*;
 use libc::c_int;
 
-use crate::{fs::node::Node, Config, OnePassword};
+use crate::{
+    fs::{dentry::DirEntry, dispatcher::Dispatcher, node::Node},
+    onepassword::{sshkey::SshKey, Backend},
+    util::{Lock, Throttle},
+    Config,
+};
 use ::slab::Slab;
 
 mod dentry;
+mod dispatcher;
+#[cfg(test)]
+pub mod harness;
+mod invalidator;
 mod node;
+mod refresher;
 mod slab;
 mod syscalls;
 
@@ -18,31 +28,51 @@ pub type Inode = u64;
 /// A pointer to a slab item
 pub type FileHandle = u64;
 
-/// A dummy TTL of 0 seconds
-///
-/// Currently used for all cachable replies. That way the kernel will always
-/// revalidate the cache. It will become handy when we implement access control.
-pub const TTL_ZERO: std::time::Duration = std::time::Duration::from_secs(0);
-
 /// The 1Password-Fuse filesystem
+///
+/// It is shared by the worker threads of the [`Dispatcher`], so that a slow
+/// request does not block the others, and by the SSH agent.
 pub struct Fs {
     config: Config,
-    op: OnePassword,
+    op: Box<dyn Backend>,
     nodes: node::Set,
-    slab: Slab<slab::Item>,
+    slab: Lock<Slab<slab::Item>>,
+    refresher: refresher::Refresher,
+    invalidator: invalidator::Invalidator,
+}
+
+/// Mounts the filesystem on the given mountpoint, and serves it until it is
+/// unmounted.
+///
+/// If the kernel caches entries or attributes, it is notified of the nodes
+/// that change when refreshed.
+pub fn mount(fs: Arc<Fs>, mountpoint: &Path, options: &[MountOption]) -> io::Result<()> {
+    let workers = fs.config.workers;
+    let notify = !fs.config.entry_ttl.is_zero() || !fs.config.attr_ttl.is_zero();
+
+    let dispatcher = Dispatcher::new(fs, workers);
+    let fs = dispatcher.fs();
+    let mut session = Session::new(dispatcher, mountpoint, options)?;
+    if notify {
+        fs.invalidator.start(session.notifier());
+    }
+    session.run()
 }
 
 impl Fs {
-    /// Creates a new filesystem from the given config and 1Password client
-    pub fn new(config: &Config, op: OnePassword) -> Fs {
+    /// Creates a new filesystem from the given config and 1Password backend
+    pub fn new(config: &Config, op: Box<dyn Backend>) -> Fs {
         let fs = Fs {
             config: config.clone(),
             op,
             nodes: node::Set::new(),
-            slab: Slab::new(),
+            slab: Lock::new(Slab::new()),
+            refresher: refresher::Refresher::default(),
+            invalidator: invalidator::Invalidator::default(),
         };
 
         assert_eq!(0, fs.node_alloc(|_| Node::new_dummy()).persist());
+        assert_eq!(0, fs.slab_alloc(slab::Item::Reserved));
         assert_eq!(
             FUSE_ROOT_ID,
             fs.node_alloc(|_| Node::new_root(&fs)).persist()
@@ -51,7 +81,7 @@ impl Fs {
         fs
     }
 
-    /**
+    /*
      * Node management
      */
 
@@ -64,34 +94,103 @@ impl Fs {
     }
 
     /// Gets a node by its inode
-    fn node_get(&self, ino: Inode) -> Rc<node::Node> {
+    fn node_get(&self, ino: Inode) -> Arc<node::Node> {
         self.nodes.get(ino)
     }
 
-    /**
-     * Slab management
+    /*
+     * Cache management
      */
 
-    /// Allocates a new slab item
-    fn slab_alloc(&mut self, item: slab::Item) -> FileHandle {
-        self.slab.insert(item) as FileHandle
+    /// Refreshes the cache of a node if it is older than `max_age`.
+    ///
+    /// A cache expired for less than the configured `max_stale` is served as
+    /// is, while the node is queued to be refreshed in the background. Nodes
+    /// that are never cached are always refreshed.
+    fn refresh_cache<T, F>(
+        &self,
+        ino: Inode,
+        cache: &Lock<Throttle<T>>,
+        max_age: Duration,
+        refresh: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut T) -> anyhow::Result<()>,
+    {
+        let max_stale = if max_age.is_zero() {
+            Duration::ZERO
+        } else {
+            self.config.refresh.max_stale
+        };
+        let generation = || self.nodes.resolve(ino).map(|(_, generation)| generation);
+        if self.config.refresh.background {
+            if let Some(generation) = generation() {
+                self.refresher.touch(ino, generation);
+            }
+        }
+
+        let mut cache = cache.lock();
+        let stale = cache
+            .age()
+            .is_some_and(|age| age >= max_age && age < max_age.saturating_add(max_stale));
+        if stale {
+            drop(cache);
+            if let Some(generation) = generation() {
+                self.refresher.schedule(ino, generation);
+            }
+            return Ok(());
+        }
+        cache.try_refresh(max_age, &self.config.failures, refresh)
     }
 
-    /// Gets a slab item by its file handle
-    fn slab_get(&self, fh: FileHandle) -> Option<&slab::Item> {
-        self.slab.get(u64_to_usize(fh))
+    /// Invalidates the kernel cache of the entries of a directory that were
+    /// removed or now refer to another node.
+    fn invalidate_entries(&self, parent: Inode, before: &[DirEntry], after: &[DirEntry]) {
+        for entry in before {
+            let current = after.iter().find(|e| e.name == entry.name);
+            if current.is_none_or(|e| e.inode != entry.inode) {
+                self.invalidator.entry(parent, &entry.name);
+            }
+        }
+    }
+
+    /// Invalidates the kernel cache of the attributes and content of a node.
+    fn invalidate_inode(&self, ino: Inode) {
+        self.invalidator.inode(ino);
     }
 
-    /// Gets a mutable slab item by its file handle
-    #[allow(dead_code)]
-    fn slab_get_mut(&mut self, fh: FileHandle) -> Option<&mut slab::Item> {
-        self.slab.get_mut(u64_to_usize(fh))
+    /*
+     * SSH agent
+     */
+
+    /// Returns the private keys of the SSH Key items of the mounted vaults.
+    ///
+    /// The keys are loaded through the nodes of the filesystem, so that they
+    /// follow the cache durations, discovery and prefetching of their vaults.
+    pub fn ssh_keys(&self) -> anyhow::Result<Vec<Arc<SshKey>>> {
+        match self.node_get(FUSE_ROOT_ID).as_ref() {
+            Node::Root(root) => root.ssh_keys(self),
+            _ => unreachable!("node should be the root"),
+        }
+    }
+
+    /*
+     * Slab management
+     */
+
+    /// Allocates a new slab item
+    fn slab_alloc(&self, item: slab::Item) -> FileHandle {
+        self.slab.lock().insert(item) as FileHandle
+    }
+
+    /// Gets a copy of a slab item by its file handle
+    fn slab_get(&self, fh: FileHandle) -> Option<slab::Item> {
+        self.slab.lock().get(u64_to_usize(fh)).cloned()
     }
 
     /// Frees a slab item by its file handle
-    #[allow(dead_code)]
-    fn slab_free(&mut self, fh: FileHandle) {
-        self.slab.remove(u64_to_usize(fh));
+    fn slab_free(&self, fh: FileHandle) {
+        self.slab.lock().remove(u64_to_usize(fh));
     }
 }
 
@@ -100,29 +199,41 @@ fn u64_to_usize(x: u64) -> usize {
     usize::try_from(x).expect("pointers should be 64 bits")
 }
 
-impl fuser::Filesystem for Fs {
-    fn getattr(&mut self, _req: &Request, ino: Inode, reply: ReplyAttr) {
+/*
+ * Request handlers, run by the dispatcher on its worker threads
+ */
+
+impl Fs {
+    /// Returns the duration the kernel caches the attributes of a node for.
+    ///
+    /// Nodes that are never cached are not cached by the kernel either. The
+    /// kernel holds a reference to the node, so that its inode is not reused.
+    fn attr_ttl(&self, ino: Inode) -> Duration {
+        if self.node_get(ino).volatile(self) {
+            Duration::ZERO
+        } else {
+            self.config.attr_ttl
+        }
+    }
+
+    /// Replies to a `getattr` request.
+    fn getattr(&self, ino: Inode, reply: ReplyAttr) {
         match syscalls::getattr(self, ino) {
-            Ok(attr) => reply.attr(&TTL_ZERO, &attr),
+            Ok(attr) => reply.attr(&self.attr_ttl(ino), &attr),
             Err(errno) => reply.error(trace_err(errno)),
         }
     }
 
-    fn opendir(&mut self, _req: &Request, ino: Inode, _flags: i32, reply: ReplyOpen) {
+    /// Replies to an `opendir` request.
+    fn opendir(&self, ino: Inode, reply: ReplyOpen) {
         match syscalls::opendir(self, ino) {
             Ok(fh) => reply.opened(fh, 0),
             Err(errno) => reply.error(trace_err(errno)),
         }
     }
 
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: Inode,
-        fh: FileHandle,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
+    /// Replies to a `readdir` request.
+    fn readdir(&self, ino: Inode, fh: FileHandle, offset: i64, mut reply: ReplyDirectory) {
         match syscalls::readdir(self, ino, fh, offset) {
             Ok(entries) => {
                 for (offset, entry) in entries {
@@ -136,75 +247,198 @@ impl fuser::Filesystem for Fs {
         }
     }
 
-    fn releasedir(
-        &mut self,
-        _req: &Request,
-        ino: Inode,
-        fh: FileHandle,
-        _flags: i32,
-        reply: ReplyEmpty,
-    ) {
+    /// Replies to a `releasedir` request.
+    fn releasedir(&self, ino: Inode, fh: FileHandle, reply: ReplyEmpty) {
         match syscalls::releasedir(self, ino, fh) {
             Ok(()) => reply.ok(),
             Err(errno) => reply.error(trace_err(errno)),
         }
     }
 
-    fn lookup(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEntry) {
-        match syscalls::lookup(self, parent, name).and_then(|ino| syscalls::getattr(self, ino)) {
-            Ok(attr) => reply.entry(&TTL_ZERO, &attr, 1),
+    /// Returns the attributes and generation of a node replied as an entry of
+    /// its parent, along with the duration the kernel caches them for, and
+    /// records that the kernel looked it up.
+    ///
+    /// The node is kept until the kernel forgets it, even if it is removed
+    /// from its parent meanwhile. Entries of directories or nodes that are
+    /// never cached are not cached by the kernel either.
+    fn entry(&self, parent: Inode, ino: Inode) -> syscalls::Result<Entry> {
+        // The attributes are built without holding the slab, as they may wait
+        // for the node. The lookup is then only recorded if the inode still
+        // holds the same node.
+        let (node, generation) = self.nodes.resolve(ino).ok_or(libc::ENOENT)?;
+        let attr = node.attr(self).ok_or(libc::ENOENT)?;
+        let ttl = if node.volatile(self) || self.node_get(parent).volatile(self) {
+            Duration::ZERO
+        } else {
+            self.config.entry_ttl
+        };
+        if !self.nodes.lookup(ino, generation) {
+            return Err(libc::ENOENT);
+        }
+        Ok(Entry {
+            ttl,
+            attr,
+            generation,
+        })
+    }
+
+    /// Replies to a `lookup` request.
+    fn lookup(&self, parent: Inode, name: &OsStr, reply: ReplyEntry) {
+        match syscalls::lookup(self, parent, name).and_then(|ino| self.entry(parent, ino)) {
+            // The attributes are cached along with the entry
+            Ok(entry) => reply.entry(&entry.ttl, &entry.attr, entry.generation),
             Err(errno) => reply.error(trace_err(errno)),
         }
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
-        ino: Inode,
-        _fh: Inode,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyData,
-    ) {
-        match syscalls::read(self, ino, offset, size) {
-            Ok(data) => reply.data(&data),
+    /// Replies to a `mkdir` request.
+    fn mkdir(&self, parent: Inode, name: &OsStr, reply: ReplyEntry) {
+        match syscalls::mkdir(self, parent, name).and_then(|ino| self.entry(parent, ino)) {
+            Ok(entry) => reply.entry(&entry.ttl, &entry.attr, entry.generation),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `mknod` request.
+    fn mknod(&self, parent: Inode, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        match syscalls::mknod(self, parent, name, mode).and_then(|ino| self.entry(parent, ino)) {
+            Ok(entry) => reply.entry(&entry.ttl, &entry.attr, entry.generation),
             Err(errno) => reply.error(trace_err(errno)),
         }
     }
 
-    fn flush(
-        &mut self,
-        _req: &Request,
-        _ino: Inode,
-        _fh: u64,
-        _lock_owner: u64,
-        reply: ReplyEmpty,
-    ) {
-        reply.ok();
+    /// Replies to an `unlink` request.
+    fn unlink(&self, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+        match syscalls::unlink(self, parent, name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
     }
 
-    fn release(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        _fh: u64,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        _flush: bool,
-        reply: ReplyEmpty,
-    ) {
-        // Not used as we don't keep file handles
-        reply.ok();
+    /// Replies to a `rmdir` request.
+    fn rmdir(&self, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+        match syscalls::rmdir(self, parent, name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
     }
 
-    fn readlink(&mut self, _req: &Request, ino: Inode, reply: ReplyData) {
+    /// Replies to a `create` request.
+    fn create(&self, parent: Inode, name: &OsStr, flags: i32, reply: ReplyCreate) {
+        let created = syscalls::create(self, parent, name, flags)
+            .and_then(|(ino, fh)| Ok((self.entry(parent, ino)?, fh)));
+        match created {
+            Ok((entry, fh)) => {
+                reply.created(&entry.ttl, &entry.attr, entry.generation, fh, 0);
+            }
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Handles a `forget` request.
+    fn forget(&self, ino: Inode, nlookup: u64) {
+        self.nodes.forget(ino, nlookup);
+    }
+
+    /// Replies to an `open` request.
+    fn open(&self, ino: Inode, flags: i32, reply: ReplyOpen) {
+        match syscalls::open(self, ino, flags) {
+            Ok((fh, flags)) => reply.opened(fh, flags),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `write` request.
+    fn write(&self, ino: Inode, fh: FileHandle, offset: i64, data: &[u8], reply: ReplyWrite) {
+        match syscalls::write(self, ino, fh, offset, data) {
+            Ok(size) => reply.written(size),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `setattr` request.
+    fn setattr(&self, ino: Inode, fh: Option<FileHandle>, size: Option<u64>, reply: ReplyAttr) {
+        match syscalls::setattr(self, ino, fh, size) {
+            Ok(attr) => reply.attr(&self.attr_ttl(ino), &attr),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `flush` request.
+    fn flush(&self, ino: Inode, fh: FileHandle, reply: ReplyEmpty) {
+        match syscalls::flush(self, ino, fh) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `release` request.
+    fn release(&self, ino: Inode, fh: FileHandle, reply: ReplyEmpty) {
+        match syscalls::release(self, ino, fh) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `read` request.
+    fn read(&self, ino: Inode, fh: FileHandle, offset: i64, size: u32, reply: ReplyData) {
+        match syscalls::read(self, ino, fh, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `readlink` request.
+    fn readlink(&self, ino: Inode, reply: ReplyData) {
         match syscalls::read_link(self, ino) {
             Ok(target) => reply.data(target.as_bytes()),
             Err(errno) => reply.error(trace_err(errno)),
         }
     }
+
+    /// Replies to a `getxattr` request.
+    fn getxattr(&self, ino: Inode, name: &OsStr, size: u32, reply: ReplyXattr) {
+        match syscalls::getxattr(self, ino, name) {
+            Ok(value) => reply_xattr(reply, &value, size),
+            // Missing attributes are routinely probed for, e.g. by `ls`
+            Err(libc::ENODATA) => reply.error(libc::ENODATA),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+
+    /// Replies to a `listxattr` request.
+    fn listxattr(&self, ino: Inode, size: u32, reply: ReplyXattr) {
+        match syscalls::listxattr(self, ino) {
+            Ok(list) => reply_xattr(reply, &list, size),
+            Err(errno) => reply.error(trace_err(errno)),
+        }
+    }
+}
+
+/// A node replied as an entry of a directory.
+struct Entry {
+    /// The duration the kernel caches the entry and attributes for
+    ttl: Duration,
+
+    /// The attributes of the node
+    attr: FileAttr,
+
+    /// The generation of the inode of the node
+    generation: u64,
+}
+
+/// Replies to an extended attribute request.
+/// A size of 0 requests the size of the data, not the data itself.
+fn reply_xattr(reply: ReplyXattr, data: &[u8], size: u32) {
+    let len = u32::try_from(data.len()).expect("xattr should fit u32");
+    if size == 0 {
+        reply.size(len);
+    } else if len <= size {
+        reply.data(data);
+    } else {
+        reply.error(trace_err(libc::ERANGE));
+    }
 }
 
 /// Logs an error and returns it
@@ -219,12 +453,254 @@ fn trace_err(err: c_int) -> c_int {
 
 /// Returns the name of a libc error code, if known
 fn err_name(err: c_int) -> Option<&'static str> {
-    use libc::{EINVAL, EIO, EISDIR, ENOENT};
+    use libc::{
+        EACCES, EAGAIN, EBADF, EFBIG, EINVAL, EIO, EISDIR, ENOENT, ENOTDIR, EPERM, ERANGE, EROFS,
+        ESTALE, ETIMEDOUT,
+    };
     Some(match err {
         ENOENT => "ENOENT",
         EIO => "EIO",
         EISDIR => "EISDIR",
         EINVAL => "EINVAL",
+        EACCES => "EACCES",
+        EAGAIN => "EAGAIN",
+        ETIMEDOUT => "ETIMEDOUT",
+        ERANGE => "ERANGE",
+        EBADF => "EBADF",
+        EFBIG => "EFBIG",
+        EROFS => "EROFS",
+        ESTALE => "ESTALE",
+        ENOTDIR => "ENOTDIR",
+        EPERM => "EPERM",
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, io,
+        path::Path,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        config::Item,
+        fs::harness::{database_item, list, private_vault, xattr, Harness, FIXTURES},
+        onepassword::Error,
+    };
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn browses_the_tree() {
+        let h = Harness::mount(FIXTURES);
+
+        assert_eq!(list(&h.path("")), [".ref", ".search", "personal"]);
+        assert_eq!(list(&h.path("personal")), ["private"]);
+        assert_eq!(
+            list(&h.path("personal/private")),
+            [
+                ".by-category",
+                ".by-tag",
+                ".category",
+                ".favorites",
+                "Database",
+                "dbitem"
+            ]
+        );
+        assert_eq!(
+            list(&h.path("personal/private/dbitem")),
+            [
+                "api",
+                "api_token",
+                "password",
+                "pwfield",
+                "tokfield",
+                "username"
+            ]
+        );
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(username).unwrap(), "admin");
+        assert_eq!(
+            fs::metadata(h.path("personal/private/dbitem/tokfield"))
+                .unwrap()
+                .len(),
+            6
+        );
+
+        let alias = h.path("personal/private/Database");
+        assert_eq!(fs::read_link(&alias).unwrap(), Path::new("dbitem"));
+        let token = h.path("personal/private/Database/api_token");
+        assert_eq!(fs::read_link(&token).unwrap(), Path::new("tokfield"));
+        assert_eq!(fs::read_to_string(token).unwrap(), "s3cr3t");
+
+        let missing = fs::metadata(h.path("personal/private/nope")).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn serves_last_known_data_on_failures() {
+        // The data expires right away, but is still cached
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_millis(1);
+        });
+
+        let item = h.path("personal/private/dbitem");
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+        assert_eq!(xattr(&item, "user.op.error"), None);
+
+        let limited = Error::RateLimited("slow down".to_string());
+        h.fixtures().fail_vault(&private_vault(), Some(limited));
+        assert!(list(&item).contains(&"username".to_string()));
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+        let error = xattr(&item, "user.op.error").expect("error should be reported");
+        assert!(error.contains("slow down"), "{error}");
+        assert_eq!(xattr(&item, "user.op.failures").as_deref(), Some("1"));
+
+        h.fixtures().fail_vault(&private_vault(), None);
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+        assert_eq!(xattr(&item, "user.op.error"), None);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn fails_never_cached_items_on_failures() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_millis(1);
+            let vault = config.accounts.get_mut("personal").unwrap();
+            let vault = vault.vaults.get_mut("private").unwrap();
+            let item = Item {
+                cache: Some(Duration::ZERO),
+            };
+            vault.items.insert("Database".to_string(), item);
+        });
+
+        let vault = h.path("personal/private");
+        let username = vault.join("dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // The listing is served as last known, but not the item
+        let limited = Error::RateLimited("slow down".to_string());
+        h.fixtures().fail_vault(&private_vault(), Some(limited));
+        assert_eq!(list(&vault)[4..], ["Database", "dbitem"]);
+        let err = fs::read_to_string(&username).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn backs_off_failed_refreshes() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.failures.serve_stale = false;
+            config.failures.min_backoff = Duration::from_secs(1);
+        });
+
+        let vault = h.path("personal/private");
+        assert_eq!(list(&vault)[4..], ["Database", "dbitem"]);
+
+        let limited = Error::RateLimited("slow down".to_string());
+        h.fixtures().fail_vault(&private_vault(), Some(limited));
+        let err = fs::read_dir(&vault).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+
+        // The last error is reported until the refresh is retried
+        h.fixtures().fail_vault(&private_vault(), None);
+        let err = fs::read_dir(&vault).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(list(&vault)[4..], ["Database", "dbitem"]);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn applies_cache_policies() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+            let account = config.accounts.get_mut("personal").unwrap();
+            account.cache.listing = Some(Duration::ZERO);
+            account.cache.fields = Some(Duration::ZERO);
+            let vault = account.vaults.get_mut("private").unwrap();
+            let item = Item {
+                cache: Some(Duration::from_secs(60)),
+            };
+            vault.items.insert("Database".to_string(), item);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // The fields of the item are cached, unlike the other items of the account
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // The vault listing is not cached
+        h.fixtures().remove_item(&private_vault(), "dbitem");
+        assert_eq!(
+            list(&h.path("personal/private")),
+            [".by-category", ".by-tag", ".category", ".favorites"]
+        );
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn never_caches_uncached_fields_in_kernel() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+            config.entry_ttl = Duration::from_secs(60);
+            config.attr_ttl = Duration::from_secs(60);
+            let account = config.accounts.get_mut("personal").unwrap();
+            account.cache.fields = Some(Duration::ZERO);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // The kernel would otherwise read the new value up to the cached size
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("administrator".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+        assert_eq!(fs::metadata(&username).unwrap().len(), 13);
+        assert_eq!(fs::read_to_string(&username).unwrap(), "administrator");
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn serves_stale_data_while_refreshing() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            // Data that is never cached is never served stale
+            config.cache_duration = Duration::from_millis(1);
+            config.refresh.max_stale = Duration::from_secs(60);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+        h.fixtures()
+            .delay_vault(&private_vault(), Some(Duration::from_secs(1)));
+
+        // The expired value is served while the refresh waits for the vault
+        let start = Instant::now();
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        h.fixtures().delay_vault(&private_vault(), None);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while fs::read_to_string(&username).unwrap() != "root" {
+            assert!(Instant::now() < deadline, "value should be refreshed");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{ffi::OsStr, sync::Arc, time::SystemTime};
+
+use libc::c_int;
+
+#[allow(clippy::wildcard_imports)]
+use fuser::*;
+
+use crate::util::Pool;
+
+use super::{refresher, FileHandle, Fs, Inode};
+
+/// Dispatches the requests of the kernel to a pool of worker threads.
+///
+/// Requests are served concurrently, so that reading an already cached field
+/// does not wait for another vault to be listed. Requests that do not need
+/// the filesystem are replied to immediately.
+pub struct Dispatcher {
+    fs: Arc<Fs>,
+    pool: Pool,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher serving the filesystem with the given number of
+    /// worker threads.
+    ///
+    /// The refresher thread of the filesystem is started along with them.
+    pub fn new(fs: Arc<Fs>, workers: usize) -> Dispatcher {
+        refresher::spawn(&fs);
+        Dispatcher {
+            fs,
+            pool: Pool::new("op-fuse", workers),
+        }
+    }
+
+    /// Returns the filesystem served by the dispatcher.
+    pub fn fs(&self) -> Arc<Fs> {
+        Arc::clone(&self.fs)
+    }
+
+    /// Queues a request to be served by a worker thread.
+    fn spawn<F>(&self, request: F)
+    where
+        F: FnOnce(&Fs) + Send + 'static,
+    {
+        let fs = Arc::clone(&self.fs);
+        self.pool.execute(move || request(&fs));
+    }
+}
+
+impl fuser::Filesystem for Dispatcher {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // Fields opened with `O_TRUNC` are truncated along with their write
+        // buffer, instead of by a separate `setattr` request
+        if let Err(unsupported) = config.add_capabilities(consts::FUSE_ATOMIC_O_TRUNC) {
+            warn!(unsupported, "Kernel does not support atomic O_TRUNC");
+        }
+        Ok(())
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: Inode, reply: ReplyAttr) {
+        self.spawn(move |fs| fs.getattr(ino, reply));
+    }
+
+    fn opendir(&mut self, _req: &Request, ino: Inode, _flags: i32, reply: ReplyOpen) {
+        self.spawn(move |fs| fs.opendir(ino, reply));
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        fh: FileHandle,
+        offset: i64,
+        reply: ReplyDirectory,
+    ) {
+        self.spawn(move |fs| fs.readdir(ino, fh, offset, reply));
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        fh: FileHandle,
+        _flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        self.spawn(move |fs| fs.releasedir(ino, fh, reply));
+    }
+
+    fn lookup(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEntry) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.lookup(parent, &name, reply));
+    }
+
+    fn forget(&mut self, _req: &Request, ino: Inode, nlookup: u64) {
+        // Forgetting a node only updates its lookup count, and does not reply
+        self.fs.forget(ino, nlookup);
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request,
+        parent: Inode,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.mkdir(parent, &name, reply));
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        parent: Inode,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.mknod(parent, &name, mode, reply));
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.unlink(parent, &name, reply));
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.rmdir(parent, &name, reply));
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: Inode,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.create(parent, &name, flags, reply));
+    }
+
+    fn open(&mut self, _req: &Request, ino: Inode, flags: i32, reply: ReplyOpen) {
+        self.spawn(move |fs| fs.open(ino, flags, reply));
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        fh: FileHandle,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        self.spawn(move |fs| fs.read(ino, fh, offset, size, reply));
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        fh: FileHandle,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let data = data.to_owned();
+        self.spawn(move |fs| fs.write(ino, fh, offset, &data, reply));
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<FileHandle>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        self.spawn(move |fs| fs.setattr(ino, fh, size, reply));
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        fh: FileHandle,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        self.spawn(move |fs| fs.flush(ino, fh, reply));
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        fh: FileHandle,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.spawn(move |fs| fs.release(ino, fh, reply));
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: Inode, reply: ReplyData) {
+        self.spawn(move |fs| fs.readlink(ino, reply));
+    }
+
+    fn getxattr(&mut self, _req: &Request, ino: Inode, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let name = name.to_owned();
+        self.spawn(move |fs| fs.getxattr(ino, &name, size, reply));
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: Inode, size: u32, reply: ReplyXattr) {
+        self.spawn(move |fs| fs.listxattr(ino, size, reply));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        fs::harness::{database_item, list, private_vault, Harness, FIXTURES},
+        onepassword::{id, types},
+    };
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn serves_requests_concurrently() {
+        let fixtures = FIXTURES.replacen(
+            r#""vaults": {"#,
+            r#""vaults": {
+                    "slow": {},"#,
+            1,
+        );
+        let h = Harness::mount_with(&fixtures, |config| {
+            config.cache_duration = Duration::from_secs(60);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // Listing the slow vault keeps a worker busy until the delay elapses
+        let slow = id::Vault::new(&id::Account::new("personal"), "slow");
+        h.fixtures()
+            .delay_vault(&slow, Some(Duration::from_secs(2)));
+        let listing = thread::spawn({
+            let path = h.path("personal/slow");
+            move || list(&path)
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        let start = Instant::now();
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let listing = listing.join().expect("listing should not panic");
+        assert_eq!(
+            listing,
+            [".by-category", ".by-tag", ".category", ".favorites"]
+        );
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn serves_lookups_while_fetching_attachments() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+        });
+        let mut item = database_item(&h);
+        item.files.push(types::SecretFile {
+            id: "certfile".to_string(),
+            name: "cert.pem".to_string(),
+            size: 4,
+        });
+        h.fixtures().put_item(&private_vault(), item);
+        h.fixtures().update(|set| {
+            let contents = &mut set.vault_mut(&private_vault()).contents;
+            contents.insert("certfile".to_string(), "CERT".to_string());
+        });
+
+        let cert = h.path("personal/private/dbitem/files/cert.pem");
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::metadata(&cert).unwrap().len(), 4);
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // Reading the attachment keeps its content fetching until the delay
+        // elapses, while it is looked up again
+        h.fixtures()
+            .delay_vault(&private_vault(), Some(Duration::from_secs(2)));
+        let reading = thread::spawn({
+            let cert = cert.clone();
+            move || fs::read_to_string(cert)
+        });
+        thread::sleep(Duration::from_millis(200));
+        let looking_up = thread::spawn({
+            let cert = cert.clone();
+            move || fs::metadata(cert).map(|meta| meta.len())
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        let start = Instant::now();
+        assert_eq!(fs::metadata(&username).unwrap().len(), 5);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(reading.join().unwrap().unwrap(), "CERT");
+        assert_eq!(looking_up.join().unwrap().unwrap(), 4);
+    }
+}
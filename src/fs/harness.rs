@@ -0,0 +1,271 @@
+//! An in-process harness mounting the filesystem on a temporary directory,
+//! backed by in-memory fixtures, along with the fixtures shared by the mount
+//! tests of each module.
+//!
+//! The mount tests need FUSE, so they are ignored by default and run with
+//! `cargo test -- --ignored`. They fail if the filesystem cannot be mounted.
+
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use fuser::MountOption;
+use tempfile::TempDir;
+
+use crate::{
+    agent::Agent,
+    config::{Account, Vault},
+    onepassword::{id, sshkey::TEST_ED25519_KEY, types, Fixtures},
+    Config,
+};
+
+use super::Fs;
+
+/// A filesystem mounted on a temporary directory.
+///
+/// The filesystem is unmounted when the harness is dropped.
+pub struct Harness {
+    /// The fixtures backing the filesystem
+    fixtures: Fixtures,
+
+    /// The temporary mountpoint
+    dir: TempDir,
+
+    /// The thread running the FUSE session
+    session: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl Harness {
+    /// Mounts a filesystem serving the given JSON fixtures.
+    ///
+    /// Every account and vault in the fixtures is added to the configuration.
+    /// Panics if FUSE is not available on this host.
+    pub fn mount(fixtures: &str) -> Harness {
+        Harness::mount_with(fixtures, |_| {})
+    }
+
+    /// Mounts a filesystem serving the given JSON fixtures, with a
+    /// configuration adjusted by the given closure.
+    pub fn mount_with<F>(fixtures: &str, configure: F) -> Harness
+    where
+        F: FnOnce(&mut Config),
+    {
+        let fixtures = Fixtures::from_json(fixtures).expect("fixtures should be valid");
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+
+        let mut config: Config = toml::from_str(&format!(
+            "mountpoint = {:?}\ncache_duration = \"0s\"",
+            dir.path()
+        ))
+        .expect("config should be valid");
+        config.failures.min_backoff = Duration::ZERO;
+        config.uid = unsafe { libc::getuid() };
+        config.gid = unsafe { libc::getgid() };
+
+        fixtures.update(|set| {
+            for (account_id, account) in &set.accounts {
+                let mut entry: Account =
+                    toml::from_str(&format!("id = {account_id:?}")).expect("account is valid");
+                for vault_id in account.vaults.keys() {
+                    let vault: Vault =
+                        toml::from_str(&format!("id = {vault_id:?}")).expect("vault is valid");
+                    entry.vaults.insert(vault_id.clone(), vault);
+                }
+                config.accounts.insert(account_id.clone(), entry);
+            }
+        });
+        configure(&mut config);
+
+        let fs = Arc::new(Fs::new(&config, Box::new(fixtures.clone())));
+        if let Some(agent) = &config.ssh_agent {
+            Agent::bind(&config, &agent.socket, Arc::clone(&fs))
+                .expect("agent socket should be created")
+                .spawn();
+        }
+
+        let session = thread::spawn({
+            let mountpoint = dir.path().to_path_buf();
+            move || super::mount(fs, &mountpoint, &[MountOption::FSName("op-fuse".into())])
+        });
+
+        let mut harness = Harness {
+            fixtures,
+            dir,
+            session: Some(session),
+        };
+
+        harness.wait_mounted();
+        harness
+    }
+
+    /// Waits for the mountpoint to be served by the filesystem.
+    /// Panics if the session failed to start.
+    fn wait_mounted(&mut self) {
+        let parent = fs::metadata(self.dir.path().join("..")).expect("parent should exist");
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while Instant::now() < deadline {
+            if self
+                .session
+                .as_ref()
+                .is_some_and(thread::JoinHandle::is_finished)
+            {
+                let result = self.session.take().map(thread::JoinHandle::join);
+                panic!("filesystem could not be mounted, is FUSE available? {result:?}");
+            }
+            if let Ok(meta) = fs::metadata(self.dir.path()) {
+                if meta.dev() != parent.dev() {
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("filesystem was not mounted in time");
+    }
+
+    /// Returns the path of the given entry in the mounted filesystem
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    /// Returns the fixtures backing the filesystem
+    pub fn fixtures(&self) -> &Fixtures {
+        &self.fixtures
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+
+        let path = CString::new(self.dir.path().as_os_str().as_bytes())
+            .expect("path should not contain nul bytes");
+        // SAFETY: `path` is a valid nul-terminated string.
+        if unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) } != 0 {
+            let _ = std::process::Command::new("fusermount")
+                .arg("-u")
+                .arg(self.dir.path())
+                .status();
+        }
+
+        let _ = session.join();
+    }
+}
+
+/// Returns the sorted names of the entries of a directory
+pub fn list(path: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(path)
+        .expect("directory should be readable")
+        .map(|entry| entry.expect("entry should be readable").file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+pub const FIXTURES: &str = r#"{
+    "accounts": {
+        "personal": {
+            "vaults": {
+                "private": {
+                    "items": [
+                        {
+                            "id": "dbitem",
+                            "title": "Database",
+                            "version": 1,
+                            "category": "LOGIN",
+                            "created_at": "2024-01-01T00:00:00Z",
+                            "updated_at": "2024-01-01T00:00:00Z",
+                            "fields": [
+                                {
+                                    "id": "username",
+                                    "type": "STRING",
+                                    "purpose": "USERNAME",
+                                    "label": "username",
+                                    "reference": "op://private/Database/username",
+                                    "value": "admin"
+                                },
+                                {
+                                    "id": "pwfield",
+                                    "type": "CONCEALED",
+                                    "purpose": "PASSWORD",
+                                    "label": "password",
+                                    "entropy": 42.5,
+                                    "reference": "op://private/Database/password",
+                                    "value": "hunter2"
+                                },
+                                {
+                                    "id": "tokfield",
+                                    "type": "CONCEALED",
+                                    "label": "token",
+                                    "section": { "id": "apisection" },
+                                    "reference": "op://private/Database/api/token",
+                                    "value": "s3cr3t"
+                                }
+                            ],
+                            "sections": [
+                                { "id": "apisection", "label": "api" }
+                            ]
+                        }
+                    ]
+                }
+            }
+        }
+    }
+}"#;
+
+/// Returns the vault ID of the test fixtures
+pub fn private_vault() -> id::Vault {
+    id::Vault::new(&id::Account::new("personal"), "private")
+}
+
+/// Returns the database item of the test fixtures
+pub fn database_item(harness: &Harness) -> types::Secret {
+    item(harness, "dbitem")
+}
+
+/// Returns an item of the vault of the test fixtures
+pub fn item(harness: &Harness, id: &str) -> types::Secret {
+    let secret = id::Secret::new(&private_vault(), id);
+    crate::onepassword::Backend::get_secret(harness.fixtures(), &secret).expect("item should exist")
+}
+
+/// Returns an SSH Key item holding an Ed25519 key in PKCS#8 format
+pub fn ssh_item() -> types::Secret {
+    serde_json::from_value(serde_json::json!({
+        "id": "sshitem",
+        "title": "Deploy key",
+        "version": 1,
+        "category": "SSH_KEY",
+        "created_at": "2024-01-01T00:00:00Z",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "fields": [{
+            "id": "private_key",
+            "type": "SSHKEY",
+            "label": "private key",
+            "reference": "op://private/Deploy key/private key",
+            "value": TEST_ED25519_KEY,
+        }],
+    }))
+    .unwrap()
+}
+
+/// Reads an extended attribute of a file
+pub fn xattr(path: &Path, name: &str) -> Option<String> {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let name = CString::new(name).unwrap();
+    let mut buf = vec![0u8; 256];
+    // SAFETY: the strings are nul-terminated and the buffer is large enough.
+    let len = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), 256) };
+    let len = usize::try_from(len).ok()?;
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
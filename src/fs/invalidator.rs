@@ -0,0 +1,109 @@
+use std::{
+    ffi::OsString,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use fuser::Notifier;
+
+use crate::util::Lock;
+
+use super::Inode;
+
+/// A cache entry of the kernel to invalidate.
+enum Invalidation {
+    /// The entry with the given name in the given directory.
+    Entry(Inode, OsString),
+
+    /// The attributes and content of the given node.
+    Inode(Inode),
+}
+
+/// Invalidates the kernel cache of the nodes that changed.
+///
+/// Notifications are sent from their own thread, as the kernel may wait for
+/// a pending request on the same directory to complete before processing
+/// them.
+#[derive(Default)]
+pub struct Invalidator {
+    /// The queue of the notification thread, once started.
+    sender: Lock<Option<Sender<Invalidation>>>,
+}
+
+impl Invalidator {
+    /// Starts sending notifications with the given notifier.
+    ///
+    /// The thread stops once the invalidator is dropped.
+    pub fn start(&self, notifier: Notifier) {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("op-fuse-notify".to_string())
+            .spawn(move || {
+                for invalidation in receiver {
+                    let result = match &invalidation {
+                        Invalidation::Entry(parent, name) => notifier.inval_entry(*parent, name),
+                        Invalidation::Inode(ino) => notifier.inval_inode(*ino, 0, 0),
+                    };
+                    // Entries that are not cached by the kernel are reported
+                    // as missing
+                    if let Err(err) = result {
+                        trace!(err = %err, "Failed to invalidate kernel cache");
+                    }
+                }
+            })
+            .expect("notification thread should be spawned");
+        *self.sender.lock() = Some(sender);
+    }
+
+    /// Invalidates an entry of a directory.
+    pub fn entry(&self, parent: Inode, name: &str) {
+        self.send(Invalidation::Entry(parent, name.into()));
+    }
+
+    /// Invalidates the attributes and content of a node.
+    pub fn inode(&self, ino: Inode) {
+        self.send(Invalidation::Inode(ino));
+    }
+
+    /// Queues a notification, if notifications were started.
+    fn send(&self, invalidation: Invalidation) {
+        if let Some(sender) = &*self.sender.lock() {
+            let _ = sender.send(invalidation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, thread, time::Duration};
+
+    use crate::fs::harness::{database_item, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn invalidates_kernel_cache_on_changes() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(1);
+            config.refresh.background = true;
+            config.entry_ttl = Duration::from_secs(60);
+            config.attr_ttl = Duration::from_secs(60);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+
+        // The kernel is notified once the secret is refreshed in the background
+        thread::sleep(Duration::from_millis(1500));
+        assert_eq!(fs::read_to_string(&username).unwrap(), "root");
+
+        h.fixtures().remove_item(&private_vault(), "dbitem");
+        thread::sleep(Duration::from_millis(1500));
+        let err = fs::metadata(h.path("personal/private/dbitem")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,10 +1,13 @@
-use std::{cell::RefCell, rc::Rc};
+use std::sync::Arc;
 
 use fuser::FileAttr;
 
 use crate::{
-    onepassword::{id, types::SecretMetadata},
-    util::SharedCell,
+    onepassword::{
+        id,
+        types::{SecretFile, SecretMetadata},
+    },
+    util::{Lock, SharedCell, Throttle},
 };
 
 use self::secret::FieldValue;
@@ -12,10 +15,18 @@ use self::secret::FieldValue;
 use super::{Fs, Inode};
 
 pub mod account;
+pub mod attachment;
+pub mod category;
+pub mod derived;
 pub mod field;
+pub mod index;
 pub mod link;
+pub mod otp;
+pub mod reference;
 pub mod root;
+pub mod search;
 pub mod secret;
+pub mod section;
 pub mod vault;
 
 /// A node in the filesystem tree.
@@ -39,8 +50,33 @@ pub enum Node {
     /// A secret-field node. This is the fourth level of the tree.
     Field(Box<field::Field>),
 
+    /// A one-time-password node. This is the current code of an OTP field.
+    Otp(Box<otp::Otp>),
+
+    /// A secret-section node. Groups the fields of a section by label, or the
+    /// files of a secret by name.
+    Section(Box<section::Section>),
+
+    /// An attachment node. This is a file attached to a secret.
+    Attachment(Box<attachment::Attachment>),
+
+    /// A derived node. This is a file computed from the fields of a secret.
+    Derived(Box<derived::Derived>),
+
+    /// An index node. Lists secrets by tag, by category or by favorite status.
+    Index(Box<index::Index>),
+
+    /// A search node. Looking up a name in it searches every vault.
+    Search(Box<search::Search>),
+
+    /// A reference node. Resolves `op://` references as paths.
+    Reference(Box<reference::Reference>),
+
     /// A link node. This is a symlink to another node.
     Link(Box<link::Link>),
+
+    /// A category node. Selects the category of the items created in a vault.
+    Category(Box<category::Category>),
 }
 
 impl Node {
@@ -72,11 +108,71 @@ impl Node {
     /// Creates a new field node.
     pub fn new_field(
         ino: Inode,
+        secret: Inode,
         metadata: SharedCell<SecretMetadata>,
         data: SharedCell<FieldValue>,
         trim: bool,
     ) -> Node {
-        Node::Field(Box::new(field::Field::new(ino, metadata, data, trim)))
+        Node::Field(Box::new(field::Field::new(
+            ino, secret, metadata, data, trim,
+        )))
+    }
+
+    /// Creates a new one-time-password node.
+    pub fn new_otp(
+        ino: Inode,
+        secret: Inode,
+        metadata: SharedCell<SecretMetadata>,
+        data: SharedCell<FieldValue>,
+    ) -> Node {
+        Node::Otp(Box::new(otp::Otp::new(ino, secret, metadata, data)))
+    }
+
+    /// Creates a new section node.
+    pub fn new_section(ino: Inode, secret: Inode, metadata: SharedCell<SecretMetadata>) -> Node {
+        Node::Section(Box::new(section::Section::new(ino, secret, metadata)))
+    }
+
+    /// Creates a new attachment node.
+    pub fn new_attachment(
+        ino: Inode,
+        secret: Inode,
+        id: id::Secret,
+        metadata: SharedCell<SecretMetadata>,
+        file: SecretFile,
+        document: bool,
+    ) -> Node {
+        Node::Attachment(Box::new(attachment::Attachment::new(
+            ino, secret, id, metadata, file, document,
+        )))
+    }
+
+    /// Creates a new derived node.
+    pub fn new_derived(
+        ino: Inode,
+        secret: Inode,
+        metadata: SharedCell<SecretMetadata>,
+        data: Vec<u8>,
+        private: bool,
+    ) -> Node {
+        Node::Derived(Box::new(derived::Derived::new(
+            ino, secret, metadata, data, private,
+        )))
+    }
+
+    /// Creates a new index node.
+    pub fn new_index(ino: Inode, attr: &FileAttr) -> Node {
+        Node::Index(Box::new(index::Index::new(ino, attr)))
+    }
+
+    /// Creates a new search node.
+    pub fn new_search(ino: Inode, attr: &FileAttr) -> Node {
+        Node::Search(Box::new(search::Search::new(ino, attr)))
+    }
+
+    /// Creates a new reference node.
+    pub fn new_reference(ino: Inode, attr: &FileAttr, scope: reference::Scope) -> Node {
+        Node::Reference(Box::new(reference::Reference::new(ino, attr, scope)))
     }
 
     /// Creates a new link node.
@@ -84,6 +180,15 @@ impl Node {
         Node::Link(Box::new(link::Link::new(ino, target, attr)))
     }
 
+    /// Creates a new category node.
+    pub fn new_category(
+        ino: Inode,
+        attr: &FileAttr,
+        selection: SharedCell<category::Selection>,
+    ) -> Node {
+        Node::Category(Box::new(category::Category::new(ino, attr, selection)))
+    }
+
     /// Returns the filesystem attributes of the node.
     /// Returns `None` if the node is a dummy node.
     pub fn attr(&self, fs: &Fs) -> Option<FileAttr> {
@@ -94,14 +199,95 @@ impl Node {
             Node::Vault(node) => node.attr(fs),
             Node::Secret(node) => node.attr(fs),
             Node::Field(node) => node.attr(fs),
+            Node::Otp(node) => node.attr(fs),
+            Node::Section(node) => node.attr(fs),
+            Node::Attachment(node) => node.attr(fs),
+            Node::Derived(node) => node.attr(fs),
+            Node::Index(node) => node.attr(),
+            Node::Search(node) => node.attr(),
+            Node::Reference(node) => node.attr(),
             Node::Link(node) => node.attr(),
+            Node::Category(node) => node.attr(fs),
         })
     }
+
+    /// Returns whether the node is built from 1Password data that is never
+    /// cached, so that the kernel must not cache its entry and attributes
+    /// either.
+    ///
+    /// The nodes of a secret are volatile along with the secret.
+    pub fn volatile(&self, fs: &Fs) -> bool {
+        let secret = match self {
+            Node::Vault(node) => return node.cache_duration(fs).is_zero(),
+            Node::Secret(node) => return node.volatile(fs),
+            Node::Field(node) => node.secret(),
+            Node::Otp(node) => node.secret(),
+            Node::Section(node) => node.secret(),
+            Node::Attachment(node) => node.secret(),
+            Node::Derived(node) => node.secret(),
+            _ => return false,
+        };
+        match fs.node_get(secret).as_ref() {
+            Node::Secret(node) => node.volatile(fs),
+            _ => false,
+        }
+    }
+}
+
+/// Creates a file name from a 1Password name.
+///
+/// Slashes are replaced by underscores, and empty names are rejected.
+fn make_name(name: &str) -> Option<String> {
+    Some(name.replace('/', "_")).filter(|name| !name.is_empty())
+}
+
+/// Returns the extended attributes describing the failed refreshes of a
+/// cache, if its last refresh failed.
+fn failure_xattrs<T>(cache: &Throttle<T>) -> Vec<(&'static str, String)> {
+    cache.failure().map_or_else(Vec::new, |failure| {
+        vec![
+            ("user.op.error", failure.error.to_string()),
+            ("user.op.failures", failure.attempts.to_string()),
+        ]
+    })
 }
 
 /// A slab of nodes.
+///
+/// A node is only freed once it is neither owned by a handler nor referenced
+/// by the kernel, so that its inode is not reused while the kernel may still
+/// use it.
 struct Slab {
-    inner: RefCell<::slab::Slab<Rc<Node>>>,
+    inner: Lock<Nodes>,
+}
+
+/// The nodes of a slab, and the generation of the next allocated node.
+struct Nodes {
+    entries: ::slab::Slab<Entry>,
+    generation: u64,
+}
+
+/// A node of a slab.
+struct Entry {
+    /// The node.
+    node: Arc<Node>,
+
+    /// The generation of the inode. It differs from the generations of the
+    /// previous nodes allocated with the same inode.
+    generation: u64,
+
+    /// Whether the node is owned by a handler.
+    owned: bool,
+
+    /// The number of lookups of the node not yet forgotten by the kernel.
+    lookups: u64,
+}
+
+impl Entry {
+    /// Returns whether the node can be freed.
+    fn unused(&self) -> bool {
+        !self.owned && self.lookups == 0
+    }
 }
 
 impl Slab {
@@ -110,47 +296,120 @@ impl Slab {
     where
         F: FnOnce(Inode) -> Node,
     {
-        let mut nodes = self.inner.borrow_mut();
-        let entry = nodes.vacant_entry();
+        // The node is created while the slab is locked, so it must not
+        // allocate other nodes
+        let mut nodes = self.inner.lock();
+        nodes.generation += 1;
+        let generation = nodes.generation;
+        let entry = nodes.entries.vacant_entry();
 
         let ino = entry.key() as Inode;
-        entry.insert(Rc::new(node(ino)));
+        entry.insert(Entry {
+            node: Arc::new(node(ino)),
+            generation,
+            owned: true,
+            lookups: 0,
+        });
         ino
     }
 
     /// Gets a node by its inode.
-    fn get(&self, ino: Inode) -> Rc<Node> {
-        // To prevent panics, this must not return the borrowed ref
+    fn get(&self, ino: Inode) -> Arc<Node> {
+        // The slab must not stay locked while the node is used
+        self.inner
+            .lock()
+            .entries
+            .get(super::u64_to_usize(ino))
+            .map_or_else(
+                || Arc::new(Node::new_dummy()),
+                |entry| Arc::clone(&entry.node),
+            )
+    }
+
+    /// Gets a node by its inode, along with its generation.
+    /// Returns `None` if the node was freed.
+    fn resolve(&self, ino: Inode) -> Option<(Arc<Node>, u64)> {
+        // The slab must not stay locked while the node is used
         self.inner
-            .borrow()
+            .lock()
+            .entries
             .get(super::u64_to_usize(ino))
-            .map_or_else(|| Rc::new(Node::new_dummy()), Rc::clone)
+            .map(|entry| (Arc::clone(&entry.node), entry.generation))
     }
 
-    /// Removes a node by its inode.
-    fn free(&self, ino: Inode) {
-        self.inner.borrow_mut().remove(super::u64_to_usize(ino));
+    /// Records a lookup of a node by the kernel, unless its inode was freed
+    /// or reused since the given generation was resolved.
+    /// Returns whether the lookup was recorded.
+    fn lookup(&self, ino: Inode, generation: u64) -> bool {
+        let mut nodes = self.inner.lock();
+        match nodes.entries.get_mut(super::u64_to_usize(ino)) {
+            Some(entry) if entry.generation == generation => {
+                entry.lookups += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets lookups of a node by the kernel, and frees it if it is no
+    /// longer used.
+    fn forget(&self, ino: Inode, lookups: u64) {
+        self.update(ino, |entry| {
+            entry.lookups = entry.lookups.saturating_sub(lookups);
+        });
+    }
+
+    /// Releases a node from its handler, and frees it if it is no longer used.
+    fn release(&self, ino: Inode) {
+        self.update(ino, |entry| entry.owned = false);
+    }
+
+    /// Updates the usage of a node, and frees it if it is no longer used.
+    fn update<F>(&self, ino: Inode, update: F)
+    where
+        F: FnOnce(&mut Entry),
+    {
+        let key = super::u64_to_usize(ino);
+        let mut nodes = self.inner.lock();
+        let Some(entry) = nodes.entries.get_mut(key) else {
+            return;
+        };
+        update(entry);
+        if !entry.unused() {
+            return;
+        }
+
+        // The node must be dropped after the slab is released, as it may free
+        // the nodes it owns
+        let entry = nodes.entries.remove(key);
+        drop(nodes);
+        debug!(ino, "freeing node");
+        drop(entry);
     }
 }
 
 /// A set of nodes.
 /// This is a wrapper around a slab of nodes and handles node lifetime.
 pub struct Set {
-    slab: Rc<Slab>,
+    slab: Arc<Slab>,
 }
 
 impl Set {
     /// Creates a new set of nodes.
     pub fn new() -> Set {
         Set {
-            slab: Rc::new(Slab {
-                inner: RefCell::new(::slab::Slab::new()),
+            slab: Arc::new(Slab {
+                inner: Lock::new(Nodes {
+                    entries: ::slab::Slab::new(),
+                    generation: 0,
+                }),
             }),
         }
     }
 
     /// Allocates a new node and returns a handler to it.
-    /// The handler will free the node when dropped, unless `persist` is called.
+    /// The handler will release the node when dropped, unless `persist` is
+    /// called.
     pub fn alloc<F>(&self, node: F) -> Handler
     where
         F: FnOnce(Inode) -> Node,
@@ -160,14 +419,34 @@ impl Set {
     }
 
     /// Gets a node by its inode.
-    pub fn get(&self, ino: Inode) -> Rc<Node> {
+    pub fn get(&self, ino: Inode) -> Arc<Node> {
         self.slab.get(ino)
     }
+
+    /// Gets a node by its inode, along with its generation.
+    /// Returns `None` if the node was freed.
+    pub fn resolve(&self, ino: Inode) -> Option<(Arc<Node>, u64)> {
+        self.slab.resolve(ino)
+    }
+
+    /// Records a lookup of a node by the kernel, unless its inode was freed
+    /// or reused since the given generation was resolved.
+    /// Returns whether the lookup was recorded.
+    pub fn lookup(&self, ino: Inode, generation: u64) -> bool {
+        self.slab.lookup(ino, generation)
+    }
+
+    /// Forgets lookups of a node by the kernel.
+    /// The node is freed once it is neither looked up nor owned by a handler.
+    pub fn forget(&self, ino: Inode, lookups: u64) {
+        self.slab.forget(ino, lookups);
+    }
 }
 
 /// A handler for a node.
-/// It frees the node when dropped, unless `persist` is called.
-pub struct Handler(Inode, Option<Rc<Slab>>);
+/// It releases the node when dropped, unless `persist` is called. The node is
+/// freed once the kernel forgets it too.
+pub struct Handler(Inode, Option<Arc<Slab>>);
 
 impl Handler {
     /// Returns the inode of the node.
@@ -176,7 +455,7 @@ impl Handler {
     }
 
     /// Returns a reference to the node.
-    pub fn node(&self) -> Rc<Node> {
+    pub fn node(&self) -> Arc<Node> {
         self.1
             .as_ref()
             .expect("the slab cannot be None")
@@ -195,8 +474,106 @@ impl Handler {
 impl Drop for Handler {
     fn drop(&mut self) {
         if let Some(slab) = self.1.take() {
-            debug!(ino = self.0, "freeing node");
-            slab.free(self.0);
+            slab.release(self.0);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read};
+
+    use super::*;
+    use crate::fs::harness::{database_item, private_vault, Harness, FIXTURES};
+
+    /// Records a lookup of a node, and returns its generation
+    fn lookup(set: &Set, ino: Inode) -> Option<u64> {
+        let (_, generation) = set.resolve(ino)?;
+        set.lookup(ino, generation).then_some(generation)
+    }
+
+    #[test]
+    fn keeps_nodes_until_released_and_forgotten() {
+        let set = Set::new();
+        let handler = set.alloc(|_| Node::new_dummy());
+        let ino = handler.ino();
+        let generation = lookup(&set, ino).expect("node should be allocated");
+
+        // The node is kept while the kernel still knows it
+        drop(handler);
+        assert_eq!(lookup(&set, ino), Some(generation));
+        set.forget(ino, 1);
+        assert_eq!(lookup(&set, ino), Some(generation));
+        set.forget(ino, 2);
+        assert_eq!(lookup(&set, ino), None);
+    }
+
+    #[test]
+    fn bumps_generations_of_reused_inodes() {
+        let set = Set::new();
+        let first = set.alloc(|_| Node::new_dummy());
+        let ino = first.ino();
+        let generation = lookup(&set, ino).unwrap();
+        set.forget(ino, 1);
+        drop(first);
+
+        let second = set.alloc(|_| Node::new_dummy());
+        assert_eq!(second.ino(), ino);
+        assert!(lookup(&set, ino).unwrap() > generation);
+    }
+
+    #[test]
+    fn records_lookups_of_the_resolved_node_only() {
+        let set = Set::new();
+        let first = set.alloc(|_| Node::new_dummy());
+        let ino = first.ino();
+        let (_, generation) = set.resolve(ino).unwrap();
+
+        // The inode was reused by another node since it was resolved
+        drop(first);
+        let second = set.alloc(|_| Node::new_dummy());
+        assert_eq!(second.ino(), ino);
+        assert!(!set.lookup(ino, generation));
+        drop(second);
+        assert!(set.resolve(ino).is_none());
+        assert!(!set.lookup(ino, generation));
+    }
+
+    #[test]
+    fn persists_nodes() {
+        let set = Set::new();
+        let ino = set.alloc(|_| Node::new_dummy()).persist();
+        assert!(lookup(&set, ino).is_some());
+        set.forget(ino, u64::MAX);
+        assert!(lookup(&set, ino).is_some());
+    }
+
+    #[test]
+    fn makes_file_names() {
+        assert_eq!(make_name("db/primary").as_deref(), Some("db_primary"));
+        assert_eq!(make_name("Database").as_deref(), Some("Database"));
+        assert_eq!(make_name(""), None);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn keeps_nodes_until_forgotten() {
+        let h = Harness::mount(FIXTURES);
+
+        let mut file = fs::File::open(h.path("personal/private/dbitem/username")).unwrap();
+
+        // The item is replaced by another one, whose nodes must not reuse the
+        // inodes still used by the kernel
+        let mut item = database_item(&h);
+        h.fixtures().remove_item(&private_vault(), "dbitem");
+        item.metadata.id = "otheritem".to_string();
+        item.fields[0].value = Some("intruder".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+        let other = h.path("personal/private/otheritem/username");
+        assert_eq!(fs::read_to_string(other).unwrap(), "intruder");
+
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "admin");
+    }
+}
@@ -1,13 +1,15 @@
-use std::{cell::OnceCell, time::SystemTime};
+use std::{collections::HashMap, sync::OnceLock, time::SystemTime};
 
+use anyhow::Result;
 use fuser::{FileAttr, FileType};
 
 use crate::{
     fs::{dentry::DirEntry, Fs, Inode},
     onepassword::id,
+    util::{diff, Lock, Throttle},
 };
 
-use super::Node;
+use super::{failure_xattrs, make_name, Handler, Node};
 
 /// An account node.
 pub struct Account {
@@ -18,10 +20,13 @@ pub struct Account {
     id: id::Account,
 
     /// The cached file attributes of the node.
-    attr: OnceCell<FileAttr>,
+    attr: OnceLock<FileAttr>,
 
-    /// The cached directory entries of the node.
-    entries: OnceCell<Vec<DirEntry>>,
+    /// The cached vault handlers of the node, by name.
+    entries: Lock<Throttle<HashMap<String, (id::Vault, Handler)>>>,
+
+    /// The cached names of the vaults in 1Password, by vault ID.
+    names: Lock<Throttle<HashMap<String, String>>>,
 }
 
 impl Account {
@@ -30,8 +35,9 @@ impl Account {
         Self {
             ino,
             id,
-            attr: OnceCell::new(),
-            entries: OnceCell::new(),
+            attr: OnceLock::new(),
+            entries: Lock::new(Throttle::default()),
+            names: Lock::new(Throttle::default()),
         }
     }
 
@@ -40,9 +46,75 @@ impl Account {
         *self.attr.get_or_init(|| make_attr(self, fs))
     }
 
+    /// Returns the extended attributes of the node, describing its last
+    /// failed refresh if any.
+    pub fn xattrs(&self) -> Vec<(&'static str, String)> {
+        failure_xattrs(&self.entries.lock())
+    }
+
     /// Returns the directory entries of the node.
-    pub fn entries(&self, fs: &Fs) -> impl Iterator<Item = &DirEntry> {
-        self.entries.get_or_init(|| make_entries(self, fs)).iter()
+    /// Each entry represents a vault in the account.
+    pub fn entries(&self, fs: &Fs) -> Result<impl Iterator<Item = DirEntry>> {
+        let mut entries = self.entries.lock();
+        entries.try_refresh(fs.config.cache_duration, &fs.config.failures, |entries| {
+            let mut vaults = list_vaults(self, fs)?;
+
+            let (delete, update, create) = diff(entries.keys(), vaults.keys());
+
+            for name in delete {
+                entries.remove(&name);
+            }
+
+            for name in update {
+                // The name now refers to another vault
+                let id = vaults.remove(&name).expect("vault should be in list");
+                if entries[&name].0 != id {
+                    let handler = fs.node_alloc(|ino| Node::new_vault(ino, id.clone()));
+                    entries.insert(name, (id, handler));
+                }
+            }
+
+            for name in create {
+                let id = vaults.remove(&name).expect("vault should be in list");
+                let handler = fs.node_alloc(|ino| Node::new_vault(ino, id.clone()));
+                entries.insert(name, (id, handler));
+            }
+
+            Ok(())
+        })?;
+
+        Ok(entries
+            .iter()
+            .map(|(name, (_, handler))| DirEntry {
+                inode: handler.ino(),
+                name: name.clone(),
+                file_type: FileType::Directory,
+            })
+            .collect::<Vec<DirEntry>>()
+            .into_iter())
+    }
+
+    /// Returns the names of the vaults of the account in 1Password.
+    ///
+    /// The vaults are listed apart from the entries, as configured vaults are
+    /// mounted without listing them. No names are returned if the vaults
+    /// cannot be listed.
+    pub fn vault_names(&self, fs: &Fs) -> Vec<(id::Vault, String)> {
+        let mut names = self.names.lock();
+        let refreshed = names.try_refresh(fs.config.cache_duration, &fs.config.failures, |names| {
+            let vaults = fs.op.list_vaults(&self.id)?;
+            *names = vaults.into_iter().map(|v| (v.id, v.name)).collect();
+            Ok(())
+        });
+        if let Err(err) = refreshed {
+            warn!(err = %err, account = self.id.account(), "Failed to list vault names");
+            return Vec::new();
+        }
+
+        names
+            .iter()
+            .map(|(id, name)| (id::Vault::new(&self.id, id), name.clone()))
+            .collect()
     }
 }
 
@@ -68,21 +140,96 @@ fn make_attr(node: &Account, fs: &Fs) -> FileAttr {
     }
 }
 
-/// Creates the directory entries of an account node.
-/// Each entry represents a vault in the account.
-fn make_entries(node: &Account, fs: &Fs) -> Vec<DirEntry> {
-    let mut entries = Vec::new();
-    for vault in fs.config.accounts[node.id.account()].vaults.keys() {
-        let entry = DirEntry {
-            inode: fs
-                .node_alloc(|ino| Node::new_vault(ino, id::Vault::new(&node.id, vault)))
-                .persist(),
-            name: vault.to_string(),
-            file_type: FileType::Directory,
-        };
+/// Lists the vaults to mount from an account, by name.
+///
+/// Configured vaults are listed first, then completed with the discovered
+/// vaults if enabled. The vaults of a discovered account are always
+/// discovered. If the vaults cannot be discovered, only the configured vaults
+/// are listed, unless there are none.
+fn list_vaults(node: &Account, fs: &Fs) -> Result<HashMap<String, id::Vault>> {
+    let config = fs.config.account(node.id.account());
+
+    let mut vaults = config
+        .map(|config| {
+            config
+                .vaults
+                .iter()
+                .map(|(name, vault)| (name.clone(), id::Vault::new(&node.id, &vault.id)))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
 
-        entries.push(entry);
+    if config.is_none() || fs.config.discovery.vaults {
+        let discovered = match fs.op.list_vaults(&node.id) {
+            Ok(discovered) => discovered,
+            Err(err) if !vaults.is_empty() => {
+                warn!(err = %err, account = node.id.account(), "Failed to discover vaults");
+                Vec::new()
+            }
+            Err(err) => return Err(err),
+        };
+        for vault in discovered {
+            if vaults.values().any(|id| id.vault() == vault.id) {
+                continue;
+            }
+            if let Some(name) = make_name(&vault.name) {
+                if fs.config.discovery.matches(&name) {
+                    let id = id::Vault::new(&node.id, &vault.id);
+                    vaults.entry(name).or_insert(id);
+                }
+            }
+        }
     }
 
-    entries
+    Ok(vaults)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use crate::{
+        fs::harness::{list, Harness, FIXTURES},
+        onepassword::Error,
+    };
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn discovers_vaults_with_account_settings() {
+        let fixtures = FIXTURES.replacen(
+            r#""accounts": {"#,
+            r#""accounts": {
+            "work": { "vaults": { "shared": {} } },"#,
+            1,
+        );
+        let h = Harness::mount_with(&fixtures, |config| {
+            config.accounts.retain(|name, _| name == "personal");
+            config.discovery.accounts = true;
+            config.discovery.vaults = true;
+            let account = config.accounts.get_mut("personal").unwrap();
+            account.prefetch = Some(true);
+            account.cache.fields = Some(Duration::from_secs(60));
+        });
+
+        // The configured entries are still listed when discovery fails
+        h.fixtures()
+            .fail_listings(Some(Error::Timeout(Duration::ZERO)));
+        assert_eq!(list(&h.path("")), [".ref", ".search", "personal"]);
+        assert_eq!(list(&h.path("personal")), ["private"]);
+        h.fixtures().fail_listings(None);
+        assert_eq!(list(&h.path("")), [".ref", ".search", "personal", "work"]);
+
+        // Discovered vaults are prefetched and cached as the other vaults of
+        // their account
+        h.fixtures().update(|set| {
+            let account = set.accounts.get_mut("personal").unwrap();
+            let vault = account.vaults.remove("private").unwrap();
+            account.vaults.insert("discovered".to_string(), vault);
+        });
+        assert_eq!(list(&h.path("personal")), ["discovered", "private"]);
+        let password = h.path("personal/discovered/dbitem/password");
+        assert_eq!(fs::read_to_string(password).unwrap(), "hunter2");
+        assert!(h.fixtures().calls("get_secrets") > 0);
+        assert_eq!(h.fixtures().calls("get_secret"), 0);
+    }
 }
@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use fuser::FileAttr;
+
+use crate::{
+    fs::{Fs, Inode},
+    onepassword::{
+        id,
+        types::{SecretFile, SecretMetadata, SecretVersion},
+    },
+    util::{Lock, SharedCell},
+};
+
+/// A node representing a file attached to a secret, or the file of a
+/// Document item.
+///
+/// The content of the file is fetched on first access and kept until the
+/// secret is updated.
+pub struct Attachment {
+    /// The inode number of the node.
+    ino: Inode,
+
+    /// The inode number of the secret node the file belongs to.
+    secret: Inode,
+
+    /// The ID of the secret.
+    id: id::Secret,
+
+    /// The metadata of the secret.
+    metadata: SharedCell<SecretMetadata>,
+
+    /// The file as described by the secret.
+    file: Lock<SecretFile>,
+
+    /// Whether the file is the content of a Document item.
+    document: bool,
+
+    /// The content of the file, and the version of the secret it was fetched
+    /// from. It is only locked to be read or replaced, never while fetching.
+    content: Lock<Option<(SecretVersion, Arc<Vec<u8>>)>>,
+}
+
+impl Attachment {
+    /// Creates a new attachment node.
+    pub fn new(
+        ino: Inode,
+        secret: Inode,
+        id: id::Secret,
+        metadata: SharedCell<SecretMetadata>,
+        file: SecretFile,
+        document: bool,
+    ) -> Attachment {
+        Self {
+            ino,
+            secret,
+            id,
+            metadata,
+            file: Lock::new(file),
+            document,
+            content: Lock::new(None),
+        }
+    }
+
+    /// Returns the inode number of the secret node the file belongs to.
+    pub fn secret(&self) -> Inode {
+        self.secret
+    }
+
+    /// Returns the file attributes of the node.
+    ///
+    /// The size is the one reported by 1Password until the content is
+    /// fetched, and the actual size afterwards.
+    pub fn attr(&self, fs: &Fs) -> FileAttr {
+        let metadata = self.metadata.read();
+
+        let updated_at = metadata.updated_at.into();
+        let created_at = metadata.created_at.into();
+
+        FileAttr {
+            ino: self.ino,
+            size: self.size(),
+            blocks: 0,
+            atime: updated_at,
+            mtime: updated_at,
+            ctime: updated_at,
+            crtime: created_at,
+            kind: fuser::FileType::RegularFile,
+            perm: fs.config.file_mode,
+            nlink: 1,
+            uid: fs.config.uid,
+            gid: fs.config.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// Returns the size of the file.
+    ///
+    /// This is the size of the fetched content if it is current, or the size
+    /// reported by 1Password otherwise. It never waits for a fetch.
+    fn size(&self) -> u64 {
+        let version = self.metadata.read().version;
+        match &*self.content.lock() {
+            Some((fetched, content)) if *fetched == version => content.len() as u64,
+            _ => self.file.lock().size,
+        }
+    }
+
+    /// Updates the file from a refreshed secret.
+    /// The content is fetched again if the file has changed.
+    pub fn update(&self, file: SecretFile) {
+        // The content is locked before the file elsewhere, so the file must
+        // be unlocked first
+        let changed = {
+            let mut current = self.file.lock();
+            let changed = *current != file;
+            *current = file;
+            changed
+        };
+        if changed {
+            *self.content.lock() = None;
+        }
+    }
+
+    /// Fetches the content of the file if it is not already known.
+    ///
+    /// Returns whether the size reported by 1Password was accurate, i.e.
+    /// whether the kernel can rely on the attributes to read the file.
+    pub fn load(&self, fs: &Fs) -> Result<bool> {
+        let size = self.content(fs)?.len() as u64;
+        Ok(size == self.file.lock().size)
+    }
+
+    /// Reads the content of the file, fetching it if needed.
+    pub fn read(&self, fs: &Fs, offset: usize, size: usize) -> Result<Vec<u8>> {
+        use std::cmp::min;
+
+        let bytes = self.content(fs)?;
+        let start = min(bytes.len(), offset);
+        let end = min(bytes.len(), offset.saturating_add(size));
+
+        Ok(Vec::from(&bytes[start..end]))
+    }
+
+    /// Returns the content of the file, fetching it if it is not known for
+    /// the current version of the secret.
+    ///
+    /// The content is fetched without holding it, so that the attributes and
+    /// the other readers of the file are not blocked by the download. It is
+    /// only kept if the file was not updated meanwhile.
+    fn content(&self, fs: &Fs) -> Result<Arc<Vec<u8>>> {
+        let version = self.metadata.read().version;
+        if let Some((fetched, content)) = &*self.content.lock() {
+            if *fetched == version {
+                return Ok(Arc::clone(content));
+            }
+        }
+
+        let file = self.file.lock().clone();
+        let data = Arc::new(if self.document {
+            fs.op.get_document(&self.id)?
+        } else {
+            fs.op.get_file(&self.id, &file.id)?
+        });
+
+        // The content is locked before the file, as in `update`, which clears
+        // the content after replacing the file
+        let mut content = self.content.lock();
+        if *self.file.lock() == file {
+            *content = Some((version, Arc::clone(&data)));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        fs::harness::{database_item, list, private_vault, Harness, FIXTURES},
+        onepassword::types,
+    };
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn exposes_files() {
+        let h = Harness::mount(FIXTURES);
+
+        let mut item = database_item(&h);
+        item.files.push(types::SecretFile {
+            id: "certfile".to_string(),
+            name: "cert.pem".to_string(),
+            size: 4,
+        });
+        h.fixtures().put_item(&private_vault(), item);
+
+        // The reported size of the document is wrong on purpose
+        let document = serde_json::from_str(
+            r#"{
+                "id": "docitem",
+                "title": "Report",
+                "version": 1,
+                "category": "DOCUMENT",
+                "created_at": "2024-01-01T00:00:00Z",
+                "updated_at": "2024-01-01T00:00:00Z",
+                "fields": [],
+                "files": [{ "id": "docfile", "name": "report.txt", "size": 3 }]
+            }"#,
+        )
+        .unwrap();
+        h.fixtures().put_item(&private_vault(), document);
+        h.fixtures().update(|set| {
+            let contents = &mut set.vault_mut(&private_vault()).contents;
+            contents.insert("certfile".to_string(), "CERT".to_string());
+            contents.insert("docitem".to_string(), "quarterly figures".to_string());
+        });
+
+        let files = h.path("personal/private/dbitem/files");
+        assert_eq!(list(&files), ["cert.pem"]);
+        assert_eq!(fs::metadata(files.join("cert.pem")).unwrap().len(), 4);
+        assert_eq!(fs::read_to_string(files.join("cert.pem")).unwrap(), "CERT");
+
+        let report = h.path("personal/private/docitem/files/report.txt");
+        assert_eq!(fs::read_to_string(&report).unwrap(), "quarterly figures");
+        assert_eq!(fs::metadata(&report).unwrap().len(), 17);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn stats_attachments_while_fetching_them() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+        });
+        let mut item = database_item(&h);
+        item.files.push(types::SecretFile {
+            id: "certfile".to_string(),
+            name: "cert.pem".to_string(),
+            size: 4,
+        });
+        h.fixtures().put_item(&private_vault(), item);
+        h.fixtures().update(|set| {
+            let contents = &mut set.vault_mut(&private_vault()).contents;
+            contents.insert("certfile".to_string(), "CERT".to_string());
+        });
+
+        let cert = h.path("personal/private/dbitem/files/cert.pem");
+        assert_eq!(fs::metadata(&cert).unwrap().len(), 4);
+
+        h.fixtures()
+            .delay_vault(&private_vault(), Some(Duration::from_secs(2)));
+        let reading = thread::spawn({
+            let cert = cert.clone();
+            move || fs::read_to_string(cert)
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        // The declared size is reported without waiting for the content
+        let start = Instant::now();
+        assert_eq!(fs::metadata(&cert).unwrap().len(), 4);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(reading.join().unwrap().unwrap(), "CERT");
+    }
+}
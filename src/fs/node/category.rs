@@ -0,0 +1,153 @@
+use fuser::{FileAttr, FileType};
+
+use crate::{
+    fs::{Fs, Inode},
+    util::SharedCell,
+};
+
+/// The name of the file selecting the category of the items created in a
+/// vault.
+pub const CATEGORY_FILE: &str = ".category";
+
+/// The categories of the items that can be created, by their names in the
+/// filesystem, along with their 1Password names.
+const CATEGORIES: &[(&str, &str)] = &[
+    ("login", "LOGIN"),
+    ("password", "PASSWORD"),
+    ("note", "SECURE_NOTE"),
+    ("api-credential", "API_CREDENTIAL"),
+    ("database", "DATABASE"),
+    ("server", "SERVER"),
+    ("email", "EMAIL_ACCOUNT"),
+    ("software-license", "SOFTWARE_LICENSE"),
+];
+
+/// The category of the items created in a vault, unless selected otherwise.
+pub const DEFAULT_CATEGORY: &str = "login";
+
+/// What the items created in a vault are based on.
+pub enum Selection {
+    /// An empty item of the category with the given name, e.g. `login`.
+    Category(&'static str),
+
+    /// An item template, as output by `op item template get`, which carries
+    /// the 1Password name of its category along with its fields.
+    Template(serde_json::Value),
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Selection::Category(DEFAULT_CATEGORY)
+    }
+}
+
+/// A file selecting the category of the items created in its vault.
+///
+/// It contains the name of the category, e.g. `login`. Writing the name of
+/// another category to it selects that category for the items created
+/// afterwards, unless their name selects one. Writing an item template
+/// instead creates the items from that template.
+pub struct Category {
+    /// The attributes of the node.
+    attr: FileAttr,
+
+    /// The selected category or template, shared with the vault.
+    selection: SharedCell<Selection>,
+}
+
+impl Category {
+    /// Creates a new category node, with the attributes of its vault.
+    pub fn new(ino: Inode, attr: &FileAttr, selection: SharedCell<Selection>) -> Category {
+        Self {
+            attr: FileAttr {
+                ino,
+                kind: FileType::RegularFile,
+                ..*attr
+            },
+            selection,
+        }
+    }
+
+    /// Returns the file attributes of the node.
+    pub fn attr(&self, fs: &Fs) -> FileAttr {
+        FileAttr {
+            size: self.content().len() as u64,
+            perm: fs.config.file_mode,
+            ..self.attr
+        }
+    }
+
+    /// Returns the content of the file, i.e. the name of the category or the
+    /// template.
+    pub fn content(&self) -> Vec<u8> {
+        match &*self.selection.read() {
+            Selection::Category(name) => format!("{name}\n").into_bytes(),
+            Selection::Template(template) => {
+                let mut content = serde_json::to_vec_pretty(template).unwrap_or_default();
+                content.push(b'\n');
+                content
+            }
+        }
+    }
+
+    /// Reads the name of the category.
+    pub fn read(&self, offset: usize, size: usize) -> Vec<u8> {
+        use std::cmp::min;
+
+        let content = self.content();
+        let start = min(content.len(), offset);
+        let end = min(content.len(), offset.saturating_add(size));
+
+        Vec::from(&content[start..end])
+    }
+
+    /// Selects the category named by the content written to the file, or the
+    /// template it contains. Returns `false` if it is neither the name of a
+    /// category nor a template of a known category.
+    pub fn select(&self, content: &[u8]) -> bool {
+        let content = std::str::from_utf8(content).unwrap_or_default().trim();
+        let selection = if content.starts_with('{') {
+            serde_json::from_str::<serde_json::Value>(content)
+                .ok()
+                .filter(|template| {
+                    let category = template["category"].as_str().unwrap_or_default();
+                    CATEGORIES.iter().any(|(_, id)| *id == category)
+                })
+                .map(Selection::Template)
+        } else {
+            CATEGORIES
+                .iter()
+                .find(|(known, _)| *known == content)
+                .map(|(name, _)| Selection::Category(name))
+        };
+
+        match selection {
+            Some(selection) => {
+                *self.selection.write() = selection;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns the 1Password name of the category with the given name.
+pub fn category_id(name: &str) -> Option<&'static str> {
+    CATEGORIES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, id)| *id)
+}
+
+/// Splits the name of a new item directory into the title of the item and
+/// the 1Password name of its category.
+///
+/// Directories named `<title>.<category>`, e.g. `Staging.database`, create an
+/// item of the given category. Other names are only a title, e.g.
+/// `example.com`.
+pub fn split_name(name: &str) -> (&str, Option<&'static str>) {
+    name.rsplit_once('.')
+        .filter(|(title, _)| !title.is_empty())
+        .and_then(|(title, category)| Some((title, Some(category_id(category)?))))
+        .unwrap_or((name, None))
+}
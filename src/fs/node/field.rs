@@ -2,17 +2,23 @@ use fuser::FileAttr;
 
 use crate::{
     fs::{Fs, Inode},
-    onepassword::types::SecretMetadata,
+    onepassword::types::{SecretMetadata, SecretVersion},
     util::SharedCell,
 };
 
 use super::secret::FieldValue;
 
+/// The prefix trimmed from the notes.
+const TRIMMED_PREFIX: &str = "```";
+
 /// A node representing a secret field.
 pub struct Field {
     /// The inode number of the node.
     ino: Inode,
 
+    /// The inode number of the secret node the field belongs to.
+    secret: Inode,
+
     /// The metadata of the secret.
     metadata: SharedCell<SecretMetadata>,
 
@@ -31,12 +37,14 @@ impl Field {
     /// Creates a new field node.
     pub fn new(
         ino: Inode,
+        secret: Inode,
         metadata: SharedCell<SecretMetadata>,
         data: SharedCell<FieldValue>,
         trim: bool,
     ) -> Field {
         Self {
             ino,
+            secret,
             metadata,
             data,
             trim,
@@ -45,15 +53,14 @@ impl Field {
 
     /// Returns the file attributes of the node.
     pub fn attr(&self, fs: &Fs) -> FileAttr {
-        let metadata = self.metadata.borrow();
-        let data = self.data.borrow();
+        let metadata = self.metadata.read();
 
         let updated_at = metadata.updated_at.into();
         let created_at = metadata.created_at.into();
 
         FileAttr {
             ino: self.ino,
-            size: data.value().len() as u64,
+            size: self.content().len() as u64,
             blocks: 0,
             atime: updated_at,
             mtime: updated_at,
@@ -70,20 +77,129 @@ impl Field {
         }
     }
 
+    /// Returns the extended attributes of the node.
+    /// They describe the field as returned by 1Password.
+    pub fn xattrs(&self) -> Vec<(&'static str, String)> {
+        let data = self.data.read();
+        let field = data.field();
+
+        let mut attrs = vec![
+            ("user.op.id", field.id.clone()),
+            ("user.op.type", field.kind.name().to_string()),
+            ("user.op.label", field.label.clone()),
+        ];
+        if let Some(purpose) = field.purpose {
+            attrs.push(("user.op.purpose", purpose.name().to_string()));
+        }
+        if let Some(section) = field.section.as_ref().and_then(|s| s.label.clone()) {
+            attrs.push(("user.op.section", section));
+        }
+        if let Some(entropy) = field.entropy {
+            attrs.push(("user.op.entropy", entropy.to_string()));
+        }
+        if !field.reference.is_empty() {
+            attrs.push(("user.op.reference", field.reference.clone()));
+        }
+        attrs
+    }
+
+    /// Returns the inode number of the secret node the field belongs to.
+    pub fn secret(&self) -> Inode {
+        self.secret
+    }
+
+    /// Returns the ID of the field.
+    pub fn id(&self) -> String {
+        self.data.read().field().id.clone()
+    }
+
+    /// Returns the content of the file along with the version of the secret
+    /// it was loaded from, to edit the field from that content.
+    pub fn snapshot(&self) -> (Vec<u8>, SecretVersion) {
+        let data = self.data.read();
+        (
+            self.trimmed(data.value()).as_bytes().to_vec(),
+            data.version(),
+        )
+    }
+
     /// Reads the field value.
     pub fn read(&self, offset: usize, size: usize) -> Vec<u8> {
         use std::cmp::min;
 
-        let data = self.data.borrow();
-        let mut value = data.value();
-        if self.trim && value.starts_with("```") {
-            value = &value[3..];
+        let content = self.content();
+        let start = min(content.len(), offset);
+        let end = min(content.len(), offset.saturating_add(size));
+
+        Vec::from(&content[start..end])
+    }
+
+    /// Returns the content of the file, i.e. the possibly trimmed value.
+    pub fn content(&self) -> Vec<u8> {
+        self.trimmed(self.data.read().value()).as_bytes().to_vec()
+    }
+
+    /// Removes the leading backticks from a value, if the field is trimmed.
+    fn trimmed<'a>(&self, value: &'a str) -> &'a str {
+        match value.strip_prefix(TRIMMED_PREFIX) {
+            Some(trimmed) if self.trim => trimmed,
+            _ => value,
         }
-        let bytes = value.as_bytes();
+    }
+
+    /// Converts the content written to the file to a field value.
+    ///
+    /// The trailing newline added by most tools, e.g. `echo`, is removed,
+    /// except from the notes. The backticks trimmed from the notes are put
+    /// back, so that the value is left as is if the content is. Returns
+    /// `None` if the content is not UTF-8.
+    pub fn parse(&self, content: &[u8]) -> Option<String> {
+        let value = std::str::from_utf8(content).ok()?;
+        if self.trim {
+            let prefix = if self.data.read().value().starts_with(TRIMMED_PREFIX) {
+                TRIMMED_PREFIX
+            } else {
+                ""
+            };
+            return Some(format!("{prefix}{value}"));
+        }
+        let value = value.strip_suffix('\n').unwrap_or(value);
+        Some(value.strip_suffix('\r').unwrap_or(value).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::fs::harness::{list, xattr, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn exposes_field_model() {
+        let h = Harness::mount(FIXTURES);
+
+        let password = h.path("personal/private/dbitem/password");
+        assert_eq!(fs::read_link(&password).unwrap(), Path::new("pwfield"));
+        assert_eq!(fs::read_to_string(&password).unwrap(), "hunter2");
 
-        let start = min(bytes.len(), offset);
-        let end = min(bytes.len(), offset + size);
+        let section = h.path("personal/private/dbitem/api");
+        assert_eq!(list(&section), ["token"]);
+        assert_eq!(fs::read_to_string(section.join("token")).unwrap(), "s3cr3t");
 
-        Vec::from(&bytes[start..end])
+        assert_eq!(
+            xattr(&password, "user.op.type").as_deref(),
+            Some("CONCEALED")
+        );
+        assert_eq!(
+            xattr(&password, "user.op.purpose").as_deref(),
+            Some("PASSWORD")
+        );
+        assert_eq!(xattr(&password, "user.op.entropy").as_deref(), Some("42.5"));
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(xattr(&username, "user.op.type").as_deref(), Some("STRING"));
+        assert_eq!(xattr(&username, "user.op.section"), None);
+        let token = section.join("token");
+        assert_eq!(xattr(&token, "user.op.section").as_deref(), Some("api"));
     }
 }
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use fuser::{FileAttr, FileType};
+
+use crate::{
+    fs::{dentry::DirEntry, Fs, Inode},
+    onepassword::types::SecretMetadata,
+    util::{diff, Lock},
+};
+
+use super::{make_name, Handler, Node};
+
+/// The name of the directory listing secrets by tag.
+pub const BY_TAG_DIR: &str = ".by-tag";
+
+/// The name of the directory listing secrets by category.
+pub const BY_CATEGORY_DIR: &str = ".by-category";
+
+/// The name of the directory listing favorite secrets.
+pub const FAVORITES_DIR: &str = ".favorites";
+
+/// A node listing secrets by tag, by category or by favorite status.
+///
+/// It contains symlinks to the secret nodes, or directories of such symlinks.
+/// The entries are maintained by the parent vault, root or search node.
+pub struct Index {
+    /// The attributes of the node.
+    attr: FileAttr,
+
+    /// The directory entries of the node.
+    entries: Lock<Vec<DirEntry>>,
+}
+
+impl Index {
+    /// Creates a new index node, with the attributes of its parent.
+    pub fn new(ino: Inode, attr: &FileAttr) -> Index {
+        Self {
+            attr: FileAttr { ino, ..*attr },
+            entries: Lock::new(Vec::new()),
+        }
+    }
+
+    /// Returns the file attributes of the node.
+    pub fn attr(&self) -> FileAttr {
+        self.attr
+    }
+
+    /// Returns the directory entries of the node.
+    pub fn entries(&self) -> impl Iterator<Item = DirEntry> {
+        self.entries.lock().clone().into_iter()
+    }
+
+    /// Replaces the directory entries of the node, invalidating the ones
+    /// cached by the kernel that changed.
+    fn set_entries(&self, fs: &Fs, entries: Vec<DirEntry>) {
+        let before = std::mem::replace(&mut *self.entries.lock(), entries.clone());
+        fs.invalidate_entries(self.attr.ino, &before, &entries);
+    }
+}
+
+/// The index directories of a vault, or of the whole filesystem.
+pub struct Indexes {
+    /// The attributes of the directory containing the indexes.
+    attr: FileAttr,
+
+    /// The secrets by tag.
+    by_tag: Groups,
+
+    /// The secrets by category.
+    by_category: Groups,
+
+    /// The favorite secrets.
+    favorites: Links,
+}
+
+impl Indexes {
+    /// Creates empty index directories, with the attributes of their parent.
+    pub fn new(fs: &Fs, attr: &FileAttr) -> Indexes {
+        Self {
+            attr: *attr,
+            by_tag: Groups::new(fs, attr),
+            by_category: Groups::new(fs, attr),
+            favorites: Links::new(fs, attr),
+        }
+    }
+
+    /// Updates the index directories from the given secrets, by path relative
+    /// to the directory containing the indexes.
+    pub fn update(&mut self, fs: &Fs, secrets: &[(String, SecretMetadata)]) {
+        let mut by_tag: HashMap<_, Vec<_>> = HashMap::new();
+        let mut by_category: HashMap<_, Vec<_>> = HashMap::new();
+        for secret in secrets {
+            for tag in secret.1.tags.iter().filter_map(|tag| make_name(tag)) {
+                by_tag.entry(tag).or_default().push(secret);
+            }
+            by_category
+                .entry(category_name(&secret.1.category))
+                .or_default()
+                .push(secret);
+        }
+
+        let groups = |groups: HashMap<String, Vec<_>>| {
+            groups
+                .into_iter()
+                .map(|(name, secrets)| (name, link_targets(&secrets, "../../")))
+                .collect()
+        };
+        self.by_tag.update(fs, &self.attr, groups(by_tag));
+        self.by_category.update(fs, &self.attr, groups(by_category));
+
+        let favorites = secrets.iter().filter(|secret| secret.1.favorite);
+        let favorites = link_targets(&favorites.collect::<Vec<_>>(), "../");
+        self.favorites.update(fs, &self.attr, favorites);
+    }
+
+    /// Returns the directory entries of the index directories.
+    pub fn entries(&self) -> impl Iterator<Item = DirEntry> {
+        [
+            (BY_TAG_DIR, &self.by_tag.dir),
+            (BY_CATEGORY_DIR, &self.by_category.dir),
+            (FAVORITES_DIR, &self.favorites.dir),
+        ]
+        .map(|(name, handler)| DirEntry {
+            inode: handler.ino(),
+            name: name.to_string(),
+            file_type: FileType::Directory,
+        })
+        .into_iter()
+    }
+}
+
+/// A directory of symlinks to secrets.
+pub struct Links {
+    /// The handler of the directory.
+    dir: Handler,
+
+    /// The target and handler of each symlink, by name.
+    links: HashMap<String, (String, Handler)>,
+}
+
+impl Links {
+    /// Creates an empty directory of symlinks.
+    pub fn new(fs: &Fs, attr: &FileAttr) -> Links {
+        Self {
+            dir: fs.node_alloc(|ino| Node::new_index(ino, attr)),
+            links: HashMap::new(),
+        }
+    }
+
+    /// Returns the inode of the directory.
+    pub fn ino(&self) -> Inode {
+        self.dir.ino()
+    }
+
+    /// Updates the symlinks of the directory from their targets, by name.
+    pub fn update(&mut self, fs: &Fs, attr: &FileAttr, mut targets: HashMap<String, String>) {
+        let (delete, update, create) = diff(self.links.keys(), targets.keys());
+
+        for name in delete {
+            self.links.remove(&name);
+        }
+
+        for name in update {
+            let target = targets.remove(&name).expect("target should be in list");
+            if self.links[&name].0 != target {
+                let handler = fs.node_alloc(|ino| Node::new_link(ino, &target, attr));
+                self.links.insert(name, (target, handler));
+            }
+        }
+
+        for name in create {
+            let target = targets.remove(&name).expect("target should be in list");
+            let handler = fs.node_alloc(|ino| Node::new_link(ino, &target, attr));
+            self.links.insert(name, (target, handler));
+        }
+
+        let entries = self
+            .links
+            .iter()
+            .map(|(name, (_, handler))| DirEntry {
+                inode: handler.ino(),
+                name: name.clone(),
+                file_type: FileType::Symlink,
+            })
+            .collect();
+        match self.dir.node().as_ref() {
+            Node::Index(index) => index.set_entries(fs, entries),
+            _ => unreachable!("node should be an index"),
+        }
+    }
+}
+
+/// A directory of symlink directories, i.e. the tags or the categories.
+struct Groups {
+    /// The handler of the directory.
+    dir: Handler,
+
+    /// The symlink directories, by name.
+    groups: HashMap<String, Links>,
+}
+
+impl Groups {
+    /// Creates an empty directory of groups.
+    fn new(fs: &Fs, attr: &FileAttr) -> Groups {
+        Self {
+            dir: fs.node_alloc(|ino| Node::new_index(ino, attr)),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Updates the groups from the targets of their symlinks, by group name.
+    fn update(
+        &mut self,
+        fs: &Fs,
+        attr: &FileAttr,
+        mut groups: HashMap<String, HashMap<String, String>>,
+    ) {
+        let (delete, update, create) = diff(self.groups.keys(), groups.keys());
+
+        for name in delete {
+            self.groups.remove(&name);
+        }
+
+        for name in update {
+            let targets = groups.remove(&name).expect("group should be in list");
+            let links = self.groups.get_mut(&name).expect("group should be in list");
+            links.update(fs, attr, targets);
+        }
+
+        for name in create {
+            let targets = groups.remove(&name).expect("group should be in list");
+            let mut links = Links::new(fs, attr);
+            links.update(fs, attr, targets);
+            self.groups.insert(name, links);
+        }
+
+        let entries = self
+            .groups
+            .iter()
+            .map(|(name, links)| DirEntry {
+                inode: links.dir.ino(),
+                name: name.clone(),
+                file_type: FileType::Directory,
+            })
+            .collect();
+        match self.dir.node().as_ref() {
+            Node::Index(index) => index.set_entries(fs, entries),
+            _ => unreachable!("node should be an index"),
+        }
+    }
+}
+
+/// Returns the targets of the symlinks to the given secrets, by name.
+///
+/// Symlinks are named after the titles of the secrets. Secrets sharing the
+/// same title are named after their IDs instead.
+pub fn link_targets(
+    secrets: &[&(String, SecretMetadata)],
+    prefix: &str,
+) -> HashMap<String, String> {
+    let mut titles: HashMap<_, Vec<_>> = HashMap::new();
+    for (path, meta) in secrets {
+        let name = make_name(&meta.title).unwrap_or_else(|| meta.id.clone());
+        titles.entry(name).or_default().push((path, meta));
+    }
+
+    let mut targets = HashMap::new();
+    for (name, secrets) in titles {
+        if let [(path, _)] = secrets[..] {
+            targets.insert(name, format!("{prefix}{path}"));
+        } else {
+            for (path, meta) in secrets {
+                targets.insert(meta.id.clone(), format!("{prefix}{path}"));
+            }
+        }
+    }
+    targets
+}
+
+/// Returns the directory name of a category, e.g. `ssh-key` for `SSH_KEY`.
+fn category_name(category: &str) -> String {
+    category.to_lowercase().replace('_', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::fs::harness::{database_item, list, private_vault, ssh_item, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn indexes_secrets() {
+        let h = Harness::mount_with(FIXTURES, |config| config.indexes.global = true);
+
+        let mut item = database_item(&h);
+        item.metadata.tags = vec!["prod".to_string(), "db/primary".to_string()];
+        item.metadata.favorite = true;
+        h.fixtures().put_item(&private_vault(), item);
+        let mut key = ssh_item();
+        key.metadata.tags = vec!["prod".to_string()];
+        h.fixtures().put_item(&private_vault(), key);
+
+        let vault = h.path("personal/private");
+        assert_eq!(list(&vault.join(".by-tag")), ["db_primary", "prod"]);
+        assert_eq!(
+            list(&vault.join(".by-tag/prod")),
+            ["Database", "Deploy key"]
+        );
+        assert_eq!(list(&vault.join(".by-category")), ["login", "ssh-key"]);
+        assert_eq!(list(&vault.join(".favorites")), ["Database"]);
+
+        let favorite = vault.join(".favorites/Database");
+        assert_eq!(fs::read_link(&favorite).unwrap(), Path::new("../dbitem"));
+        assert_eq!(
+            fs::read_to_string(favorite.join("username")).unwrap(),
+            "admin"
+        );
+        let login = vault.join(".by-category/login/Database");
+        assert_eq!(fs::read_link(login).unwrap(), Path::new("../../dbitem"));
+
+        // Secrets sharing a title are named after their IDs
+        let mut twin = ssh_item();
+        twin.metadata.id = "twinitem".to_string();
+        h.fixtures().put_item(&private_vault(), twin);
+        assert_eq!(
+            list(&vault.join(".by-category/ssh-key")),
+            ["sshitem", "twinitem"]
+        );
+
+        let global = h.path(".by-tag/prod/Database");
+        assert_eq!(
+            fs::read_link(&global).unwrap(),
+            Path::new("../../personal/private/dbitem")
+        );
+        assert_eq!(
+            fs::read_to_string(global.join("username")).unwrap(),
+            "admin"
+        );
+        assert_eq!(
+            list(&h.path("")),
+            [
+                ".by-category",
+                ".by-tag",
+                ".favorites",
+                ".ref",
+                ".search",
+                "personal"
+            ]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::FileAttr;
+
+use crate::{
+    fs::{Fs, Inode},
+    onepassword::types::SecretMetadata,
+    util::{SharedCell, Totp},
+};
+
+use super::secret::FieldValue;
+
+/// A node representing the current code of a one-time-password field.
+///
+/// The code is computed locally from the seed stored in the field, each time
+/// the file is read. The file is always opened in direct I/O mode so that the
+/// kernel never serves a cached code.
+pub struct Otp {
+    /// The inode number of the node.
+    ino: Inode,
+
+    /// The inode number of the secret node the field belongs to.
+    secret: Inode,
+
+    /// The metadata of the secret.
+    metadata: SharedCell<SecretMetadata>,
+
+    /// The data of the one-time-password field.
+    data: SharedCell<FieldValue>,
+}
+
+impl Otp {
+    /// Creates a new one-time-password node.
+    pub fn new(
+        ino: Inode,
+        secret: Inode,
+        metadata: SharedCell<SecretMetadata>,
+        data: SharedCell<FieldValue>,
+    ) -> Otp {
+        Self {
+            ino,
+            secret,
+            metadata,
+            data,
+        }
+    }
+
+    /// Returns the inode number of the secret node the field belongs to.
+    pub fn secret(&self) -> Inode {
+        self.secret
+    }
+
+    /// Returns the file attributes of the node.
+    ///
+    /// The size is the number of digits of the codes, and the modification
+    /// time is the start of the validity period of the current code.
+    pub fn attr(&self, fs: &Fs) -> FileAttr {
+        let metadata = self.metadata.read();
+        let totp = self.totp();
+
+        let created_at = metadata.created_at.into();
+        let updated_at = match &totp {
+            Some(totp) => {
+                let now = unix_time();
+                UNIX_EPOCH + Duration::from_secs(now - now % totp.period())
+            }
+            None => metadata.updated_at.into(),
+        };
+
+        FileAttr {
+            ino: self.ino,
+            size: totp.map_or(0, |totp| u64::from(totp.digits())),
+            blocks: 0,
+            atime: updated_at,
+            mtime: updated_at,
+            ctime: updated_at,
+            crtime: created_at,
+            kind: fuser::FileType::RegularFile,
+            perm: fs.config.file_mode,
+            nlink: 1,
+            uid: fs.config.uid,
+            gid: fs.config.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// Reads the current code.
+    /// Returns `None` if the field does not hold a valid TOTP definition.
+    pub fn read(&self, offset: usize, size: usize) -> Option<Vec<u8>> {
+        use std::cmp::min;
+
+        let code = self.totp()?.code(unix_time());
+        let bytes = code.as_bytes();
+
+        let start = min(bytes.len(), offset);
+        let end = min(bytes.len(), offset.saturating_add(size));
+
+        Some(Vec::from(&bytes[start..end]))
+    }
+
+    /// Parses the TOTP definition stored in the field.
+    fn totp(&self) -> Option<Totp> {
+        Totp::parse(self.data.read().value())
+    }
+}
+
+/// Returns the current Unix time, in seconds.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::fs::harness::{database_item, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn computes_totp_codes() {
+        let h = Harness::mount(FIXTURES);
+
+        let uri = "otpauth://totp/Database?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8";
+        let mut item = database_item(&h);
+        item.fields.push(
+            serde_json::from_value(serde_json::json!({
+                "id": "otpfield",
+                "type": "OTP",
+                "label": "one-time password",
+                "reference": "op://private/Database/one-time password",
+                "value": uri,
+            }))
+            .unwrap(),
+        );
+        h.fixtures().put_item(&private_vault(), item);
+
+        let code = h.path("personal/private/dbitem/otpfield.totp");
+        assert_eq!(fs::metadata(&code).unwrap().len(), 8);
+
+        let totp = crate::util::Totp::parse(uri).unwrap();
+        let now = || {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        let before = totp.code(now());
+        let read = fs::read_to_string(&code).unwrap();
+        let after = totp.code(now());
+        assert!(read == before || read == after, "unexpected code {read}");
+        assert_eq!(
+            fs::read_to_string(h.path("personal/private/dbitem/otpfield")).unwrap(),
+            uri
+        );
+    }
+}
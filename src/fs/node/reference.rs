@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use fuser::{FileAttr, FUSE_ROOT_ID};
+
+use crate::{
+    fs::{Fs, Inode},
+    onepassword::{types::SecretField, Error},
+    util::Lock,
+};
+
+use super::{Handler, Node};
+
+/// The name of the reference directory, at the root of the filesystem.
+pub const REF_DIR: &str = ".ref";
+
+/// A node resolving `op://` secret references as paths.
+///
+/// `.ref/<vault>/<item>/[<section>/]<field>` resolves to the field of the
+/// reference `op://<vault>/<item>/[<section>/]<field>`, using names or IDs at
+/// each level. Each level is a directory resolving the next one when looked
+/// up, and fields are symlinks to the field nodes. The directories are always
+/// empty when listed.
+pub struct Reference {
+    /// The attributes of the node.
+    attr: FileAttr,
+
+    /// What the directory resolves.
+    scope: Scope,
+
+    /// The resolved children of the directory, by name.
+    children: Lock<HashMap<String, (Target, Handler)>>,
+}
+
+/// What a reference directory resolves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// The vaults of every account.
+    Root,
+
+    /// The items of a vault.
+    Vault {
+        /// The inode of the vault node.
+        ino: Inode,
+
+        /// The path of the vault, relative to the root.
+        path: String,
+    },
+
+    /// The sections and fields of an item.
+    Item {
+        /// The inode of the secret node.
+        ino: Inode,
+
+        /// The path of the secret, relative to the root.
+        path: String,
+    },
+
+    /// The fields of a section of an item.
+    Section {
+        /// The inode of the secret node.
+        ino: Inode,
+
+        /// The path of the secret, relative to the root.
+        path: String,
+
+        /// The ID of the section.
+        section: String,
+    },
+}
+
+/// The resolution of a name in a reference directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    /// A directory resolving the next level.
+    Dir(Scope),
+
+    /// A symlink to a field node.
+    Link(String),
+}
+
+impl Reference {
+    /// Creates a new reference node, with the attributes of the root.
+    pub fn new(ino: Inode, attr: &FileAttr, scope: Scope) -> Reference {
+        Self {
+            attr: FileAttr { ino, ..*attr },
+            scope,
+            children: Lock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the file attributes of the node.
+    pub fn attr(&self) -> FileAttr {
+        self.attr
+    }
+
+    /// Resolves a name in the directory and returns the inode of the child.
+    pub fn lookup(&self, fs: &Fs, name: &str) -> Result<Inode> {
+        let target = match &self.scope {
+            Scope::Root => resolve_vault(fs, name)?,
+            Scope::Vault { ino, path } => resolve_item(fs, *ino, path, name)?,
+            Scope::Item { ino, path } => resolve_field(fs, *ino, path, None, name)?,
+            Scope::Section { ino, path, section } => {
+                resolve_field(fs, *ino, path, Some(section), name)?
+            }
+        };
+
+        let mut children = self.children.lock();
+        let Some(target) = target else {
+            children.remove(name);
+            return Err(Error::NotFound(format!("no match for {name}")).into());
+        };
+
+        if let Some((existing, handler)) = children.get(name) {
+            if *existing == target {
+                return Ok(handler.ino());
+            }
+        }
+
+        let handler = match &target {
+            Target::Dir(scope) => {
+                fs.node_alloc(|ino| Node::new_reference(ino, &self.attr, scope.clone()))
+            }
+            Target::Link(path) => fs.node_alloc(|ino| Node::new_link(ino, path, &self.attr)),
+        };
+        let ino = handler.ino();
+        children.insert(name.to_string(), (target, handler));
+        Ok(ino)
+    }
+}
+
+/// Resolves a vault of any account by name or ID, ignoring case as 1Password
+/// does.
+///
+/// Vaults are named as in 1Password, not as mounted, or by their mount name if
+/// their account fails to list them.
+fn resolve_vault(fs: &Fs, name: &str) -> Result<Option<Target>> {
+    let root = fs.node_get(FUSE_ROOT_ID);
+    let Node::Root(root) = root.as_ref() else {
+        unreachable!("node should be the root");
+    };
+
+    let vaults = root.vaults(fs)?;
+    let names = root.vault_names(fs);
+    let candidates = vaults.iter().map(|(path, ino)| {
+        let node = fs.node_get(*ino);
+        let Node::Vault(vault) = node.as_ref() else {
+            unreachable!("node should be a vault");
+        };
+        let vault_name = names
+            .iter()
+            .find(|(id, _)| id == vault.id())
+            .map_or_else(|| path.rsplit('/').next().unwrap_or_default(), |(_, n)| n);
+        (
+            vault.id().vault().to_lowercase(),
+            vault_name.to_lowercase(),
+            (path, ino),
+        )
+    });
+
+    Ok(find(candidates, &name.to_lowercase()).map(|(path, ino)| {
+        Target::Dir(Scope::Vault {
+            ino: *ino,
+            path: path.clone(),
+        })
+    }))
+}
+
+/// Resolves an item of a vault by title or ID.
+fn resolve_item(fs: &Fs, ino: Inode, path: &str, name: &str) -> Result<Option<Target>> {
+    let node = fs.node_get(ino);
+    let Node::Vault(vault) = node.as_ref() else {
+        return Ok(None);
+    };
+
+    vault.refresh(fs)?;
+    let secrets = vault.secrets();
+    let candidates = secrets
+        .iter()
+        .map(|meta| (meta.id.clone(), meta.title.clone(), &meta.id));
+
+    Ok(find(candidates, name).and_then(|id| {
+        Some(Target::Dir(Scope::Item {
+            ino: vault.secret(id)?,
+            path: format!("{path}/{id}"),
+        }))
+    }))
+}
+
+/// Resolves a field of an item, or of one of its sections, by label or ID.
+///
+/// Outside of a section, fields take precedence over sections of the same
+/// name, as in a reference without a section.
+fn resolve_field(
+    fs: &Fs,
+    ino: Inode,
+    path: &str,
+    section: Option<&str>,
+    name: &str,
+) -> Result<Option<Target>> {
+    let node = fs.node_get(ino);
+    let Node::Secret(secret) = node.as_ref() else {
+        return Ok(None);
+    };
+
+    let fields = secret.fields(fs)?;
+    let in_section = |field: &&SecretField| {
+        section.is_none() || field.section.as_ref().map(|s| s.id.as_str()) == section
+    };
+    let candidates = fields
+        .iter()
+        .filter(in_section)
+        .map(|field| (field.id.clone(), field.label.clone(), &field.id));
+
+    // The field is three levels below `.ref`, or four within a section
+    let up = if section.is_some() {
+        "../../../.."
+    } else {
+        "../../.."
+    };
+    if let Some(id) = find(candidates, name) {
+        return Ok(Some(Target::Link(format!("{up}/{path}/{id}"))));
+    }
+    if section.is_some() {
+        return Ok(None);
+    }
+
+    let mut sections = fields
+        .iter()
+        .filter_map(|field| field.section.as_ref())
+        .map(|s| (s.id.clone(), s.label.clone().unwrap_or_default(), &s.id))
+        .collect::<Vec<_>>();
+    sections.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    sections.dedup_by(|a, b| a.0 == b.0);
+
+    Ok(find(sections.into_iter(), name).map(|id| {
+        Target::Dir(Scope::Section {
+            ino,
+            path: path.to_string(),
+            section: id.clone(),
+        })
+    }))
+}
+
+/// Finds the candidate with the given ID, or else the single candidate with
+/// the given name. Candidates are given as `(id, name, value)`.
+///
+/// Names matching several candidates are not resolved, as they are ambiguous.
+fn find<T, I>(candidates: I, query: &str) -> Option<T>
+where
+    I: Iterator<Item = (String, String, T)>,
+{
+    let mut by_name = Vec::new();
+    for (id, name, value) in candidates {
+        if id == query {
+            return Some(value);
+        }
+        if name == query {
+            by_name.push(value);
+        }
+    }
+
+    if by_name.len() == 1 {
+        by_name.pop()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, path::Path};
+
+    use crate::fs::harness::{database_item, list, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn resolves_references() {
+        let h = Harness::mount(FIXTURES);
+
+        let read = |reference: &str| fs::read_to_string(h.path(".ref").join(reference)).unwrap();
+        assert_eq!(read("private/Database/password"), "hunter2");
+        assert_eq!(read("private/dbitem/pwfield"), "hunter2");
+        assert_eq!(read("private/Database/api/token"), "s3cr3t");
+        assert_eq!(read("private/Database/apisection/tokfield"), "s3cr3t");
+        // Fields can be referenced without their section
+        assert_eq!(read("private/Database/token"), "s3cr3t");
+
+        let field = h.path(".ref/private/Database/api/token");
+        assert_eq!(
+            fs::read_link(&field).unwrap(),
+            Path::new("../../../../personal/private/dbitem/tokfield")
+        );
+        assert!(list(&h.path(".ref")).is_empty());
+
+        // Ambiguous titles are not resolved
+        let mut twin = database_item(&h);
+        twin.metadata.id = "twinitem".to_string();
+        h.fixtures().put_item(&private_vault(), twin);
+        let err = fs::metadata(h.path(".ref/private/Database")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(read("private/twinitem/username"), "admin");
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn resolves_vaults_by_their_name() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            let account = config.accounts.get_mut("personal").unwrap();
+            let vault = account.vaults.remove("private").unwrap();
+            account.vaults.insert("mounted".to_string(), vault);
+        });
+        h.fixtures().update(|set| {
+            set.vault_mut(&private_vault()).name = Some("Private Vault".to_string());
+        });
+
+        // Vaults are named as in 1Password, ignoring case, or by ID
+        let read = |reference: &str| fs::read_to_string(h.path(".ref").join(reference)).unwrap();
+        assert_eq!(read("Private Vault/Database/password"), "hunter2");
+        assert_eq!(read("private vault/Database/password"), "hunter2");
+        assert_eq!(read("PRIVATE/Database/password"), "hunter2");
+        assert_eq!(
+            fs::read_link(h.path(".ref/private/Database/password")).unwrap(),
+            Path::new("../../../personal/mounted/dbitem/pwfield")
+        );
+
+        // The mount name is not the name of the vault
+        let err = fs::metadata(h.path(".ref/mounted/Database")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,21 +1,47 @@
-use std::{cell::OnceCell, time::SystemTime};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
+};
 
+use anyhow::Result;
 use fuser::{FileAttr, FileType};
 
 use crate::{
-    fs::{dentry::DirEntry, Fs},
-    onepassword::id,
+    fs::{dentry::DirEntry, Fs, Inode},
+    onepassword::{
+        id,
+        sshkey::{SshKey, SSH_KEY_CATEGORY},
+        types::SecretMetadata,
+    },
+    util::{diff, Lock, Throttle},
 };
 
-use super::Node;
+use super::{
+    failure_xattrs,
+    index::Indexes,
+    make_name,
+    reference::{Scope, REF_DIR},
+    search::SEARCH_DIR,
+    Handler, Node,
+};
 
 /// The root node.
 pub struct Root {
     /// The cached file attributes of the node.
     attr: FileAttr,
 
-    /// The cached directory entries of the node.
-    entries: OnceCell<Vec<DirEntry>>,
+    /// The cached account handlers of the node, by name.
+    entries: Lock<Throttle<HashMap<String, (id::Account, Handler)>>>,
+
+    /// The index directories across all vaults, if enabled.
+    indexes: Lock<Option<Indexes>>,
+
+    /// The search directory, once listed.
+    search: OnceLock<Handler>,
+
+    /// The reference directory, once listed.
+    reference: OnceLock<Handler>,
 }
 
 impl Root {
@@ -23,7 +49,10 @@ impl Root {
     pub fn new(fs: &Fs) -> Self {
         Root {
             attr: make_attr(fs),
-            entries: OnceCell::new(),
+            entries: Lock::new(Throttle::default()),
+            indexes: Lock::new(None),
+            search: OnceLock::new(),
+            reference: OnceLock::new(),
         }
     }
 
@@ -32,10 +61,222 @@ impl Root {
         self.attr
     }
 
+    /// Returns the extended attributes of the node, describing its last
+    /// failed refresh if any.
+    pub fn xattrs(&self) -> Vec<(&'static str, String)> {
+        failure_xattrs(&self.entries.lock())
+    }
+
     /// Returns the directory entries of the node.
-    pub fn entries(&self, fs: &Fs) -> impl Iterator<Item = &DirEntry> {
-        self.entries.get_or_init(|| make_entries(fs)).iter()
+    /// Each account is represented as a directory, along with the search and
+    /// reference directories.
+    ///
+    /// If global indexes are enabled, every vault is listed to update them.
+    pub fn entries(&self, fs: &Fs) -> Result<impl Iterator<Item = DirEntry>> {
+        self.refresh(fs)?;
+
+        let search = self
+            .search
+            .get_or_init(|| fs.node_alloc(|ino| Node::new_search(ino, &self.attr)));
+        let reference = self
+            .reference
+            .get_or_init(|| fs.node_alloc(|ino| Node::new_reference(ino, &self.attr, Scope::Root)));
+        let special =
+            [(SEARCH_DIR, search), (REF_DIR, reference)].map(|(name, handler)| DirEntry {
+                inode: handler.ino(),
+                name: name.to_string(),
+                file_type: FileType::Directory,
+            });
+
+        let entries = self.entries.lock();
+        let indexes = self.indexes.lock();
+        Ok(entries
+            .iter()
+            .map(|(name, (_, handler))| DirEntry {
+                inode: handler.ino(),
+                name: name.clone(),
+                file_type: FileType::Directory,
+            })
+            .chain(indexes.iter().flat_map(Indexes::entries))
+            .chain(special)
+            .collect::<Vec<DirEntry>>()
+            .into_iter())
+    }
+
+    /// Returns the vault nodes of every account, by path relative to the root.
+    pub fn vaults(&self, fs: &Fs) -> Result<Vec<(String, Inode)>> {
+        self.refresh(fs)?;
+        Ok(list_vaults(fs, self.accounts()))
+    }
+
+    /// Returns the names of the vaults of every account in 1Password.
+    pub fn vault_names(&self, fs: &Fs) -> Vec<(id::Vault, String)> {
+        let mut names = Vec::new();
+        for (_, node) in self.accounts() {
+            let Node::Account(account) = node.as_ref() else {
+                unreachable!("node should be an account");
+            };
+            names.extend(account.vault_names(fs));
+        }
+        names
+    }
+
+    /// Returns the secrets of every vault, by path relative to the root.
+    pub fn secrets(&self, fs: &Fs) -> Result<Vec<(String, SecretMetadata)>> {
+        self.refresh(fs)?;
+        Ok(list_secrets(fs, self.accounts()))
+    }
+
+    /// Returns the private keys of the SSH Key items of every vault.
+    ///
+    /// The keys are loaded through the nodes of their vaults and items, so
+    /// that they are cached as the files of the filesystem are.
+    pub fn ssh_keys(&self, fs: &Fs) -> Result<Vec<Arc<SshKey>>> {
+        self.refresh(fs)?;
+        Ok(list_ssh_keys(fs, self.accounts()))
+    }
+
+    /// Returns the account nodes, by name.
+    ///
+    /// The nodes are collected so that the accounts are listed without
+    /// holding the lock of the root.
+    fn accounts(&self) -> Vec<(String, Arc<Node>)> {
+        self.entries
+            .lock()
+            .iter()
+            .map(|(name, (_, handler))| (name.clone(), handler.node()))
+            .collect()
+    }
+
+    /// Refreshes the account handlers, and the global indexes if enabled.
+    fn refresh(&self, fs: &Fs) -> Result<()> {
+        let mut refreshed = false;
+        self.entries.lock().try_refresh(
+            fs.config.cache_duration,
+            &fs.config.failures,
+            |entries| {
+                let mut accounts = list_accounts(fs)?;
+
+                let (delete, update, create) = diff(entries.keys(), accounts.keys());
+
+                for name in delete {
+                    entries.remove(&name);
+                }
+
+                for name in update {
+                    // The name now refers to another account
+                    let id = accounts.remove(&name).expect("account should be in list");
+                    if entries[&name].0 != id {
+                        let handler = fs.node_alloc(|ino| Node::new_account(ino, id.clone()));
+                        entries.insert(name, (id, handler));
+                    }
+                }
+
+                for name in create {
+                    let id = accounts.remove(&name).expect("account should be in list");
+                    let handler = fs.node_alloc(|ino| Node::new_account(ino, id.clone()));
+                    entries.insert(name, (id, handler));
+                }
+
+                refreshed = true;
+                Ok(())
+            },
+        )?;
+
+        // Listing every vault may take a while, so it is done once the root
+        // is unlocked, as other requests may need it meanwhile
+        if refreshed && fs.config.indexes.global {
+            let secrets = list_secrets(fs, self.accounts());
+            self.indexes
+                .lock()
+                .get_or_insert_with(|| Indexes::new(fs, &self.attr))
+                .update(fs, &secrets);
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the vaults of every account, by path relative to the root.
+///
+/// Accounts that fail to list are skipped, so that the other accounts are
+/// still covered.
+fn list_vaults(fs: &Fs, accounts: Vec<(String, Arc<Node>)>) -> Vec<(String, Inode)> {
+    let mut vaults = Vec::new();
+    for (account_name, node) in accounts {
+        let Node::Account(account) = node.as_ref() else {
+            unreachable!("node should be an account");
+        };
+        match account.entries(fs) {
+            Ok(entries) => vaults.extend(
+                entries.map(|vault| (format!("{account_name}/{}", vault.name), vault.inode)),
+            ),
+            Err(err) => error!(err = %err, account = account_name, "Failed to list vaults"),
+        }
     }
+    vaults
+}
+
+/// Lists the refreshed vault nodes of every account, by path relative to the
+/// root.
+///
+/// Vaults that fail to list are skipped, so that the other vaults are still
+/// covered.
+fn list_refreshed_vaults(fs: &Fs, accounts: Vec<(String, Arc<Node>)>) -> Vec<(String, Arc<Node>)> {
+    let mut vaults = Vec::new();
+    for (vault_path, ino) in list_vaults(fs, accounts) {
+        let node = fs.node_get(ino);
+        let Node::Vault(vault) = node.as_ref() else {
+            unreachable!("node should be a vault");
+        };
+        match vault.refresh(fs) {
+            Ok(()) => vaults.push((vault_path, node)),
+            Err(err) => error!(err = %err, vault = vault_path, "Failed to list secrets"),
+        }
+    }
+    vaults
+}
+
+/// Lists the secrets of every vault, by path relative to the root.
+fn list_secrets(fs: &Fs, accounts: Vec<(String, Arc<Node>)>) -> Vec<(String, SecretMetadata)> {
+    let mut secrets = Vec::new();
+    for (vault_path, node) in list_refreshed_vaults(fs, accounts) {
+        let Node::Vault(vault) = node.as_ref() else {
+            unreachable!("node should be a vault");
+        };
+        for meta in vault.secrets() {
+            secrets.push((format!("{vault_path}/{}", meta.id), meta));
+        }
+    }
+    secrets
+}
+
+/// Lists the private keys of the SSH Key items of every vault.
+///
+/// Items that fail to load are skipped, so that a single broken item does not
+/// hide every other key.
+fn list_ssh_keys(fs: &Fs, accounts: Vec<(String, Arc<Node>)>) -> Vec<Arc<SshKey>> {
+    let mut keys = Vec::new();
+    for (_, node) in list_refreshed_vaults(fs, accounts) {
+        let Node::Vault(vault) = node.as_ref() else {
+            unreachable!("node should be a vault");
+        };
+        for meta in vault.secrets() {
+            if meta.category != SSH_KEY_CATEGORY {
+                continue;
+            }
+            // The item may have been removed meanwhile
+            let node = vault.secret(&meta.id).map(|ino| fs.node_get(ino));
+            let Some(Node::Secret(secret)) = node.as_deref() else {
+                continue;
+            };
+            match secret.ssh_key(fs) {
+                Ok(key) => keys.extend(key),
+                Err(err) => error!(err = %err, item = meta.id, "Failed to load SSH key"),
+            }
+        }
+    }
+    keys
 }
 
 /// Creates the file attributes of the root node.
@@ -60,19 +301,65 @@ fn make_attr(fs: &Fs) -> FileAttr {
     }
 }
 
-/// Creates the directory entries of the root node.
-/// Each account is represented as a directory.
-fn make_entries(fs: &Fs) -> Vec<DirEntry> {
-    let mut entries = Vec::new();
-    for account in fs.config.accounts.keys() {
-        let entry = DirEntry {
-            inode: fs
-                .node_alloc(|ino| Node::new_account(ino, id::Account::new(account)))
-                .persist(),
-            name: account.to_string(),
-            file_type: FileType::Directory,
+/// Lists the accounts to mount, by name.
+///
+/// Configured accounts are listed first, then completed with the discovered
+/// accounts if enabled. If the accounts cannot be discovered, only the
+/// configured accounts are listed, unless there are none.
+fn list_accounts(fs: &Fs) -> Result<HashMap<String, id::Account>> {
+    let mut accounts = fs
+        .config
+        .accounts
+        .iter()
+        .map(|(name, account)| (name.clone(), id::Account::new(&account.id)))
+        .collect::<HashMap<_, _>>();
+
+    if fs.config.discovery.accounts {
+        let discovered = match fs.op.list_accounts() {
+            Ok(discovered) => discovered,
+            Err(err) if !accounts.is_empty() => {
+                warn!(err = %err, "Failed to discover accounts");
+                Vec::new()
+            }
+            Err(err) => return Err(err),
         };
-        entries.push(entry);
+        for account in discovered {
+            if accounts.values().any(|id| id.account() == account.id) {
+                continue;
+            }
+            if let Some(name) = make_name(&account.name) {
+                if fs.config.discovery.matches(&name) {
+                    let id = id::Account::new(&account.id);
+                    accounts.entry(name).or_insert(id);
+                }
+            }
+        }
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::harness::{list, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn discovers_accounts_and_vaults() {
+        let fixtures = FIXTURES.replacen(
+            r#""accounts": {"#,
+            r#""accounts": {
+            "work": { "vaults": { "shared": {}, "archive": {} } },"#,
+            1,
+        );
+        let h = Harness::mount_with(&fixtures, |config| {
+            config.accounts.retain(|name, _| name == "personal");
+            config.discovery.accounts = true;
+            config.discovery.exclude = vec!["archive".to_string().try_into().unwrap()];
+        });
+
+        assert_eq!(list(&h.path("")), [".ref", ".search", "personal", "work"]);
+        assert_eq!(list(&h.path("personal")), ["private"]);
+        assert_eq!(list(&h.path("work")), ["shared"]);
     }
-    entries
 }
@@ -0,0 +1,167 @@
+use std::{collections::HashMap, time::Instant};
+
+use anyhow::Result;
+use fuser::{FileAttr, FUSE_ROOT_ID};
+
+use crate::{
+    fs::{Fs, Inode},
+    onepassword::types::SecretMetadata,
+    util::Lock,
+};
+
+use super::{
+    index::{link_targets, Links},
+    make_name, Node,
+};
+
+/// The name of the search directory, at the root of the filesystem.
+pub const SEARCH_DIR: &str = ".search";
+
+/// The maximum number of queries to keep the results of.
+const MAX_QUERIES: usize = 64;
+
+/// The search node.
+///
+/// Looking up any name in this directory searches the secrets of every mounted
+/// vault, and returns a directory of symlinks to the matching secrets. The
+/// directory itself is always empty when listed.
+pub struct Search {
+    /// The attributes of the node.
+    attr: FileAttr,
+
+    /// The results of the most recent queries, by query.
+    queries: Lock<HashMap<String, Query>>,
+}
+
+/// The results of a query.
+struct Query {
+    /// The last time the query was looked up.
+    last_used: Instant,
+
+    /// The symlinks to the matching secrets.
+    links: Links,
+}
+
+impl Search {
+    /// Creates a new search node, with the attributes of the root.
+    pub fn new(ino: Inode, attr: &FileAttr) -> Search {
+        Self {
+            attr: FileAttr { ino, ..*attr },
+            queries: Lock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the file attributes of the node.
+    pub fn attr(&self) -> FileAttr {
+        self.attr
+    }
+
+    /// Searches the secrets matching the given query, and returns the inode of
+    /// the directory of results.
+    ///
+    /// The results of the least recently used query are dropped when too many
+    /// queries are kept.
+    pub fn lookup(&self, fs: &Fs, query: &str) -> Result<Inode> {
+        let root = fs.node_get(FUSE_ROOT_ID);
+        let Node::Root(root) = root.as_ref() else {
+            unreachable!("node should be the root");
+        };
+
+        let secrets = root.secrets(fs)?;
+        let matching = secrets
+            .iter()
+            .filter(|(_, meta)| matches(meta, query))
+            .collect::<Vec<_>>();
+        let targets = link_targets(&matching, "../../");
+
+        let mut queries = self.queries.lock();
+        if !queries.contains_key(query) && queries.len() >= MAX_QUERIES {
+            let oldest = queries
+                .iter()
+                .min_by_key(|(_, query)| query.last_used)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                queries.remove(&oldest);
+            }
+        }
+
+        let entry = queries.entry(query.to_string()).or_insert_with(|| Query {
+            last_used: Instant::now(),
+            links: Links::new(fs, &self.attr),
+        });
+        entry.last_used = Instant::now();
+        entry.links.update(fs, &self.attr, targets);
+        Ok(entry.links.ino())
+    }
+}
+
+/// Returns whether a secret matches a query.
+///
+/// The query is matched case-insensitively against a substring of the title,
+/// a whole tag, or the host of a URL and its parent domains.
+fn matches(meta: &SecretMetadata, query: &str) -> bool {
+    let query = query.to_lowercase();
+    let subdomain = format!(".{query}");
+
+    meta.title.to_lowercase().contains(&query)
+        || meta
+            .tags
+            .iter()
+            .filter_map(|tag| make_name(tag))
+            .any(|tag| tag.to_lowercase() == query)
+        || meta.urls.iter().any(|url| {
+            let host = url_host(&url.href).to_lowercase();
+            host == query || host.ends_with(&subdomain)
+        })
+}
+
+/// Returns the host of a URL, without its scheme, credentials or port.
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    host.split(':').next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::fs::harness::{database_item, list, private_vault, ssh_item, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn searches_secrets() {
+        let h = Harness::mount(FIXTURES);
+
+        let mut item = database_item(&h);
+        item.metadata.tags = vec!["prod".to_string()];
+        item.metadata.urls =
+            vec![
+                serde_json::from_str(r#"{ "href": "https://admin@db.example.com:5432/app" }"#)
+                    .unwrap(),
+            ];
+        h.fixtures().put_item(&private_vault(), item);
+        h.fixtures().put_item(&private_vault(), ssh_item());
+
+        let search = h.path(".search");
+        assert!(list(&search).is_empty());
+        assert_eq!(list(&search.join("DATA")), ["Database"]);
+        assert_eq!(list(&search.join("prod")), ["Database"]);
+        assert_eq!(list(&search.join("example.com")), ["Database"]);
+        assert_eq!(list(&search.join("key")), ["Deploy key"]);
+        assert!(list(&search.join("nothing")).is_empty());
+
+        let result = search.join("db.example.com/Database");
+        assert_eq!(
+            fs::read_link(&result).unwrap(),
+            Path::new("../../personal/private/dbitem")
+        );
+        assert_eq!(
+            fs::read_to_string(result.join("username")).unwrap(),
+            "admin"
+        );
+    }
+}
@@ -1,15 +1,20 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use fuser::{FileAttr, FileType};
 
 use crate::{
     fs::{dentry::DirEntry, Fs, Inode},
-    onepassword::{id, types::SecretMetadata},
-    util::{diff, SharedCell, Throttle},
+    onepassword::{
+        id,
+        sshkey::SshKey,
+        types::{self, FieldPurpose, FieldType, SecretMetadata, SecretVersion},
+        Error,
+    },
+    util::{diff, Lock, SharedCell, Throttle},
 };
 
-use super::{Handler, Node};
+use super::{failure_xattrs, make_name, Handler, Node};
 
 /// A secret node.
 pub struct Secret {
@@ -22,11 +27,39 @@ pub struct Secret {
     /// The metadata of the secret.
     metadata: SharedCell<SecretMetadata>,
 
-    /// The field handlers of the secret.
-    entries: RefCell<Throttle<HashMap<String, FieldHandler>>>,
+    /// The field, section and file handlers of the secret.
+    entries: Lock<Throttle<Fields>>,
 }
 
-/// A field handler that contains the field-node handler, the alias handler if
+/// The field, section and file handlers of a secret.
+#[derive(Default)]
+struct Fields {
+    /// The field handlers, by field ID.
+    fields: HashMap<String, FieldHandler>,
+
+    /// The section-node handlers, by section name.
+    sections: HashMap<String, Handler>,
+
+    /// The attachment-node handlers, by file ID.
+    files: HashMap<String, Handler>,
+
+    /// The handler of the `files` directory, if the secret has files.
+    files_dir: Option<Handler>,
+
+    /// The SSH key file handlers, by file name.
+    ssh: HashMap<String, Handler>,
+
+    /// The handler of the `ssh` directory, for SSH Key items.
+    ssh_dir: Option<Handler>,
+
+    /// The private key of SSH Key items.
+    key: Option<Arc<SshKey>>,
+
+    /// The version of the secret the handlers were last updated from.
+    version: Option<SecretVersion>,
+}
+
+/// A field handler that contains the field-node handler, the alias handlers if
 /// present and the field data.
 pub struct FieldHandler {
     /// The field-node handler.
@@ -36,18 +69,42 @@ pub struct FieldHandler {
     /// If present, the alias is a symlink to the field-node.
     alias: Option<(String, Handler)>,
 
+    /// The purpose alias handler if present.
+    /// If present, the alias is a symlink to the field-node named after the
+    /// purpose of the field, e.g. `password`.
+    purpose: Option<(String, Handler)>,
+
+    /// The one-time-password handler, for OTP fields.
+    /// If present, the node is a file named `<field id>.totp` containing the
+    /// current code.
+    otp: Option<(String, Handler)>,
+
     /// The field data.
     data: SharedCell<FieldValue>,
 }
 
-/// The value of a field.
-pub struct FieldValue(String);
+/// The value of a field, along with the version of the secret it was loaded
+/// from.
+pub struct FieldValue(types::SecretField, SecretVersion);
 
 impl FieldValue {
     /// Returns the value of the field.
     pub fn value(&self) -> &str {
+        self.0.value.as_deref().unwrap_or_default()
+    }
+
+    /// Returns the complete field.
+    pub fn field(&self) -> &types::SecretField {
         &self.0
     }
+
+    /// Returns the version of the secret the value was loaded from.
+    ///
+    /// It may be older than the version in the metadata of the secret, which
+    /// is also updated by the vault listing.
+    pub fn version(&self) -> SecretVersion {
+        self.1
+    }
 }
 
 impl Secret {
@@ -57,13 +114,13 @@ impl Secret {
             ino,
             id,
             metadata: SharedCell::new(meta),
-            entries: RefCell::new(Throttle::default()),
+            entries: Lock::new(Throttle::default()),
         }
     }
 
     /// Returns the file attributes of the node.
     pub fn attr(&self, fs: &Fs) -> FileAttr {
-        let meta = self.metadata.borrow();
+        let meta = self.metadata.read();
         let updated_at = meta.updated_at.into();
         let created_at = meta.created_at.into();
         FileAttr {
@@ -85,90 +142,599 @@ impl Secret {
         }
     }
 
+    /// Returns the extended attributes of the node, describing its last
+    /// failed refresh if any.
+    pub fn xattrs(&self) -> Vec<(&'static str, String)> {
+        failure_xattrs(&self.entries.lock())
+    }
+
+    /// Returns the metadata of the secret.
+    pub fn metadata(&self) -> SecretMetadata {
+        self.metadata.read().clone()
+    }
+
     /// Updates the metadata of the secret.
     ///
     /// This is used to update the metadata of the secret when the full vault
-    /// is refreshed.
-    pub fn update_metadata(&self, meta: SecretMetadata) {
-        *self.metadata.borrow_mut() = meta;
+    /// is refreshed. The attributes cached by the kernel are invalidated if the
+    /// secret has changed.
+    pub fn update_metadata(&self, fs: &Fs, meta: SecretMetadata) {
+        let mut current = self.metadata.write();
+        let changed = current.version != meta.version || current.updated_at != meta.updated_at;
+        *current = meta;
+        if changed {
+            fs.invalidate_inode(self.ino);
+        }
     }
 
     /// Returns the directory entries of the node.
+    ///
+    /// Fields are listed by ID, with symlinks named after their reference and
+    /// purpose. OTP fields also get a `<field id>.totp` file with the current
+    /// code. Sections are listed as directories of fields named after their
+    /// labels, attached files are listed in the `files` directory, and SSH
+    /// keys are available in OpenSSH format in the `ssh` directory.
     pub fn entries(&self, fs: &Fs) -> Result<impl Iterator<Item = DirEntry>> {
-        let mut entries = self.entries.borrow_mut();
+        self.refresh(fs)?;
+        let entries = dir_entries(&self.entries.lock());
+        Ok(entries.into_iter())
+    }
+
+    /// Returns the fields of the secret.
+    pub fn fields(&self, fs: &Fs) -> Result<Vec<types::SecretField>> {
+        self.refresh(fs)?;
+        Ok(self
+            .entries
+            .lock()
+            .fields
+            .values()
+            .map(|handler| handler.data.read().field().clone())
+            .collect())
+    }
 
-        entries.try_refresh(fs.config.cache_duration, |entries| {
-            let secret = fs.op.get_secret(&self.id)?;
-            self.update_metadata(secret.metadata);
+    /// Returns the private key of the secret, for SSH Key items.
+    pub fn ssh_key(&self, fs: &Fs) -> Result<Option<Arc<SshKey>>> {
+        self.refresh(fs)?;
+        Ok(self.entries.lock().key.clone())
+    }
+
+    /// Refreshes the fields of the secret if the cache has expired.
+    fn refresh(&self, fs: &Fs) -> Result<()> {
+        fs.refresh_cache(
+            self.ino,
+            &self.entries,
+            self.cache_duration(fs),
+            |entries| {
+                let secret = fs.op.get_secret(&self.id)?;
+                self.update_fields(fs, entries, secret);
+                Ok(())
+            },
+        )
+    }
 
-            let mut fields = secret
+    /// Refreshes the fields of the secret if the cache expires within
+    /// `ahead`.
+    ///
+    /// Unlike [`Secret::refresh`], the secret is fetched before locking the
+    /// cache, so that requests are still served from it meanwhile. The fetched
+    /// secret is discarded if it was edited meanwhile. A failed fetch is
+    /// recorded, to be retried after a backoff.
+    pub fn revalidate(&self, fs: &Fs, ahead: Duration) {
+        let epoch = {
+            let entries = self.entries.lock();
+            if !entries.is_due(self.cache_duration(fs), ahead) {
+                return;
+            }
+            entries.epoch()
+        };
+
+        let secret = match fs.op.get_secret(&self.id) {
+            Ok(fetched) => fetched,
+            Err(err) => return self.entries.lock().fail(&fs.config.failures, &err),
+        };
+        self.entries
+            .lock()
+            .update_since(epoch, |entries| self.update_fields(fs, entries, secret));
+    }
+
+    /// Sets the value of a field of the secret, if the secret is still at the
+    /// given version, and returns the new version of the secret.
+    ///
+    /// The fields are updated from the edited secret.
+    pub fn edit_field(
+        &self,
+        fs: &Fs,
+        field: &str,
+        version: SecretVersion,
+        value: &str,
+    ) -> Result<SecretVersion> {
+        let secret = fs
+            .op
+            .edit_field(&self.id, version, field, value)
+            .inspect_err(|err| self.edit_failed(fs, err))?;
+
+        let version = secret.metadata.version;
+        self.entries
+            .lock()
+            .update(|entries| self.update_fields(fs, entries, secret));
+        Ok(version)
+    }
+
+    /// Adds an empty field with the given label to the secret, and returns the
+    /// inode of its node.
+    pub fn add_field(&self, fs: &Fs, label: &str) -> Result<Inode> {
+        let version = self.metadata.read().version;
+        let secret = fs
+            .op
+            .add_field(&self.id, version, label)
+            .inspect_err(|err| self.edit_failed(fs, err))?;
+
+        let mut entries = self.entries.lock();
+        let added = secret
+            .fields
+            .iter()
+            .find(|f| {
+                f.label == label && f.section.is_none() && !entries.fields.contains_key(&f.id)
+            })
+            .map(|f| f.id.clone())
+            .ok_or_else(|| Error::NotFound(format!("field {label}")))?;
+        entries.update(|entries| self.update_fields(fs, entries, secret));
+        Ok(entries.fields[&added].node.ino())
+    }
+
+    /// Removes a field from the secret.
+    pub fn delete_field(&self, fs: &Fs, field: &str) -> Result<()> {
+        let version = self.metadata.read().version;
+        let secret = fs
+            .op
+            .delete_field(&self.id, version, field)
+            .inspect_err(|err| self.edit_failed(fs, err))?;
+
+        self.entries
+            .lock()
+            .update(|entries| self.update_fields(fs, entries, secret));
+        Ok(())
+    }
+
+    /// Reports a failed edit of the secret.
+    ///
+    /// If the secret was modified elsewhere meanwhile, the fields are
+    /// refreshed, so that the current values can be read before editing the
+    /// secret again.
+    fn edit_failed(&self, fs: &Fs, err: &anyhow::Error) {
+        warn!(err = %err, item = self.id.secret(), "Failed to edit item");
+        if matches!(err.downcast_ref(), Some(Error::Conflict(_))) {
+            self.revalidate(fs, Duration::MAX);
+        }
+    }
+
+    /// Returns the duration to cache the fields of the secret for.
+    pub fn cache_duration(&self, fs: &Fs) -> Duration {
+        let title = self.metadata.read().title.clone();
+        let id = &self.id;
+        fs.config
+            .fields_cache(id.account(), id.vault(), id.secret(), &title)
+    }
+
+    /// Returns whether the fields or the metadata of the secret are never
+    /// cached.
+    pub fn volatile(&self, fs: &Fs) -> bool {
+        let listing = fs.config.listing_cache(self.id.account(), self.id.vault());
+        self.cache_duration(fs).is_zero() || listing.is_zero()
+    }
+
+    /// Updates the fields of the secret from an already fetched secret.
+    ///
+    /// This is used when the secret was fetched along with the rest of its
+    /// vault, so that reading its fields does not require another call.
+    pub fn prefetched(&self, fs: &Fs, secret: types::Secret) {
+        self.entries
+            .lock()
+            .update(|entries| self.update_fields(fs, entries, secret));
+    }
+
+    /// Updates the field, section and file handlers from the given secret.
+    fn update_fields(&self, fs: &Fs, entries: &mut Fields, secret: types::Secret) {
+        let key = SshKey::from_secret(&secret)
+            .inspect_err(|e| error!(err = %e, "Failed to parse SSH key"))
+            .ok()
+            .flatten()
+            .map(Arc::new);
+
+        let before = dir_entries(entries);
+        let version = secret.metadata.version;
+        let changed = entries
+            .version
+            .replace(version)
+            .is_some_and(|previous| previous != version);
+
+        self.update_metadata(fs, secret.metadata);
+        self.update_files(fs, entries, secret.files);
+        self.update_ssh(fs, entries, key.as_deref());
+        entries.key = key;
+
+        // Section labels are not always repeated in the fields
+        let labels = secret
+            .sections
+            .into_iter()
+            .map(|section| (section.id, section.label))
+            .collect::<HashMap<_, _>>();
+
+        let mut fields = secret
+            .fields
+            .into_iter()
+            .filter(|field| !field.id.is_empty())
+            .map(|mut field| {
+                if let Some(section) = &mut field.section {
+                    if section.label.is_none() {
+                        section.label = labels.get(&section.id).cloned();
+                    }
+                }
+                (field.id.clone(), field)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let (delete, update, create) = diff(entries.fields.keys(), fields.keys());
+
+        for id in delete {
+            entries.fields.remove(&id);
+        }
+
+        for id in update {
+            let field = fields.remove(&id).expect("field should be in list");
+            let handler = entries
                 .fields
-                .into_iter()
-                .filter(|field| !field.id.is_empty())
-                .map(|field| (field.id.clone(), field))
-                .collect::<HashMap<_, _>>();
+                .get_mut(&id)
+                .expect("handler should be in list");
 
-            let (delete, update, create) = diff(entries.keys(), fields.keys());
+            // Update the purpose alias if the purpose has changed
+            let purpose = purpose_alias(&field);
+            if purpose.as_ref() != handler.purpose.as_ref().map(|(name, _)| name) {
+                handler.purpose = purpose.map(|name| (name, make_link(fs, &handler.node, &id)));
+            }
 
-            for id in delete {
-                entries.remove(&id);
+            // Add or remove the code file if the type has changed
+            let otp = field.kind == FieldType::Otp;
+            *handler.data.write() = FieldValue(field, version);
+            if otp != handler.otp.is_some() {
+                handler.otp = otp.then(|| self.make_otp(fs, &id, &handler.data));
             }
+        }
 
-            for id in update {
-                let field = fields.remove(&id).expect("field should be in list");
-                let handler = entries.get(&id).expect("handler should be in list");
+        for id in create {
+            let field = fields.remove(&id).expect("field should be in list");
 
-                handler.data.borrow_mut().0 = field.value.unwrap_or_default();
+            let alias = field_alias(&field.reference).filter(|alias| *alias != field.id);
+            let purpose = purpose_alias(&field);
+            // Trim the notes field only
+            let trim = field.purpose == Some(FieldPurpose::Notes) || id == "notesPlain";
+            let otp = field.kind == FieldType::Otp;
+
+            let data = SharedCell::new(FieldValue(field, version));
+            let node = fs.node_alloc({
+                let metadata = self.metadata.clone();
+                let data = data.clone();
+                move |ino| Node::new_field(ino, self.ino, metadata, data, trim)
+            });
+
+            let alias = alias.map(|alias| (alias, make_link(fs, &node, &id)));
+            let purpose = purpose.map(|name| (name, make_link(fs, &node, &id)));
+            let otp = otp.then(|| self.make_otp(fs, &id, &data));
+
+            entries.fields.insert(
+                id,
+                FieldHandler {
+                    node,
+                    alias,
+                    purpose,
+                    otp,
+                    data,
+                },
+            );
+        }
+
+        self.update_sections(fs, entries);
+
+        fs.invalidate_entries(self.ino, &before, &dir_entries(entries));
+        if changed {
+            for ino in owned_inodes(entries) {
+                fs.invalidate_inode(ino);
             }
+        }
+    }
 
-            for id in create {
-                let field = fields.remove(&id).expect("field should be in list");
+    /// Updates the section nodes from the fields of the secret.
+    ///
+    /// Each section with a label is a directory containing its fields, named
+    /// after their labels.
+    fn update_sections(&self, fs: &Fs, entries: &mut Fields) {
+        let mut contents: HashMap<String, HashMap<String, DirEntry>> = HashMap::new();
+        for handler in entries.fields.values() {
+            let data = handler.data.read();
+            let field = data.field();
 
-                let data = SharedCell::new(FieldValue(field.value.unwrap_or_default()));
-                let node = fs.node_alloc({
-                    let metadata = self.metadata.clone();
-                    let data = data.clone();
-                    let trim = id == "notesPlain"; // Trim the notes field only
-                    move |ino| Node::new_field(ino, metadata, data, trim)
+            let section = field.section.as_ref().and_then(|s| s.label.as_deref());
+            if let (Some(section), Some(name)) =
+                (section.and_then(make_name), make_name(&field.label))
+            {
+                contents
+                    .entry(section)
+                    .or_default()
+                    .entry(name.clone())
+                    .or_insert(DirEntry {
+                        inode: handler.node.ino(),
+                        name,
+                        file_type: FileType::RegularFile,
+                    });
+            }
+        }
+
+        entries
+            .sections
+            .retain(|name, _| contents.contains_key(name));
+
+        for (name, fields) in contents {
+            let handler = entries.sections.entry(name).or_insert_with(|| {
+                let metadata = self.metadata.clone();
+                fs.node_alloc(|ino| Node::new_section(ino, self.ino, metadata))
+            });
+            match handler.node().as_ref() {
+                Node::Section(section) => section.set_entries(fs, fields.into_values().collect()),
+                _ => unreachable!("node should be a section"),
+            }
+        }
+    }
+
+    /// Creates the code file of an OTP field.
+    fn make_otp(&self, fs: &Fs, id: &str, data: &SharedCell<FieldValue>) -> (String, Handler) {
+        let metadata = self.metadata.clone();
+        let data = data.clone();
+        let node = fs.node_alloc(|ino| Node::new_otp(ino, self.ino, metadata, data));
+        (format!("{id}.totp"), node)
+    }
+
+    /// Updates the attachment nodes and the `files` directory from the files of
+    /// the secret.
+    ///
+    /// Document items expose their document in the `files` directory too,
+    /// named after the item title if 1Password does not report the file.
+    fn update_files(&self, fs: &Fs, entries: &mut Fields, files: Vec<types::SecretFile>) {
+        let (document, title) = {
+            let meta = self.metadata.read();
+            (meta.category == "DOCUMENT", meta.title.clone())
+        };
+
+        let mut files = files
+            .into_iter()
+            .map(|file| (file.id.clone(), file))
+            .collect::<HashMap<_, _>>();
+        if document && files.is_empty() {
+            let file = types::SecretFile {
+                id: String::new(),
+                name: title,
+                size: 0,
+            };
+            files.insert(file.id.clone(), file);
+        }
+
+        let (delete, update, create) = diff(entries.files.keys(), files.keys());
+
+        for id in delete {
+            entries.files.remove(&id);
+        }
+
+        for id in update {
+            let file = files.get(&id).expect("file should be in list").clone();
+            let handler = entries.files.get(&id).expect("handler should be in list");
+            match handler.node().as_ref() {
+                Node::Attachment(node) => node.update(file),
+                _ => unreachable!("node should be an attachment"),
+            }
+        }
+
+        for id in create {
+            let file = files.get(&id).expect("file should be in list").clone();
+            let node = fs.node_alloc(|ino| {
+                Node::new_attachment(
+                    ino,
+                    self.ino,
+                    self.id.clone(),
+                    self.metadata.clone(),
+                    file,
+                    document,
+                )
+            });
+            entries.files.insert(id, node);
+        }
+
+        let mut contents = HashMap::new();
+        for (id, handler) in &entries.files {
+            let file = files.get(id).expect("file should be in list");
+            if let Some(name) = make_name(&file.name) {
+                contents.entry(name.clone()).or_insert(DirEntry {
+                    inode: handler.ino(),
+                    name,
+                    file_type: FileType::RegularFile,
                 });
+            }
+        }
 
-                let alias = field_alias(&field.reference)
-                    .filter(|alias| *alias != field.id)
-                    .map(|alias| {
-                        let attr = node.node().attr(fs).expect("attr should be available");
-                        let handler = fs.node_alloc(|ino| Node::new_link(ino, &field.id, &attr));
-                        (alias, handler)
-                    });
+        self.update_dir(fs, &mut entries.files_dir, contents);
+    }
 
-                entries.insert(id, FieldHandler { node, alias, data });
+    /// Updates the nodes of the `ssh` directory from the private key of an
+    /// SSH Key item.
+    ///
+    /// The directory contains the private key in OpenSSH format, the public key
+    /// and the fingerprint, named after the default OpenSSH file names.
+    fn update_ssh(&self, fs: &Fs, entries: &mut Fields, key: Option<&SshKey>) {
+        let mut files = match key.map(ssh_files).transpose() {
+            Ok(files) => files.unwrap_or_default(),
+            Err(err) => {
+                error!(err = %err, "Failed to convert SSH key");
+                HashMap::new()
             }
+        };
 
-            Ok(())
-        })?;
+        let (delete, update, create) = diff(entries.ssh.keys(), files.keys());
 
-        Ok(entries
+        for name in delete {
+            entries.ssh.remove(&name);
+        }
+
+        for name in update {
+            let file = files.remove(&name).expect("file should be in list");
+            let handler = entries.ssh.get(&name).expect("handler should be in list");
+            match handler.node().as_ref() {
+                Node::Derived(node) => node.set_data(file.data),
+                _ => unreachable!("node should be a derived file"),
+            }
+        }
+
+        for name in create {
+            let file = files.remove(&name).expect("file should be in list");
+            let metadata = self.metadata.clone();
+            let node = fs.node_alloc(|ino| {
+                Node::new_derived(ino, self.ino, metadata, file.data, file.private)
+            });
+            entries.ssh.insert(name, node);
+        }
+
+        let contents = entries
+            .ssh
             .iter()
-            .flat_map(|(name, handler)| {
-                let main = DirEntry {
-                    inode: handler.node.ino(),
+            .map(|(name, handler)| {
+                let entry = DirEntry {
+                    inode: handler.ino(),
                     name: name.clone(),
                     file_type: FileType::RegularFile,
                 };
-                match &handler.alias {
-                    Some((alias, handler)) => vec![
-                        main,
-                        DirEntry {
-                            inode: handler.ino(),
-                            name: alias.clone(),
-                            file_type: FileType::Symlink,
-                        },
-                    ],
-                    None => vec![main],
-                }
+                (name.clone(), entry)
             })
-            .collect::<Vec<DirEntry>>()
-            .into_iter())
+            .collect();
+
+        self.update_dir(fs, &mut entries.ssh_dir, contents);
     }
+
+    /// Updates a directory maintained by the secret node with the given
+    /// entries. The directory is removed if there are no entries.
+    fn update_dir(&self, fs: &Fs, dir: &mut Option<Handler>, contents: HashMap<String, DirEntry>) {
+        if contents.is_empty() {
+            *dir = None;
+            return;
+        }
+
+        let handler = dir.get_or_insert_with(|| {
+            let metadata = self.metadata.clone();
+            fs.node_alloc(|ino| Node::new_section(ino, self.ino, metadata))
+        });
+        match handler.node().as_ref() {
+            Node::Section(section) => section.set_entries(fs, contents.into_values().collect()),
+            _ => unreachable!("node should be a section"),
+        }
+    }
+}
+
+/// Lists the fields of a secret by ID, along with their symlinks, code files,
+/// sections and the `files` and `ssh` directories.
+fn dir_entries(entries: &Fields) -> Vec<DirEntry> {
+    // Deduplicate entries, field IDs taking precedence over other names
+    let mut list = HashMap::new();
+    for (name, handler) in &entries.fields {
+        list.insert(name.clone(), (handler.node.ino(), FileType::RegularFile));
+    }
+    for handler in entries.fields.values() {
+        if let Some((name, handler)) = &handler.otp {
+            list.entry(name.clone())
+                .or_insert((handler.ino(), FileType::RegularFile));
+        }
+    }
+    for (name, handler) in &entries.sections {
+        list.entry(name.clone())
+            .or_insert((handler.ino(), FileType::Directory));
+    }
+    if let Some(handler) = &entries.files_dir {
+        list.entry(FILES_DIR.to_string())
+            .or_insert((handler.ino(), FileType::Directory));
+    }
+    if let Some(handler) = &entries.ssh_dir {
+        list.entry(SSH_DIR.to_string())
+            .or_insert((handler.ino(), FileType::Directory));
+    }
+    for handler in entries.fields.values() {
+        for (name, handler) in handler.purpose.iter().chain(&handler.alias) {
+            list.entry(name.clone())
+                .or_insert((handler.ino(), FileType::Symlink));
+        }
+    }
+
+    list.into_iter()
+        .map(|(name, (inode, file_type))| DirEntry {
+            inode,
+            name,
+            file_type,
+        })
+        .collect()
+}
+
+/// Returns the inodes of the files owned by a secret, whose content may
+/// change along with the secret.
+fn owned_inodes(entries: &Fields) -> impl Iterator<Item = Inode> + '_ {
+    let fields = entries.fields.values().flat_map(|handler| {
+        let otp = handler.otp.as_ref().map(|(_, handler)| handler.ino());
+        std::iter::once(handler.node.ino()).chain(otp)
+    });
+    fields
+        .chain(entries.files.values().map(Handler::ino))
+        .chain(entries.ssh.values().map(Handler::ino))
+}
+
+/// The name of the directory containing the SSH key files of a secret.
+const SSH_DIR: &str = "ssh";
+
+/// A file of the `ssh` directory.
+struct SshFile {
+    /// The content of the file.
+    data: Vec<u8>,
+
+    /// Whether the file is private.
+    private: bool,
+}
+
+/// Returns the files of the `ssh` directory, by name.
+fn ssh_files(key: &SshKey) -> Result<HashMap<String, SshFile>> {
+    let name = format!("id_{}", key.kind());
+    let file = |data: String, private| SshFile {
+        data: data.into_bytes(),
+        private,
+    };
+    Ok(HashMap::from([
+        (format!("{name}.pub"), file(key.public_openssh()?, false)),
+        (name, file(key.private_openssh()?, true)),
+        ("fingerprint".to_string(), file(key.fingerprint(), false)),
+    ]))
+}
+
+/// The name of the directory containing the files of a secret.
+const FILES_DIR: &str = "files";
+
+/// Creates a symlink to the given field node.
+fn make_link(fs: &Fs, node: &Handler, target: &str) -> Handler {
+    let attr = node.node().attr(fs).expect("attr should be available");
+    fs.node_alloc(|ino| Node::new_link(ino, target, &attr))
+}
+
+/// Returns the purpose alias name of a field.
+///
+/// Built-in fields get a stable name, independent of their ID.
+fn purpose_alias(field: &types::SecretField) -> Option<String> {
+    let name = match field.purpose? {
+        FieldPurpose::Username => "username",
+        FieldPurpose::Password => "password",
+        FieldPurpose::Notes => "notes",
+        FieldPurpose::Unknown => return None,
+    };
+    Some(name.to_string()).filter(|name| *name != field.id)
 }
 
 /// Returns the alias name of a field.
@@ -191,3 +757,27 @@ fn field_alias(reference: &str) -> Option<String> {
     let parts = reference.split('/').skip(4);
     Some(parts.collect::<Vec<&str>>().join("_"))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::fs::harness::{database_item, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn follows_edits() {
+        let h = Harness::mount(FIXTURES);
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.metadata.updated_at += time::Duration::days(1);
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+
+        assert_eq!(fs::read_to_string(&username).unwrap(), "root");
+    }
+}
@@ -0,0 +1,80 @@
+use fuser::{FileAttr, FileType};
+
+use crate::{
+    fs::{dentry::DirEntry, Fs, Inode},
+    onepassword::types::SecretMetadata,
+    util::{Lock, SharedCell},
+};
+
+/// A node representing a section of a secret.
+///
+/// It contains the fields of the section, named after their labels. The
+/// `files` directory of a secret is also a section node, containing the
+/// attached files named after their file names. The entries are maintained by
+/// the parent secret node.
+pub struct Section {
+    /// The inode number of the node.
+    ino: Inode,
+
+    /// The inode number of the secret node the section belongs to.
+    secret: Inode,
+
+    /// The metadata of the secret.
+    metadata: SharedCell<SecretMetadata>,
+
+    /// The directory entries of the node.
+    entries: Lock<Vec<DirEntry>>,
+}
+
+impl Section {
+    /// Creates a new section node.
+    pub fn new(ino: Inode, secret: Inode, metadata: SharedCell<SecretMetadata>) -> Section {
+        Self {
+            ino,
+            secret,
+            metadata,
+            entries: Lock::new(Vec::new()),
+        }
+    }
+
+    /// Returns the inode number of the secret node the section belongs to.
+    pub fn secret(&self) -> Inode {
+        self.secret
+    }
+
+    /// Returns the file attributes of the node.
+    pub fn attr(&self, fs: &Fs) -> FileAttr {
+        let meta = self.metadata.read();
+        let updated_at = meta.updated_at.into();
+        let created_at = meta.created_at.into();
+        FileAttr {
+            ino: self.ino,
+            size: 512,
+            blocks: 0,
+            atime: updated_at,
+            mtime: updated_at,
+            ctime: updated_at,
+            crtime: created_at,
+            kind: FileType::Directory,
+            perm: fs.config.dir_mode,
+            nlink: 1,
+            uid: fs.config.uid,
+            gid: fs.config.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// Returns the directory entries of the node.
+    pub fn entries(&self) -> impl Iterator<Item = DirEntry> {
+        self.entries.lock().clone().into_iter()
+    }
+
+    /// Replaces the directory entries of the node, invalidating the ones
+    /// cached by the kernel that changed.
+    pub fn set_entries(&self, fs: &Fs, entries: Vec<DirEntry>) {
+        let before = std::mem::replace(&mut *self.entries.lock(), entries.clone());
+        fs.invalidate_entries(self.ino, &before, &entries);
+    }
+}
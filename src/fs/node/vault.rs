@@ -1,7 +1,7 @@
 use std::{
-    cell::{OnceCell, RefCell},
     collections::HashMap,
-    time::SystemTime,
+    sync::OnceLock,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Result;
@@ -9,11 +9,19 @@ use fuser::{FileAttr, FileType};
 
 use crate::{
     fs::{dentry::DirEntry, Fs, Inode},
-    onepassword::id,
-    util::{diff, Throttle},
+    onepassword::{
+        id,
+        types::{self, SecretMetadata},
+    },
+    util::{diff, Lock, SharedCell, Throttle},
 };
 
-use super::{Handler, Node};
+use super::{
+    category::{category_id, Selection, CATEGORY_FILE},
+    failure_xattrs,
+    index::Indexes,
+    make_name, Handler, Node,
+};
 
 /// A vault node.
 pub struct Vault {
@@ -24,10 +32,19 @@ pub struct Vault {
     id: id::Vault,
 
     /// The cached file attributes of the node.
-    attr: OnceCell<FileAttr>,
+    attr: OnceLock<FileAttr>,
 
     /// The cached secret handlers of the node.
-    entries: RefCell<Throttle<HashMap<String, SecretHandler>>>,
+    entries: Lock<Throttle<HashMap<String, SecretHandler>>>,
+
+    /// The index directories of the vault, once listed.
+    indexes: Lock<Option<Indexes>>,
+
+    /// The category or template of the items created in the vault.
+    category: SharedCell<Selection>,
+
+    /// The handler of the `.category` file, once listed.
+    category_file: OnceLock<Handler>,
 }
 
 /// A secret handler that contains the secret-node handler, the alias handler if
@@ -38,6 +55,10 @@ struct SecretHandler {
 
     /// The alias handler if present.
     alias: Option<(String, Handler)>,
+
+    /// The name the directory of the secret was created with, if it differs
+    /// from its ID, and the handler of the symlink named after the ID.
+    created: Option<(String, Handler)>,
 }
 
 impl SecretHandler {
@@ -45,6 +66,12 @@ impl SecretHandler {
     fn alias_name(&self) -> Option<&String> {
         self.alias.as_ref().map(|(name, _)| name)
     }
+
+    /// Returns the name the directory of the secret was created with, if it
+    /// differs from its ID.
+    fn created_name(&self) -> Option<&str> {
+        self.created.as_ref().map(|(name, _)| name.as_str())
+    }
 }
 
 impl Vault {
@@ -53,124 +80,384 @@ impl Vault {
         Self {
             ino,
             id,
-            attr: OnceCell::new(),
-            entries: RefCell::new(Throttle::default()),
+            attr: OnceLock::new(),
+            entries: Lock::new(Throttle::default()),
+            indexes: Lock::new(None),
+            category: SharedCell::new(Selection::default()),
+            category_file: OnceLock::new(),
         }
     }
 
+    /// Returns the ID of the vault.
+    pub fn id(&self) -> &id::Vault {
+        &self.id
+    }
+
     /// Returns the file attributes of the node.
     pub fn attr(&self, fs: &Fs) -> FileAttr {
         *self.attr.get_or_init(|| make_attr(self, fs))
     }
 
+    /// Returns the extended attributes of the node, describing its last
+    /// failed refresh if any.
+    pub fn xattrs(&self) -> Vec<(&'static str, String)> {
+        failure_xattrs(&self.entries.lock())
+    }
+
     /// Returns the directory entries of the node.
+    ///
+    /// Secrets are listed by ID, with symlinks named after their titles. The
+    /// index directories are updated along with the listing, and the
+    /// `.category` file selects the category or template of the items
+    /// created in the vault.
     pub fn entries(&self, fs: &Fs) -> Result<impl Iterator<Item = DirEntry>> {
-        let mut entries = self.entries.borrow_mut();
-        entries.try_refresh(fs.config.cache_duration, |entries| {
-            let mut secrets = fs
-                .op
-                .list_secrets(&self.id)?
+        self.refresh(fs)?;
+
+        let entries = dir_entries(&self.entries.lock());
+        let indexes = self.indexes.lock();
+        let indexes = indexes.iter().flat_map(Indexes::entries);
+        let category = self.category_file.get_or_init(|| {
+            let attr = self.attr(fs);
+            let category = self.category.clone();
+            fs.node_alloc(|ino| Node::new_category(ino, &attr, category))
+        });
+        let category = DirEntry {
+            inode: category.ino(),
+            name: CATEGORY_FILE.to_string(),
+            file_type: FileType::RegularFile,
+        };
+
+        Ok(entries
+            .into_iter()
+            .chain(indexes)
+            .chain([category])
+            .map(|entry| (entry.name.clone(), entry))
+            .collect::<HashMap<String, DirEntry>>() // Deduplicate entries
+            .into_values())
+    }
+
+    /// Refreshes the secret handlers and the index directories if the cache
+    /// has expired.
+    pub fn refresh(&self, fs: &Fs) -> Result<()> {
+        fs.refresh_cache(
+            self.ino,
+            &self.entries,
+            self.cache_duration(fs),
+            |entries| {
+                let (list, prefetched) = self.fetch(fs)?;
+                self.update_entries(fs, entries, list, prefetched);
+                Ok(())
+            },
+        )
+    }
+
+    /// Refreshes the secret handlers and the index directories if the cache
+    /// expires within `ahead`.
+    ///
+    /// Unlike [`Vault::refresh`], the vault is fetched before locking the
+    /// cache, so that requests are still served from it meanwhile. The fetched
+    /// listing is discarded if items were created or deleted meanwhile. A
+    /// failed fetch is recorded, to be retried after a backoff.
+    pub fn revalidate(&self, fs: &Fs, ahead: Duration) {
+        let epoch = {
+            let entries = self.entries.lock();
+            if !entries.is_due(self.cache_duration(fs), ahead) {
+                return;
+            }
+            entries.epoch()
+        };
+
+        let (list, prefetched) = match self.fetch(fs) {
+            Ok(fetched) => fetched,
+            Err(err) => return self.entries.lock().fail(&fs.config.failures, &err),
+        };
+        self.entries.lock().update_since(epoch, |entries| {
+            self.update_entries(fs, entries, list, prefetched);
+        });
+    }
+
+    /// Returns the duration to cache the listing of the vault for.
+    pub fn cache_duration(&self, fs: &Fs) -> Duration {
+        fs.config.listing_cache(self.id.account(), self.id.vault())
+    }
+
+    /// Fetches the metadata of the secrets of the vault.
+    ///
+    /// When prefetching, the full secrets are also returned by ID, to be kept
+    /// aside until their nodes are created or updated.
+    fn fetch(&self, fs: &Fs) -> Result<(Vec<SecretMetadata>, HashMap<String, types::Secret>)> {
+        if self.prefetch(fs) {
+            let secrets = fs.op.get_secrets(&self.id)?;
+            let list = secrets.iter().map(|s| s.metadata.clone()).collect();
+            let prefetched = secrets
                 .into_iter()
-                .map(|secret| (secret.id.clone(), secret))
-                .collect::<HashMap<_, _>>();
+                .map(|s| (s.metadata.id.clone(), s))
+                .collect();
+            Ok((list, prefetched))
+        } else {
+            Ok((fs.op.list_secrets(&self.id)?, HashMap::new()))
+        }
+    }
+
+    /// Updates the secret handlers and the index directories from the fetched
+    /// secrets.
+    fn update_entries(
+        &self,
+        fs: &Fs,
+        entries: &mut HashMap<String, SecretHandler>,
+        list: Vec<SecretMetadata>,
+        prefetched: HashMap<String, types::Secret>,
+    ) {
+        self.update_indexes(fs, &list);
+
+        let mut secrets = list
+            .into_iter()
+            .map(|secret| (secret.id.clone(), secret))
+            .collect::<HashMap<_, _>>();
+
+        let before = dir_entries(entries);
+        let (delete, update, create) = diff(entries.keys(), secrets.keys());
+
+        for id in delete {
+            entries.remove(&id);
+        }
 
-            let (delete, update, create) = diff(entries.keys(), secrets.keys());
+        for id in update {
+            let meta = secrets.remove(&id).expect("secret should be in list");
+            let title = meta.title.clone();
+            let handler = entries.get_mut(&id).expect("handler should be in list");
 
-            for id in delete {
-                entries.remove(&id);
+            match handler.node.node().as_ref() {
+                Node::Secret(secret) => secret.update_metadata(fs, meta),
+                _ => unreachable!("node should be a secret"),
             }
 
-            for id in update {
-                let meta = secrets.remove(&id).expect("secret should be in list");
-                let title = meta.title.clone();
-                let handler = entries.get_mut(&id).expect("handler should be in list");
+            // Update the alias if the title has changed
+            let alias_name = alias_name(&title, &id, handler.created_name());
+            if alias_name.as_ref() != handler.alias_name() {
+                match (alias_name, handler.alias.take()) {
+                    // Rename: updates the existing alias by replacing the
+                    // old name and keeping the existing handler.
+                    (Some(new_name), Some((_, existing))) => {
+                        handler.alias = Some((new_name.clone(), existing));
+                    }
+                    // Create: there is no existing alias.
+                    (Some(alias_name), None) => {
+                        let attr = handler
+                            .node
+                            .node()
+                            .attr(fs)
+                            .expect("attr should be available");
+                        let target = handler.created_name().unwrap_or(&id);
+                        let alias_handler = fs.node_alloc(|ino| Node::new_link(ino, target, &attr));
+                        handler.alias = Some((alias_name, alias_handler));
+                    }
+                    // Delete: the alias name is no longer valid.
+                    // Will drop the alias as the handler is dropped.
+                    (None, Some(_)) => {}
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
 
+        for id in create {
+            let meta = secrets.remove(&id).expect("secret should be in list");
+            let handler = self.make_handler(fs, &id, meta, None);
+            entries.insert(id, handler);
+        }
+
+        for (id, secret) in prefetched {
+            if let Some(handler) = entries.get(&id) {
                 match handler.node.node().as_ref() {
-                    Node::Secret(secret) => secret.update_metadata(meta),
+                    Node::Secret(node) => node.prefetched(fs, secret),
                     _ => unreachable!("node should be a secret"),
                 }
-
-                // Update the alias if the title has changed
-                let alias_name = make_alias_name(&title);
-                if alias_name.as_ref() != handler.alias_name() {
-                    match (alias_name, handler.alias.take()) {
-                        // Rename: updates the existing alias by replacing the
-                        // old name and keeping the existing handler.
-                        (Some(new_name), Some((_, existing))) => {
-                            handler.alias = Some((new_name.clone(), existing));
-                        }
-                        // Create: there is no existing alias.
-                        (Some(alias_name), None) => {
-                            let attr = handler
-                                .node
-                                .node()
-                                .attr(fs)
-                                .expect("attr should be available");
-                            let alias_handler =
-                                fs.node_alloc(|ino| Node::new_link(ino, &id, &attr));
-                            handler.alias = Some((alias_name, alias_handler));
-                        }
-                        // Delete: the alias name is no longer valid.
-                        // Will drop the alias as the handler is dropped.
-                        (None, Some(_)) => {}
-                        (None, None) => unreachable!(),
-                    }
-                }
             }
+        }
 
-            for id in create {
-                let meta = secrets.remove(&id).expect("secret should be in list");
-                let title = meta.title.clone();
-
-                let secret_id = id::Secret::new(&self.id, &id);
-                let handler = fs.node_alloc(|ino| Node::new_secret(ino, secret_id, meta));
-
-                // Check if we can create an alias for the secret
-                let alias_handler =
-                    make_alias_name(&title)
-                        .filter(|name| *name != id)
-                        .map(|alias| {
-                            let attr = handler.node().attr(fs).expect("attr should be available");
-                            let handler = fs.node_alloc(|ino| Node::new_link(ino, &id, &attr));
-                            (alias, handler)
-                        });
-
-                entries.insert(
-                    id,
-                    SecretHandler {
-                        node: handler,
-                        alias: alias_handler,
-                    },
-                );
+        fs.invalidate_entries(self.ino, &before, &dir_entries(entries));
+    }
+
+    /// Updates the index directories from the listed secrets, if enabled.
+    fn update_indexes(&self, fs: &Fs, list: &[SecretMetadata]) {
+        if fs.config.indexes.vaults {
+            let indexed = list
+                .iter()
+                .map(|meta| (meta.id.clone(), meta.clone()))
+                .collect::<Vec<_>>();
+            self.indexes
+                .lock()
+                .get_or_insert_with(|| Indexes::new(fs, &self.attr(fs)))
+                .update(fs, &indexed);
+        }
+    }
+
+    /// Creates the handler of a secret of the vault, along with its alias if
+    /// its title differs from its ID.
+    ///
+    /// A secret created with another name than its ID is listed under that
+    /// name, with symlinks named after its ID and title.
+    fn make_handler(
+        &self,
+        fs: &Fs,
+        id: &str,
+        meta: SecretMetadata,
+        created: Option<&str>,
+    ) -> SecretHandler {
+        let title = meta.title.clone();
+
+        let secret_id = id::Secret::new(&self.id, id);
+        let handler = fs.node_alloc(|ino| Node::new_secret(ino, secret_id, meta));
+        let attr = handler.node().attr(fs).expect("attr should be available");
+
+        let created = created.filter(|name| *name != id).map(|name| {
+            let link = fs.node_alloc(|ino| Node::new_link(ino, name, &attr));
+            (name.to_string(), link)
+        });
+
+        // Check if we can create an alias for the secret
+        let target = created.as_ref().map_or(id, |(name, _)| name.as_str());
+        let alias_handler = alias_name(&title, id, Some(target)).map(|alias| {
+            let handler = fs.node_alloc(|ino| Node::new_link(ino, target, &attr));
+            (alias, handler)
+        });
+
+        SecretHandler {
+            node: handler,
+            alias: alias_handler,
+            created,
+        }
+    }
+
+    /// Creates an item in the vault, and returns the inode of its node.
+    ///
+    /// The directory of the item is listed under the given name, so that it
+    /// can be used right away. The category is the 1Password name of the
+    /// category, or `None` for the category or template selected by the
+    /// `.category` file.
+    pub fn create_item(
+        &self,
+        fs: &Fs,
+        name: &str,
+        title: &str,
+        category: Option<&str>,
+    ) -> Result<Inode> {
+        let (category, template) = match (category, &*self.category.read()) {
+            (Some(category), _) => (category.to_string(), None),
+            (None, Selection::Category(name)) => {
+                let category = category_id(name).expect("selected category should be known");
+                (category.to_string(), None)
+            }
+            (None, Selection::Template(template)) => {
+                let category = template["category"].as_str().unwrap_or_default();
+                (category.to_string(), Some(template.clone()))
             }
+        };
+        let secret = fs
+            .op
+            .create_item(&self.id, title, &category, template.as_ref())
+            .inspect_err(|err| warn!(err = %err, title, "Failed to create item"))?;
 
-            Ok(())
-        })?;
+        // The item is added to the listing and the index directories right
+        // away, rather than when the vault is next refreshed
+        let id = secret.metadata.id.clone();
+        let handler = self.make_handler(fs, &id, secret.metadata.clone(), Some(name));
+        match handler.node.node().as_ref() {
+            Node::Secret(node) => node.prefetched(fs, secret),
+            _ => unreachable!("node should be a secret"),
+        }
+        let ino = handler.node.ino();
+        self.entries.lock().modify(|entries| {
+            let before = dir_entries(entries);
+            entries.insert(id, handler);
+            self.update_indexes(fs, &secrets(entries));
+            fs.invalidate_entries(self.ino, &before, &dir_entries(entries));
+        });
+        Ok(ino)
+    }
 
-        Ok(entries
-            .iter()
-            .flat_map(|(name, handler)| {
-                let entry = DirEntry {
-                    inode: handler.node.ino(),
-                    name: name.clone(),
-                    file_type: FileType::Directory,
-                };
-                match &handler.alias {
-                    Some((alias, handler)) => vec![
-                        entry,
-                        DirEntry {
-                            inode: handler.ino(),
-                            name: alias.clone(),
-                            file_type: FileType::Symlink,
-                        },
-                    ],
-                    None => vec![entry],
-                }
-            })
-            .map(|entry| (entry.name.clone(), entry))
-            .collect::<HashMap<String, DirEntry>>() // Deduplicate entries
-            .into_values())
+    /// Deletes an item of the vault, or moves it to the archive as configured.
+    ///
+    /// The item is removed from the listing and the index directories right
+    /// away, rather than when the vault is next refreshed.
+    pub fn delete_item(&self, fs: &Fs, id: &str) -> Result<()> {
+        let secret = id::Secret::new(&self.id, id);
+        fs.op
+            .delete_item(&secret, fs.config.op.archive)
+            .inspect_err(|err| warn!(err = %err, item = id, "Failed to delete item"))?;
+
+        self.entries.lock().modify(|entries| {
+            let before = dir_entries(entries);
+            entries.remove(id);
+            self.update_indexes(fs, &secrets(entries));
+            fs.invalidate_entries(self.ino, &before, &dir_entries(entries));
+        });
+        Ok(())
     }
+
+    /// Returns the inode of the secret with the given ID, as of the last
+    /// listing.
+    pub fn secret(&self, id: &str) -> Option<Inode> {
+        self.entries
+            .lock()
+            .get(id)
+            .map(|handler| handler.node.ino())
+    }
+
+    /// Returns the metadata of the secrets of the vault, as of the last
+    /// listing.
+    pub fn secrets(&self) -> Vec<SecretMetadata> {
+        secrets(&self.entries.lock())
+    }
+
+    /// Returns whether the fields of the secrets should be fetched along with
+    /// the vault listing.
+    fn prefetch(&self, fs: &Fs) -> bool {
+        fs.config.prefetch(self.id.account(), self.id.vault())
+    }
+}
+
+/// Returns the metadata of the secrets of a vault.
+fn secrets(entries: &HashMap<String, SecretHandler>) -> Vec<SecretMetadata> {
+    entries
+        .values()
+        .map(|handler| match handler.node.node().as_ref() {
+            Node::Secret(secret) => secret.metadata(),
+            _ => unreachable!("node should be a secret"),
+        })
+        .collect()
+}
+
+/// Returns the name of the alias of a secret, unless its title is not a valid
+/// file name or is already the name of its directory.
+fn alias_name(title: &str, id: &str, created: Option<&str>) -> Option<String> {
+    make_name(title).filter(|name| name != id && Some(name.as_str()) != created)
+}
+
+/// Lists the secrets of a vault by ID, or by the name they were created with,
+/// and their aliases.
+fn dir_entries(entries: &HashMap<String, SecretHandler>) -> Vec<DirEntry> {
+    entries
+        .iter()
+        .flat_map(|(id, handler)| {
+            let directory = DirEntry {
+                inode: handler.node.ino(),
+                name: handler.created_name().unwrap_or(id).to_string(),
+                file_type: FileType::Directory,
+            };
+            let links = handler
+                .created
+                .iter()
+                .map(|(_, link)| (id, link))
+                .chain(handler.alias.iter().map(|(name, link)| (name, link)))
+                .map(|(name, link)| DirEntry {
+                    inode: link.ino(),
+                    name: name.clone(),
+                    file_type: FileType::Symlink,
+                });
+            std::iter::once(directory).chain(links).collect::<Vec<_>>()
+        })
+        .collect()
 }
 
 /// Creates the file attributes of a vault node.
@@ -195,7 +482,46 @@ fn make_attr(node: &Vault, fs: &Fs) -> FileAttr {
     }
 }
 
-/// Creates an alias name for the title.
-fn make_alias_name(title: &str) -> Option<String> {
-    Some(title.replace('/', "_")).filter(|alias| !alias.is_empty())
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, time::Duration};
+
+    use crate::fs::harness::{list, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn follows_deletions() {
+        let h = Harness::mount(FIXTURES);
+
+        assert!(h.path("personal/private/dbitem").is_dir());
+        h.fixtures().remove_item(&private_vault(), "dbitem");
+
+        assert_eq!(
+            list(&h.path("personal/private")),
+            [".by-category", ".by-tag", ".category", ".favorites"]
+        );
+        let err = fs::metadata(h.path("personal/private/dbitem")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn prefetches_vault_items() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+            let account = config.accounts.get_mut("personal").unwrap();
+            account.vaults.get_mut("private").unwrap().prefetch = Some(true);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(username).unwrap(), "admin");
+        let token = h.path("personal/private/Database/api_token");
+        assert_eq!(fs::read_to_string(token).unwrap(), "s3cr3t");
+        assert_eq!(list(&h.path("personal/private/dbitem")).len(), 6);
+
+        // The fields come with the listing of the vault, in a single call
+        assert_eq!(h.fixtures().calls("get_secrets"), 1);
+        assert_eq!(h.fixtures().calls("list_secrets"), 0);
+        assert_eq!(h.fixtures().calls("get_secret"), 0);
+    }
 }
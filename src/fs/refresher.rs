@@ -0,0 +1,237 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Condvar, PoisonError, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::util::Lock;
+
+use super::{node::Node, Fs, Inode};
+
+/// The interval at which the hot nodes are checked.
+const TICK: Duration = Duration::from_millis(250);
+
+/// A node to refresh, by inode and generation.
+///
+/// The generation tells the node apart from the nodes that reuse its inode
+/// once it is freed.
+type Key = (Inode, u64);
+
+/// The part of the cache duration during which hot nodes are refreshed before
+/// they expire, e.g. the last quarter.
+const AHEAD_DIVISOR: u32 = 4;
+
+/// Refreshes vault and secret nodes in the background.
+///
+/// Nodes serving stale data are queued to be refreshed as soon as possible.
+/// If background refresh is enabled, the nodes used recently are also
+/// refreshed shortly before they expire, so that they rarely do.
+#[derive(Default)]
+pub struct Refresher {
+    /// The last time each recently used node was used.
+    hot: Lock<HashMap<Key, Instant>>,
+
+    /// The nodes to refresh as soon as possible.
+    queue: Lock<HashSet<Key>>,
+
+    /// Wakes the refresher thread when a node is queued.
+    queued: Condvar,
+}
+
+impl Refresher {
+    /// Records that a node was used.
+    pub fn touch(&self, ino: Inode, generation: u64) {
+        self.hot.lock().insert((ino, generation), Instant::now());
+    }
+
+    /// Queues a node to be refreshed as soon as possible.
+    pub fn schedule(&self, ino: Inode, generation: u64) {
+        if self.queue.lock().insert((ino, generation)) {
+            self.queued.notify_one();
+        }
+    }
+
+    /// Waits for nodes to be queued, up to the given timeout, and returns
+    /// them.
+    fn wait(&self, timeout: Duration) -> Vec<Key> {
+        let mut queue = self.queue.lock();
+        if queue.is_empty() {
+            queue = self
+                .queued
+                .wait_timeout(queue, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        queue.drain().collect()
+    }
+
+    /// Returns the nodes used within the given duration, and forgets the
+    /// others.
+    fn hot(&self, duration: Duration) -> Vec<Key> {
+        let mut hot = self.hot.lock();
+        hot.retain(|_, used| used.elapsed() < duration);
+        hot.keys().copied().collect()
+    }
+}
+
+/// Starts the refresher thread of a filesystem, if it refreshes nodes in the
+/// background.
+///
+/// The thread stops once the filesystem is dropped.
+pub fn spawn(fs: &Arc<Fs>) {
+    if !fs.config.refresh.enabled() {
+        return;
+    }
+
+    let fs = Arc::downgrade(fs);
+    thread::Builder::new()
+        .name("op-fuse-refresh".to_string())
+        .spawn(move || run(&fs))
+        .expect("refresher thread should be spawned");
+}
+
+/// Runs the refresher until the filesystem is dropped.
+fn run(fs: &Weak<Fs>) {
+    while let Some(fs) = fs.upgrade() {
+        for key in fs.refresher.wait(TICK) {
+            revalidate(&fs, key, false);
+        }
+
+        let refresh = &fs.config.refresh;
+        if refresh.background {
+            for key in fs.refresher.hot(refresh.hot_duration) {
+                revalidate(&fs, key, true);
+            }
+        }
+    }
+}
+
+/// Refreshes a node if its cache has expired, or if `early` and it expires
+/// soon.
+///
+/// Nodes that are never cached are not refreshed early, as they would be
+/// refreshed continuously. Nodes that were freed are forgotten, even if
+/// their inode was reused since.
+fn revalidate(fs: &Fs, key: Key, early: bool) {
+    let ahead = |duration: Duration| {
+        if early {
+            Some(duration / AHEAD_DIVISOR).filter(|_| !duration.is_zero())
+        } else {
+            Some(Duration::ZERO)
+        }
+    };
+
+    let (ino, generation) = key;
+    let node = fs
+        .nodes
+        .resolve(ino)
+        .filter(|&(_, current)| current == generation)
+        .map(|(node, _)| node);
+    match node.as_deref() {
+        Some(Node::Vault(vault)) => {
+            if let Some(ahead) = ahead(vault.cache_duration(fs)) {
+                vault.revalidate(fs, ahead);
+            }
+        }
+        Some(Node::Secret(secret)) => {
+            if let Some(ahead) = ahead(secret.cache_duration(fs)) {
+                secret.revalidate(fs, ahead);
+            }
+        }
+        // The node was freed
+        _ => {
+            fs.refresher.hot.lock().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, fs};
+
+    use fuser::FUSE_ROOT_ID;
+
+    use super::*;
+    use crate::{
+        fs::{
+            harness::{database_item, private_vault, Harness, FIXTURES},
+            syscalls,
+        },
+        onepassword::{id, types, Fixtures},
+        Config,
+    };
+
+    #[test]
+    fn skips_nodes_that_reused_inodes() {
+        let op = Fixtures::from_json("{}").unwrap();
+        let item: types::Secret = serde_json::from_value(serde_json::json!({
+            "id": "database",
+            "title": "Database",
+            "version": 1,
+            "category": "DATABASE",
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "fields": [],
+        }))
+        .unwrap();
+        op.put_item(
+            &id::Vault::new(&id::Account::new("personal"), "private"),
+            item,
+        );
+        let config: Config = toml::from_str(
+            r#"
+            mountpoint = "/"
+            cache_duration = "60s"
+            [accounts.personal]
+            id = "personal"
+            [accounts.personal.vaults.private]
+            id = "private"
+            "#,
+        )
+        .unwrap();
+        let fs = Fs::new(&config, Box::new(op.clone()));
+
+        let account = syscalls::lookup(&fs, FUSE_ROOT_ID, OsStr::new("personal")).unwrap();
+        let vault = syscalls::lookup(&fs, account, OsStr::new("private")).unwrap();
+        let (_, generation) = fs.nodes.resolve(vault).unwrap();
+
+        // A node freed before the vault reused its inode is forgotten, without
+        // refreshing the vault in its place
+        let freed = (vault, generation.wrapping_sub(1));
+        fs.refresher.touch(freed.0, freed.1);
+        revalidate(&fs, freed, true);
+        assert_eq!(op.calls("list_secrets"), 0);
+        assert!(fs.refresher.hot(Duration::MAX).is_empty());
+
+        // The vault itself is refreshed
+        revalidate(&fs, (vault, generation), false);
+        assert_eq!(op.calls("list_secrets"), 1);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn refreshes_hot_secrets_in_background() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(1);
+            config.refresh.background = true;
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+
+        // The secret is refreshed before it expires, so reading it does not wait
+        // for the vault anymore
+        thread::sleep(Duration::from_millis(1200));
+        h.fixtures()
+            .delay_vault(&private_vault(), Some(Duration::from_secs(2)));
+        let start = Instant::now();
+        assert_eq!(fs::read_to_string(&username).unwrap(), "root");
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,8 +1,33 @@
+use std::sync::Arc;
+
+use crate::{onepassword::types::SecretVersion, util::Lock};
+
 use super::dentry::DirEntry;
 
 /// A slab item.
-#[derive(Debug)]
+#[derive(Clone)]
 pub enum Item {
+    /// Reserves the file handle 0, which is returned when opening a file for
+    /// reading only.
+    Reserved,
+
     /// Stores directories entries during readdir.
-    DirectoryEntries(u64, Vec<DirEntry>),
+    /// The entries are shared, so that they are read without locking the slab.
+    DirectoryEntries(u64, Arc<Vec<DirEntry>>),
+
+    /// Buffers the content written to a field until the file is flushed.
+    /// The buffer is shared, so that it is written without locking the slab.
+    FieldWrite(u64, Arc<Lock<WriteBuffer>>),
+}
+
+/// The content of a field opened for writing.
+pub struct WriteBuffer {
+    /// The content of the file, as read and written through the handle.
+    pub data: Vec<u8>,
+
+    /// The version of the secret the content applies to.
+    pub version: SecretVersion,
+
+    /// Whether the content was modified since it was last committed.
+    pub dirty: bool,
 }
@@ -1,15 +1,41 @@
+mod create;
 mod getattr;
 mod lookup;
+mod open;
 mod opendir;
 mod read;
+mod remove;
+mod write;
+mod xattr;
 
+pub use create::{create, mkdir, mknod};
 pub use getattr::getattr;
 pub use lookup::lookup;
+pub use open::open;
 pub use opendir::{opendir, readdir, releasedir};
 pub use read::{read, read_link};
+pub use remove::{rmdir, unlink};
+pub use write::{flush, release, setattr, write};
+pub use xattr::{getxattr, listxattr};
 
 pub type Result<T = ()> = std::result::Result<T, libc::c_int>;
 
+/// Maps a backend error to the errno reported to the calling process.
+///
+/// Errors that are not reported by a 1Password backend are mapped to `EIO`.
+pub fn errno(err: &anyhow::Error) -> libc::c_int {
+    use crate::onepassword::Error;
+    match err.downcast_ref::<Error>() {
+        Some(Error::NotSignedIn(_) | Error::AuthorizationDenied(_)) => libc::EACCES,
+        Some(Error::NotFound(_)) => libc::ENOENT,
+        Some(Error::RateLimited(_)) => libc::EAGAIN,
+        Some(Error::Timeout(_)) => libc::ETIMEDOUT,
+        Some(Error::Conflict(_)) => libc::ESTALE,
+        Some(Error::ReadOnly(_)) => libc::EROFS,
+        Some(Error::Other(_)) | None => libc::EIO,
+    }
+}
+
 mod prelude {
     #[allow(clippy::wildcard_imports)]
     pub use libc::*;
@@ -24,5 +50,60 @@ mod prelude {
     pub use crate::fs::Fs;
     pub use crate::fs::Inode;
 
-    pub use super::Result;
+    pub use super::{errno, Result};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::*;
+    use crate::{
+        fs::harness::{list, private_vault, Harness, FIXTURES},
+        onepassword::Error,
+    };
+
+    #[test]
+    fn maps_errors_to_errnos() {
+        let message = String::new;
+        let cases = [
+            (Error::NotSignedIn(message()), libc::EACCES),
+            (Error::AuthorizationDenied(message()), libc::EACCES),
+            (Error::NotFound(message()), libc::ENOENT),
+            (Error::RateLimited(message()), libc::EAGAIN),
+            (Error::Timeout(Duration::from_secs(1)), libc::ETIMEDOUT),
+            (Error::Conflict(message()), libc::ESTALE),
+            (Error::ReadOnly(message()), libc::EROFS),
+            (Error::Other(message()), libc::EIO),
+        ];
+
+        for (err, expected) in cases {
+            assert_eq!(errno(&err.clone().into()), expected, "{err}");
+        }
+        assert_eq!(errno(&anyhow::anyhow!("not a backend error")), libc::EIO);
+
+        // Context added on the way up does not hide the backend error
+        let err = anyhow::Error::from(Error::NotFound(message())).context("while reading");
+        assert_eq!(errno(&err), libc::ENOENT);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn reports_backend_failures() {
+        let h = Harness::mount(FIXTURES);
+
+        let vault = h.path("personal/private");
+        let denied = Error::AuthorizationDenied("denied".to_string());
+        h.fixtures().fail_vault(&private_vault(), Some(denied));
+        let err = fs::read_dir(&vault).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+
+        let limited = Error::RateLimited("slow down".to_string());
+        h.fixtures().fail_vault(&private_vault(), Some(limited));
+        let err = fs::read_dir(&vault).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+
+        h.fixtures().fail_vault(&private_vault(), None);
+        assert_eq!(list(&vault)[4..], ["Database", "dbitem"]);
+    }
 }
@@ -0,0 +1,213 @@
+use crate::fs::node::category;
+
+use super::{prelude::*, write::open_write};
+
+/// Implements the `mkdir` syscall.
+/// Creates an item in a vault and returns the inode of its directory.
+///
+/// The category of the item is selected by the name of the directory, e.g.
+/// `Staging.database`, or by the `.category` file of the vault. The directory
+/// is listed under the given name, with a symlink named after the item ID.
+pub fn mkdir(fs: &Fs, parent: Inode, name: &OsStr) -> Result<Inode> {
+    let name = name.to_str().ok_or(EINVAL)?;
+    match &*fs.node_get(parent) {
+        Node::Dummy => Err(ENOENT),
+        Node::Vault(node) => {
+            let (title, category) = category::split_name(name);
+            node.create_item(fs, name, title, category)
+                .map_err(|err| errno(&err))
+        }
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => Err(ENOTDIR),
+        _ => Err(EPERM),
+    }
+}
+
+/// Implements the `mknod` syscall.
+/// Adds an empty field to an item and returns the inode of its file.
+/// Only regular files can be created.
+pub fn mknod(fs: &Fs, parent: Inode, name: &OsStr, mode: u32) -> Result<Inode> {
+    if mode & S_IFMT != S_IFREG {
+        return Err(EPERM);
+    }
+    add_field(fs, parent, name)
+}
+
+/// Implements the `create` syscall.
+/// Adds an empty field to an item and opens its file, see [`open_write`].
+/// Returns the inode of the file and its file handle.
+pub fn create(fs: &Fs, parent: Inode, name: &OsStr, flags: i32) -> Result<(Inode, FileHandle)> {
+    let ino = add_field(fs, parent, name)?;
+    let fh = if flags & O_ACCMODE == O_RDONLY {
+        0
+    } else {
+        open_write(fs, ino, false)?
+    };
+    Ok((ino, fh))
+}
+
+/// Adds an empty field labeled after the given name to the item of a secret
+/// directory, and returns the inode of its file.
+fn add_field(fs: &Fs, parent: Inode, name: &OsStr) -> Result<Inode> {
+    let name = name.to_str().ok_or(EINVAL)?;
+    match &*fs.node_get(parent) {
+        Node::Dummy => Err(ENOENT),
+        Node::Secret(node) => node.add_field(fs, name).map_err(|err| errno(&err)),
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => Err(ENOTDIR),
+        _ => Err(EPERM),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{self, Write},
+        os::fd::IntoRawFd,
+        path::Path,
+        time::Duration,
+    };
+
+    use crate::fs::harness::{item, list, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn creates_items_and_fields() {
+        // The created items are listed without waiting for a refresh
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+        });
+
+        // The directory keeps the name it was created with
+        let vault = h.path("personal/private");
+        let item_dir = vault.join("Staging.database");
+        fs::create_dir(&item_dir).unwrap();
+        fs::write(item_dir.join("apikey"), "value\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(item_dir.join("apikey")).unwrap(),
+            "value"
+        );
+        let created = item(&h, "item");
+        assert_eq!(created.metadata.category, "DATABASE");
+        let field = &created.fields[0];
+        assert_eq!(
+            (field.id.as_str(), field.value.as_deref()),
+            ("apikey", Some("value"))
+        );
+
+        // The ID and the title of the item are symlinks to the directory
+        assert_eq!(
+            list(&vault),
+            [
+                ".by-category",
+                ".by-tag",
+                ".category",
+                ".favorites",
+                "Database",
+                "Staging",
+                "Staging.database",
+                "dbitem",
+                "item"
+            ]
+        );
+        assert!(fs::symlink_metadata(&item_dir).unwrap().is_dir());
+        let target = Path::new("Staging.database");
+        assert_eq!(fs::read_link(vault.join("item")).unwrap(), target);
+        assert_eq!(fs::read_link(vault.join("Staging")).unwrap(), target);
+        let indexed = vault.join(".by-category/database");
+        assert_eq!(list(&indexed), ["Staging"]);
+        assert_eq!(list(&indexed.join("Staging")), ["apikey"]);
+        assert_eq!(list(&vault.join("Staging")), ["apikey"]);
+
+        // Other items are created with the category selected for the vault
+        let category = vault.join(".category");
+        assert_eq!(fs::read_to_string(&category).unwrap(), "login\n");
+        fs::write(&category, "note\n").unwrap();
+        let notes = vault.join("Notes");
+        fs::create_dir(&notes).unwrap();
+        assert_eq!(item(&h, "item1").metadata.category, "SECURE_NOTE");
+        assert!(fs::symlink_metadata(&notes).unwrap().is_dir());
+        assert_eq!(
+            fs::read_link(vault.join("item1")).unwrap(),
+            Path::new("Notes")
+        );
+        fs::remove_dir(&notes).unwrap();
+        assert!(!notes.exists());
+
+        // Unknown categories are rejected when the file is closed
+        let mut file = fs::File::create(&category).unwrap();
+        file.write_all(b"unknown").unwrap();
+        // SAFETY: the file descriptor is owned and closed only once.
+        let closed = unsafe { libc::close(file.into_raw_fd()) };
+        assert_eq!(closed, -1);
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(fs::read_to_string(&category).unwrap(), "note\n");
+        let err = fs::create_dir(vault.join("dbitem/nested")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn creates_items_from_templates() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+        });
+
+        // Items are created from the template written to the `.category` file
+        let vault = h.path("personal/private");
+        let category = vault.join(".category");
+        let template = serde_json::json!({
+            "category": "DATABASE",
+            "fields": [
+                { "id": "username", "type": "STRING", "label": "username", "value": "admin" },
+                { "id": "password", "type": "CONCEALED", "label": "password", "value": "" },
+            ],
+        });
+        fs::write(&category, template.to_string()).unwrap();
+        let content = fs::read_to_string(&category).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&content).unwrap(),
+            template
+        );
+
+        let staging = vault.join("Staging");
+        fs::create_dir(&staging).unwrap();
+        assert_eq!(list(&staging), ["password", "username"]);
+        assert_eq!(
+            fs::read_to_string(staging.join("username")).unwrap(),
+            "admin"
+        );
+        let created = item(&h, "item");
+        assert_eq!(created.metadata.category, "DATABASE");
+
+        // A category suffix creates an empty item instead
+        fs::create_dir(vault.join("Notes.note")).unwrap();
+        let created = item(&h, "item1");
+        assert_eq!(created.metadata.category, "SECURE_NOTE");
+        assert!(created.fields.is_empty());
+
+        // Templates must be of a known category
+        let mut file = fs::File::create(&category).unwrap();
+        file.write_all(br#"{ "category": "UNKNOWN" }"#).unwrap();
+        // SAFETY: the file descriptor is owned and closed only once.
+        let closed = unsafe { libc::close(file.into_raw_fd()) };
+        assert_eq!(closed, -1);
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(fs::read_to_string(&category).unwrap(), content);
+    }
+}
@@ -11,11 +11,20 @@ pub fn lookup(fs: &Fs, parent: Inode, name: &OsStr) -> Result<Inode> {
 
     match &*fs.node_get(parent) {
         Node::Dummy => Err(ENOENT),
-        Node::Root(node) => scan_entries(name, node.entries(fs)),
-        Node::Account(node) => scan_entries(name, node.entries(fs)),
+        Node::Root(node) => try_scan_entries(name, node.entries(fs)),
+        Node::Account(node) => try_scan_entries(name, node.entries(fs)),
         Node::Vault(node) => try_scan_entries(name, node.entries(fs)),
         Node::Secret(node) => try_scan_entries(name, node.entries(fs)),
-        Node::Field(_) | Node::Link(_) => Err(ENOTDIR),
+        Node::Section(node) => scan_entries(name, node.entries()),
+        Node::Index(node) => scan_entries(name, node.entries()),
+        Node::Search(node) => node.lookup(fs, name).map_err(|err| errno(&err)),
+        Node::Reference(node) => node.lookup(fs, name).map_err(|err| errno(&err)),
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => Err(ENOTDIR),
     }
 }
 
@@ -32,10 +41,11 @@ where
         .ok_or(ENOENT)
 }
 
-fn try_scan_entries<I, E, Err>(name: &str, res: std::result::Result<I, Err>) -> Result<Inode>
+fn try_scan_entries<I, E>(name: &str, res: anyhow::Result<I>) -> Result<Inode>
 where
     I: Iterator<Item = E>,
     E: Borrow<DirEntry>,
 {
-    res.map_err(|_| EIO).and_then(|it| scan_entries(name, it))
+    res.map_err(|err| errno(&err))
+        .and_then(|it| scan_entries(name, it))
 }
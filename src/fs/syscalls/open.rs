@@ -0,0 +1,35 @@
+use fuser::consts::FOPEN_DIRECT_IO;
+
+use super::{prelude::*, write::open_write};
+
+/// Implements the `open` syscall.
+/// Opens a file and returns its file handle and the flags of the open file.
+///
+/// Files opened for reading only have no file handle. Fields can also be
+/// opened for writing, see [`open_write`].
+///
+/// One-time-password files are always opened in direct I/O mode, so that each
+/// read returns the current code.
+///
+/// Attachments are fetched when opened. If their actual size differs from the
+/// size reported by 1Password, they are opened in direct I/O mode so that the
+/// kernel does not truncate or pad them to the reported size.
+pub fn open(fs: &Fs, ino: Inode, flags: i32) -> Result<(FileHandle, u32)> {
+    if flags & O_ACCMODE != O_RDONLY {
+        return open_write(fs, ino, flags & O_TRUNC != 0).map(|fh| (fh, 0));
+    }
+
+    let flags = match &*fs.node_get(ino) {
+        Node::Dummy => Err(ENOENT),
+        Node::Field(_) | Node::Derived(_) | Node::Category(_) => Ok(0),
+        Node::Otp(_) => Ok(FOPEN_DIRECT_IO),
+        Node::Attachment(node) => match node.load(fs) {
+            Ok(true) => Ok(0),
+            Ok(false) => Ok(FOPEN_DIRECT_IO),
+            Err(err) => Err(errno(&err)),
+        },
+        Node::Link(_) => Err(EIO), // Symlinks are resolved by the kernel
+        _ => Err(EISDIR),
+    };
+    flags.map(|flags| (0, flags))
+}
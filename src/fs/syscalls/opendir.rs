@@ -1,61 +1,65 @@
+use std::sync::Arc;
+
 use super::prelude::*;
 
 /// Implements the `opendir` syscall.
 /// Opens a directory and returns a file handle to its entries.
 /// The complete entry list is stored with the file handle.
-pub fn opendir(fs: &mut Fs, ino: Inode) -> Result<FileHandle> {
+pub fn opendir(fs: &Fs, ino: Inode) -> Result<FileHandle> {
     let entries: Result<Vec<DirEntry>> = match &*fs.node_get(ino) {
         Node::Dummy => return Err(ENOENT),
-        Node::Root(node) => Ok(from_entries_ref(node.entries(fs))),
-        Node::Account(node) => Ok(from_entries_ref(node.entries(fs))),
+        Node::Root(node) => try_from_entries(node.entries(fs)),
+        Node::Account(node) => try_from_entries(node.entries(fs)),
         Node::Vault(node) => try_from_entries(node.entries(fs)),
         Node::Secret(node) => try_from_entries(node.entries(fs)),
-        Node::Field(_) | Node::Link(_) => return Err(ENOTDIR),
+        Node::Section(node) => Ok(node.entries().collect()),
+        Node::Index(node) => Ok(node.entries().collect()),
+        Node::Search(_) | Node::Reference(_) => Ok(Vec::new()),
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => return Err(ENOTDIR),
     };
 
-    entries.map(|entries| fs.slab_alloc(DirectoryEntries(ino, entries)))
-}
-
-fn from_entries_ref<'a, I>(entries: I) -> Vec<DirEntry>
-where
-    I: Iterator<Item = &'a DirEntry>,
-{
-    entries.map(DirEntry::clone).collect()
+    entries.map(|entries| fs.slab_alloc(DirectoryEntries(ino, Arc::new(entries))))
 }
 
-fn try_from_entries<I, E>(res: std::result::Result<I, E>) -> Result<Vec<DirEntry>>
+fn try_from_entries<I>(res: anyhow::Result<I>) -> Result<Vec<DirEntry>>
 where
     I: Iterator<Item = DirEntry>,
 {
-    res.map_err(|_| EIO).map(Iterator::collect)
+    res.map_err(|err| errno(&err)).map(Iterator::collect)
 }
 
 /// Implements the `readdir` syscall.
 /// Reads directory entries from a file handle.
 pub fn readdir(
-    fs: &mut Fs,
+    fs: &Fs,
     ino: Inode,
     fh: FileHandle,
     offset: i64,
-) -> Result<impl Iterator<Item = (i64, &DirEntry)>> {
+) -> Result<impl Iterator<Item = (i64, DirEntry)>> {
     let list = match fs.slab_get(fh) {
-        Some(DirectoryEntries(dl_ino, list)) if *dl_ino == ino => list,
+        Some(DirectoryEntries(dl_ino, list)) if dl_ino == ino => list,
         _ => return Err(EBADF),
     };
 
     let offset = usize::try_from(offset).expect("offset should be convertible to usize");
 
-    Ok(list[offset..].iter().enumerate().map(move |(i, entry)| {
-        let offset = i64::try_from(offset + i + 1).expect("offset should fit i64");
-        (offset, entry)
+    // The list is shared with the slab, entries are cloned as they are read
+    Ok((offset..list.len()).map(move |i| {
+        let offset = i64::try_from(i + 1).expect("offset should fit i64");
+        (offset, list[i].clone())
     }))
 }
 
 /// Implements the `releasedir` syscall.
 /// Closes a directory file handle and frees its resources.
-pub fn releasedir(fs: &mut Fs, ino: Inode, fh: FileHandle) -> Result {
+pub fn releasedir(fs: &Fs, ino: Inode, fh: FileHandle) -> Result {
     match fs.slab_get(fh) {
-        Some(DirectoryEntries(dl_ino, _)) if *dl_ino == ino => {
+        Some(DirectoryEntries(dl_ino, _)) if dl_ino == ino => {
             fs.slab_free(fh);
             Ok(())
         }
@@ -1,13 +1,27 @@
-use crate::fs::node::field::Field;
+use crate::fs::{
+    node::{attachment::Attachment, category::Category, derived::Derived, field::Field, otp::Otp},
+    slab::WriteBuffer,
+};
 
-use super::prelude::*;
+use super::{prelude::*, write::get_buffer};
 
 /// Implements the `read` syscall.
 /// Reads data from a file.
-pub fn read(fs: &Fs, ino: Inode, offset: i64, size: u32) -> Result<Vec<u8>> {
+///
+/// Files opened for writing are read from their write buffer, so that the
+/// content written to them is read back before it is flushed.
+pub fn read(fs: &Fs, ino: Inode, fh: FileHandle, offset: i64, size: u32) -> Result<Vec<u8>> {
+    if let Some(buffer) = get_buffer(fs, ino, fh) {
+        return read_buffer(&buffer.lock(), offset, size);
+    }
+
     match &*fs.node_get(ino) {
         Node::Dummy => Err(ENOENT),
         Node::Field(node) => read_field(node, offset, size),
+        Node::Otp(node) => read_otp(node, offset, size),
+        Node::Attachment(node) => read_attachment(fs, node, offset, size),
+        Node::Derived(node) => read_derived(node, offset, size),
+        Node::Category(node) => read_category(node, offset, size),
         Node::Link(_) => Err(EIO), // Should call `readlink` instead
         _ => Err(EISDIR),
     }
@@ -20,6 +34,48 @@ fn read_field(field: &Field, offset: i64, size: u32) -> Result<Vec<u8>> {
     ))
 }
 
+fn read_otp(otp: &Otp, offset: i64, size: u32) -> Result<Vec<u8>> {
+    otp.read(
+        usize::try_from(offset).map_err(|_| EINVAL)?,
+        usize::try_from(size).map_err(|_| EINVAL)?,
+    )
+    .ok_or(EIO)
+}
+
+fn read_attachment(fs: &Fs, attachment: &Attachment, offset: i64, size: u32) -> Result<Vec<u8>> {
+    attachment
+        .read(
+            fs,
+            usize::try_from(offset).map_err(|_| EINVAL)?,
+            usize::try_from(size).map_err(|_| EINVAL)?,
+        )
+        .map_err(|err| errno(&err))
+}
+
+fn read_derived(derived: &Derived, offset: i64, size: u32) -> Result<Vec<u8>> {
+    Ok(derived.read(
+        usize::try_from(offset).map_err(|_| EINVAL)?,
+        usize::try_from(size).map_err(|_| EINVAL)?,
+    ))
+}
+
+fn read_category(category: &Category, offset: i64, size: u32) -> Result<Vec<u8>> {
+    Ok(category.read(
+        usize::try_from(offset).map_err(|_| EINVAL)?,
+        usize::try_from(size).map_err(|_| EINVAL)?,
+    ))
+}
+
+fn read_buffer(buffer: &WriteBuffer, offset: i64, size: u32) -> Result<Vec<u8>> {
+    use std::cmp::min;
+
+    let offset = usize::try_from(offset).map_err(|_| EINVAL)?;
+    let size = usize::try_from(size).map_err(|_| EINVAL)?;
+    let start = min(buffer.data.len(), offset);
+    let end = min(buffer.data.len(), offset.saturating_add(size));
+    Ok(Vec::from(&buffer.data[start..end]))
+}
+
 /// Implements the `readlink` syscall.
 /// Reads the target of a symbolic link.
 pub fn read_link(fs: &Fs, ino: Inode) -> Result<String> {
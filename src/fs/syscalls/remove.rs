@@ -0,0 +1,126 @@
+use super::{lookup, prelude::*};
+
+/// Implements the `unlink` syscall.
+/// Removes a field from its item. Other files cannot be removed.
+pub fn unlink(fs: &Fs, parent: Inode, name: &OsStr) -> Result {
+    match &*fs.node_get(parent) {
+        Node::Dummy => return Err(ENOENT),
+        Node::Secret(_) | Node::Section(_) => {}
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => return Err(ENOTDIR),
+        _ => return Err(EPERM),
+    }
+
+    match &*fs.node_get(lookup(fs, parent, name)?) {
+        Node::Field(field) => {
+            let secret = fs.node_get(field.secret());
+            let Node::Secret(secret) = &*secret else {
+                return Err(ENOENT);
+            };
+            secret
+                .delete_field(fs, &field.id())
+                .map_err(|err| errno(&err))
+        }
+        Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => Err(EPERM),
+        _ => Err(EISDIR),
+    }
+}
+
+/// Implements the `rmdir` syscall.
+/// Deletes an item, or moves it to the archive as configured. The directory
+/// of the item does not need to be empty.
+pub fn rmdir(fs: &Fs, parent: Inode, name: &OsStr) -> Result {
+    let parent_node = fs.node_get(parent);
+    let vault = match &*parent_node {
+        Node::Dummy => return Err(ENOENT),
+        Node::Vault(vault) => vault,
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => return Err(ENOTDIR),
+        _ => return Err(EPERM),
+    };
+
+    // Items are listed by ID or by the name they were created with, their
+    // titles being symlinks
+    match &*fs.node_get(lookup(fs, parent, name)?) {
+        Node::Secret(secret) => {
+            let id = secret.metadata().id;
+            vault.delete_item(fs, &id).map_err(|err| errno(&err))
+        }
+        Node::Field(_)
+        | Node::Otp(_)
+        | Node::Attachment(_)
+        | Node::Derived(_)
+        | Node::Link(_)
+        | Node::Category(_) => Err(ENOTDIR),
+        _ => Err(EPERM),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use crate::fs::harness::{database_item, list, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn removes_fields_and_items() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+        });
+
+        let item = h.path("personal/private/dbitem");
+        fs::remove_file(item.join("username")).unwrap();
+        fs::remove_file(item.join("api/token")).unwrap();
+        let fields = database_item(&h).fields;
+        assert!(fields.iter().all(|f| f.id != "username" && f.id != "token"));
+        // Sections are removed along with their last field
+        assert!(!item.join("username").exists());
+        assert!(!item.join("api").exists());
+
+        // Only fields can be removed
+        let err = fs::remove_file(item.join("password")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+        let vault = h.path("personal/private");
+        let err = fs::remove_dir(vault.join("Database")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+
+        // The item is archived, and dropped without waiting for a refresh
+        fs::remove_dir(&item).unwrap();
+        assert_eq!(list(&vault)[4..], Vec::<String>::new());
+        assert!(fs::symlink_metadata(vault.join(".by-category/login/Database")).is_err());
+        let archived = h.fixtures().update(|set| {
+            let vault = set.vault_mut(&private_vault());
+            vault
+                .archived
+                .iter()
+                .map(|i| i.metadata.id.clone())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(archived, ["dbitem"]);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn deletes_items_permanently() {
+        let h = Harness::mount_with(FIXTURES, |config| config.op.archive = false);
+
+        fs::remove_dir(h.path("personal/private/dbitem")).unwrap();
+        let vault = h
+            .fixtures()
+            .update(|set| set.vault_mut(&private_vault()).clone());
+        assert!(vault.items.is_empty() && vault.archived.is_empty());
+    }
+}
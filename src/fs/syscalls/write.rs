@@ -0,0 +1,316 @@
+use std::sync::Arc;
+
+use crate::{
+    fs::{node::field::Field, slab::WriteBuffer},
+    util::Lock,
+};
+
+use super::{getattr, prelude::*};
+
+/// The maximum size of the content written to a field.
+const MAX_SIZE: usize = 1 << 20;
+
+/// Opens a field for writing and returns a file handle buffering its content.
+///
+/// The buffer starts with the current value, unless truncated, and keeps the
+/// version of the secret it was read from, so that the edit fails if the
+/// secret is modified elsewhere before the file is flushed. The `.category`
+/// file of vaults can be written too, other files are read-only.
+pub fn open_write(fs: &Fs, ino: Inode, truncate: bool) -> Result<FileHandle> {
+    let mut buffer = write_buffer(fs, ino)?;
+    if truncate {
+        buffer.data.clear();
+        buffer.dirty = true;
+    }
+    Ok(fs.slab_alloc(FieldWrite(ino, Arc::new(Lock::new(buffer)))))
+}
+
+/// Implements the `write` syscall.
+/// Writes data to the buffer of a field opened for writing.
+pub fn write(fs: &Fs, ino: Inode, fh: FileHandle, offset: i64, data: &[u8]) -> Result<u32> {
+    let buffer = get_buffer(fs, ino, fh).ok_or(EBADF)?;
+    let mut buffer = buffer.lock();
+
+    let start = usize::try_from(offset).map_err(|_| EINVAL)?;
+    let end = start.checked_add(data.len()).ok_or(EFBIG)?;
+    if end > MAX_SIZE {
+        return Err(EFBIG);
+    }
+    if buffer.data.len() < end {
+        buffer.data.resize(end, 0);
+    }
+    buffer.data[start..end].copy_from_slice(data);
+    buffer.dirty = true;
+
+    u32::try_from(data.len()).map_err(|_| EINVAL)
+}
+
+/// Implements the `setattr` syscall.
+/// Truncates a field, and returns its attributes.
+///
+/// Only the size of fields can be changed: other changes, e.g. of the
+/// modification time, are ignored. A field truncated without being open is
+/// edited immediately.
+pub fn setattr(fs: &Fs, ino: Inode, fh: Option<FileHandle>, size: Option<u64>) -> Result<FileAttr> {
+    let attr = getattr(fs, ino)?;
+    let Some(size) = size else {
+        return Ok(attr);
+    };
+    let len = usize::try_from(size).map_err(|_| EFBIG)?;
+    if len > MAX_SIZE {
+        return Err(EFBIG);
+    }
+
+    let truncate = |buffer: &mut WriteBuffer| {
+        buffer.data.resize(len, 0);
+        buffer.dirty = true;
+    };
+    match fh.and_then(|fh| get_buffer(fs, ino, fh)) {
+        Some(buffer) => truncate(&mut buffer.lock()),
+        None => {
+            let mut buffer = write_buffer(fs, ino)?;
+            truncate(&mut buffer);
+            commit(fs, ino, &mut buffer)?;
+        }
+    }
+    Ok(FileAttr { size, ..attr })
+}
+
+/// Implements the `flush` syscall.
+/// Commits the content written to a field, so that `close` reports failures.
+pub fn flush(fs: &Fs, ino: Inode, fh: FileHandle) -> Result {
+    match get_buffer(fs, ino, fh) {
+        Some(buffer) => commit(fs, ino, &mut buffer.lock()),
+        None => Ok(()),
+    }
+}
+
+/// Implements the `release` syscall.
+/// Commits the content written to a field if it was not flushed, and frees the
+/// file handle.
+pub fn release(fs: &Fs, ino: Inode, fh: FileHandle) -> Result {
+    let Some(buffer) = get_buffer(fs, ino, fh) else {
+        return Ok(());
+    };
+    fs.slab_free(fh);
+    let mut buffer = buffer.lock();
+    commit(fs, ino, &mut buffer)
+}
+
+/// Creates the write buffer of a field from its current value.
+fn write_buffer(fs: &Fs, ino: Inode) -> Result<WriteBuffer> {
+    match &*fs.node_get(ino) {
+        Node::Dummy => Err(ENOENT),
+        Node::Field(node) => {
+            let (data, version) = node.snapshot();
+            Ok(WriteBuffer {
+                data,
+                version,
+                dirty: false,
+            })
+        }
+        Node::Category(node) => Ok(WriteBuffer {
+            data: node.content(),
+            version: 0,
+            dirty: false,
+        }),
+        Node::Otp(_) | Node::Attachment(_) | Node::Derived(_) => Err(EACCES),
+        Node::Link(_) => Err(EIO), // Symlinks are resolved by the kernel
+        _ => Err(EISDIR),
+    }
+}
+
+/// Returns the write buffer of a file handle, if it is a field opened for
+/// writing.
+pub(super) fn get_buffer(fs: &Fs, ino: Inode, fh: FileHandle) -> Option<Arc<Lock<WriteBuffer>>> {
+    match fs.slab_get(fh) {
+        Some(FieldWrite(fw_ino, buffer)) if fw_ino == ino => Some(buffer),
+        _ => None,
+    }
+}
+
+/// Commits the content of a write buffer, if it was modified.
+fn commit(fs: &Fs, ino: Inode, buffer: &mut WriteBuffer) -> Result {
+    if !buffer.dirty {
+        return Ok(());
+    }
+
+    match &*fs.node_get(ino) {
+        Node::Field(field) => commit_field(fs, field, buffer)?,
+        Node::Category(category) if category.select(&buffer.data) => {}
+        Node::Category(_) => return Err(EINVAL),
+        _ => return Err(ENOENT),
+    }
+    buffer.dirty = false;
+    Ok(())
+}
+
+/// Edits a field with the content of its write buffer.
+///
+/// The buffer then applies to the new version of the secret, so that the file
+/// can be written and flushed again.
+fn commit_field(fs: &Fs, field: &Field, buffer: &mut WriteBuffer) -> Result {
+    let value = field.parse(&buffer.data).ok_or(EINVAL)?;
+
+    let secret = fs.node_get(field.secret());
+    let Node::Secret(secret) = &*secret else {
+        return Err(ENOENT);
+    };
+    buffer.version = secret
+        .edit_field(fs, &field.id(), buffer.version, &value)
+        .map_err(|err| errno(&err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{self, Read, Seek, Write},
+        os::fd::IntoRawFd,
+        time::Duration,
+    };
+
+    use crate::fs::harness::{database_item, list, private_vault, Harness, FIXTURES};
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn edits_fields() {
+        let h = Harness::mount(FIXTURES);
+
+        // Through the purpose symlink, without the trailing newline
+        fs::write(
+            h.path("personal/private/dbitem/password"),
+            "correct horse\n",
+        )
+        .unwrap();
+        let item = database_item(&h);
+        assert_eq!(item.metadata.version, 2);
+        assert_eq!(item.fields[1].value.as_deref(), Some("correct horse"));
+        let password = h.path("personal/private/dbitem/pwfield");
+        assert_eq!(fs::read_to_string(password).unwrap(), "correct horse");
+
+        let token = h.path("personal/private/dbitem/api/token");
+        let mut file = fs::OpenOptions::new().append(true).open(&token).unwrap();
+        file.write_all(b"!").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&token).unwrap(), "s3cr3t!");
+        assert_eq!(database_item(&h).metadata.version, 3);
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn reads_back_unflushed_writes() {
+        let h = Harness::mount(FIXTURES);
+
+        let username = h.path("personal/private/dbitem/username");
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&username)
+            .unwrap();
+        file.write_all(b"guest").unwrap();
+        file.seek(io::SeekFrom::Start(0)).unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "guest");
+        assert_eq!(database_item(&h).metadata.version, 1);
+
+        drop(file);
+        assert_eq!(database_item(&h).fields[0].value.as_deref(), Some("guest"));
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn rejects_conflicting_edits() {
+        let h = Harness::mount(FIXTURES);
+
+        let username = h.path("personal/private/dbitem/username");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&username)
+            .unwrap();
+
+        // The item is modified elsewhere before the file is closed
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+
+        file.write_all(b"guest").unwrap();
+        // SAFETY: the file descriptor is owned and closed only once.
+        let closed = unsafe { libc::close(file.into_raw_fd()) };
+        assert_eq!(closed, -1);
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error(),
+            Some(libc::ESTALE)
+        );
+
+        assert_eq!(database_item(&h).fields[0].value.as_deref(), Some("root"));
+        assert_eq!(fs::read_to_string(&username).unwrap(), "root");
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn rejects_edits_of_stale_fields() {
+        let h = Harness::mount_with(FIXTURES, |config| {
+            config.cache_duration = Duration::from_secs(60);
+            let account = config.accounts.get_mut("personal").unwrap();
+            account.cache.listing = Some(Duration::ZERO);
+        });
+
+        let username = h.path("personal/private/dbitem/username");
+        assert_eq!(fs::read_to_string(&username).unwrap(), "admin");
+
+        // The listing updates the version of the item, but not its cached fields
+        let mut item = database_item(&h);
+        item.metadata.version = 2;
+        item.fields[0].value = Some("root".to_string());
+        h.fixtures().put_item(&private_vault(), item);
+        list(&h.path("personal/private"));
+
+        let mut file = fs::OpenOptions::new().append(true).open(&username).unwrap();
+        file.write_all(b"!").unwrap();
+        // SAFETY: the file descriptor is owned and closed only once.
+        let closed = unsafe { libc::close(file.into_raw_fd()) };
+        assert_eq!(closed, -1);
+        assert_eq!(
+            io::Error::last_os_error().raw_os_error(),
+            Some(libc::ESTALE)
+        );
+        assert_eq!(database_item(&h).fields[0].value.as_deref(), Some("root"));
+    }
+
+    #[test]
+    #[ignore = "mounts a FUSE filesystem"]
+    fn edits_notes_as_displayed() {
+        let h = Harness::mount(FIXTURES);
+
+        let mut item = database_item(&h);
+        item.fields.push(
+            serde_json::from_value(serde_json::json!({
+                "id": "notesPlain",
+                "type": "STRING",
+                "purpose": "NOTES",
+                "label": "notesPlain",
+                "reference": "op://private/Database/notesPlain",
+                "value": "```sql\nSELECT 1;\n```",
+            }))
+            .unwrap(),
+        );
+        h.fixtures().put_item(&private_vault(), item);
+
+        let notes = h.path("personal/private/dbitem/notesPlain");
+        let content = fs::read_to_string(&notes).unwrap();
+        assert_eq!(content, "sql\nSELECT 1;\n```");
+        assert_eq!(fs::metadata(&notes).unwrap().len(), content.len() as u64);
+
+        fs::write(&notes, content.replace('1', "2")).unwrap();
+        let item = database_item(&h);
+        assert_eq!(
+            item.fields[3].value.as_deref(),
+            Some("```sql\nSELECT 2;\n```")
+        );
+    }
+}
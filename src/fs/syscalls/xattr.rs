@@ -0,0 +1,39 @@
+use super::prelude::*;
+
+/// Implements the `getxattr` syscall.
+/// Reads an extended attribute of the node with the given inode.
+pub fn getxattr(fs: &Fs, ino: Inode, name: &OsStr) -> Result<Vec<u8>> {
+    let name = name.to_str().ok_or(ENODATA)?;
+    xattrs(fs, ino)?
+        .into_iter()
+        .find(|(attr, _)| *attr == name)
+        .map(|(_, value)| value.into_bytes())
+        .ok_or(ENODATA)
+}
+
+/// Implements the `listxattr` syscall.
+/// Lists the extended attribute names of the node with the given inode, as a
+/// sequence of null-terminated strings.
+pub fn listxattr(fs: &Fs, ino: Inode) -> Result<Vec<u8>> {
+    let mut list = Vec::new();
+    for (name, _) in xattrs(fs, ino)? {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+    }
+    Ok(list)
+}
+
+/// Returns the extended attributes of a node.
+/// Field nodes describe their field, and the directories fetched from
+/// 1Password describe their last failed refresh, if any.
+fn xattrs(fs: &Fs, ino: Inode) -> Result<Vec<(&'static str, String)>> {
+    match &*fs.node_get(ino) {
+        Node::Dummy => Err(ENOENT),
+        Node::Field(node) => Ok(node.xattrs()),
+        Node::Root(node) => Ok(node.xattrs()),
+        Node::Account(node) => Ok(node.xattrs()),
+        Node::Vault(node) => Ok(node.xattrs()),
+        Node::Secret(node) => Ok(node.xattrs()),
+        _ => Ok(Vec::new()),
+    }
+}
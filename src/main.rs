@@ -5,12 +5,13 @@ extern crate serde;
 #[macro_use]
 extern crate tracing;
 
+mod agent;
 mod config;
 mod fs;
 mod onepassword;
 mod util;
 
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
 pub use config::Config;
 pub use onepassword::OnePassword;
@@ -43,10 +44,15 @@ fn main() -> Result<()> {
     let config = Config::read(Path::new(&cli.config))?;
     debug!(config = ?config);
 
-    let op = OnePassword::new(&config);
-    let filesystem = fs::Fs::new(&config, op);
+    let op = onepassword::new_backend(&config)?;
+    let filesystem = Arc::new(fs::Fs::new(&config, op));
 
-    fuser::mount2(
+    // The agent runs on its own threads, serving keys from the filesystem
+    if let Some(agent) = &config.ssh_agent {
+        agent::Agent::bind(&config, &agent.socket, Arc::clone(&filesystem))?.spawn();
+    }
+
+    let mounted = fs::mount(
         filesystem,
         &config.mountpoint,
         &[
@@ -58,7 +64,11 @@ fn main() -> Result<()> {
             },
             MountOption::DefaultPermissions,
         ],
-    )?;
+    );
+
+    if let Some(agent) = &config.ssh_agent {
+        let _ = std::fs::remove_file(&agent.socket);
+    }
 
-    Ok(())
+    Ok(mounted?)
 }
@@ -1,4 +1,9 @@
+mod backend;
+mod connect;
+mod error;
+mod fixtures;
 pub mod id;
+pub mod sshkey;
 pub mod types;
 
 use std::process::Command;
@@ -6,7 +11,34 @@ use std::process::Command;
 use anyhow::Result;
 use serde::de::DeserializeOwned;
 
-use crate::config::Config;
+use crate::{
+    config::{self, Config},
+    util::output_with_timeout,
+};
+
+pub use backend::Backend;
+pub use connect::Connect;
+pub use error::Error;
+pub use fixtures::Fixtures;
+
+/// Creates the backend selected in the given configuration
+pub fn new_backend(config: &Config) -> Result<Box<dyn Backend>> {
+    Ok(match config.op.backend {
+        config::Backend::Cli => Box::new(OnePassword::new(config)),
+        config::Backend::Connect => {
+            let Some(connect) = &config.op.connect else {
+                anyhow::bail!("the connect backend requires a [onepassword.connect] section");
+            };
+            Box::new(Connect::new(connect, config.op.timeout)?)
+        }
+        config::Backend::Fixtures => {
+            let Some(path) = &config.op.fixtures else {
+                anyhow::bail!("the fixtures backend requires the onepassword.fixtures option");
+            };
+            Box::new(Fixtures::read(path)?)
+        }
+    })
+}
 
 /// A client for the 1Password CLI
 #[derive(Debug)]
@@ -24,47 +56,429 @@ impl OnePassword {
     }
 
     /// Runs the 1Password CLI with the given arguments and returns the result
-    fn run<T>(&self, call_args: &[&str]) -> Result<T>
+    ///
+    /// The environment of the command is extended with the configuration of
+    /// the given account.
+    fn run<T>(&self, account: &str, call_args: &[&str]) -> Result<T>
     where
         T: DeserializeOwned,
     {
+        let stdout = self.exec(account, call_args, None)?;
+        Ok(serde_json::from_slice(&stdout)
+            .inspect_err(|e| error!(err = %e, "Failed to decode OP response"))?)
+    }
+
+    /// Runs the 1Password CLI with the given arguments and input, and returns
+    /// its raw JSON output
+    fn exec(&self, account: &str, call_args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
         let mut args: Vec<&str> = Vec::with_capacity(2 + call_args.len());
         args.extend(["--format", "json"]);
         args.extend(call_args);
+        self.call(account, &args, input)
+    }
 
+    /// Runs the 1Password CLI with the given arguments and input, and returns
+    /// its output as-is
+    fn call(&self, account: &str, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
         debug!(cmd = format!("op {:}", args.join(" ")));
 
-        let output = Command::new(&self.config.op.cmd)
-            .args(args)
-            .output()
-            .inspect_err(|e| error!(err = %e, "Failed to call OP"))?;
+        let mut cmd = Command::new(&self.config.op.cmd);
+        cmd.args(args);
+        self.apply_env(&mut cmd, account)?;
 
-        Ok(serde_json::from_slice(&output.stdout)
+        let timeout = self.config.op.timeout;
+        let Some(output) = output_with_timeout(&mut cmd, input, timeout)
+            .inspect_err(|e| error!(err = %e, "Failed to call OP"))?
+        else {
+            let err = Error::Timeout(timeout);
+            error!(err = %err, "OP call timed out");
+            return Err(err.into());
+        };
+
+        if !output.status.success() {
+            let err = Error::from_cli(
+                output.status.code(),
+                &String::from_utf8_lossy(&output.stderr),
+            );
+            error!(err = %err, "OP returned an error");
+            return Err(err.into());
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// Gets the given secret as a template to edit, if it is still at the given
+    /// version
+    ///
+    /// The CLI cannot make an edit conditional on the version of the item, so
+    /// the version is checked before editing it.
+    fn get_template(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+    ) -> Result<serde_json::Value> {
+        let template: serde_json::Value = self.run(
+            secret.account(),
+            &[
+                "item",
+                "get",
+                "--account",
+                secret.account(),
+                "--vault",
+                secret.vault(),
+                secret.secret(),
+            ],
+        )?;
+
+        let current = template["version"].as_u64().unwrap_or_default();
+        if current != u64::from(version) {
+            return Err(Error::Conflict(format!(
+                "item {} is at version {current}, not {version}",
+                secret.secret()
+            ))
+            .into());
+        }
+        Ok(template)
+    }
+
+    /// Replaces the given secret with an edited template, and returns the
+    /// updated secret
+    fn save_template(
+        &self,
+        secret: &id::Secret,
+        template: &serde_json::Value,
+    ) -> Result<types::Secret> {
+        let output = self.exec(
+            secret.account(),
+            &[
+                "item",
+                "edit",
+                "--template",
+                "/dev/stdin",
+                "--account",
+                secret.account(),
+                "--vault",
+                secret.vault(),
+                secret.secret(),
+            ],
+            Some(&serde_json::to_vec(template)?),
+        )?;
+        Ok(serde_json::from_slice(&output)
             .inspect_err(|e| error!(err = %e, "Failed to decode OP response"))?)
     }
 
+    /// Sets the environment variables configured for the given account
+    fn apply_env(&self, cmd: &mut Command, account: &str) -> Result<()> {
+        let Some(account) = self.config.account(account) else {
+            return Ok(());
+        };
+
+        if let Some(token) = &account.service_account_token {
+            cmd.env("OP_SERVICE_ACCOUNT_TOKEN", token.read()?);
+        }
+
+        if let Some(config_dir) = &account.config_dir {
+            cmd.env("OP_CONFIG_DIR", config_dir);
+        }
+
+        cmd.envs(&account.env);
+        Ok(())
+    }
+}
+
+impl Backend for OnePassword {
+    /// Lists the accounts the CLI is signed in to
+    fn list_accounts(&self) -> Result<Vec<types::AccountMetadata>> {
+        /// An account as returned by `op account list`
+        #[derive(Deserialize)]
+        struct ListedAccount {
+            account_uuid: String,
+        }
+
+        // `op account list` does not return the account names
+        let accounts: Vec<ListedAccount> = self.run("", &["account", "list"])?;
+        accounts
+            .iter()
+            .map(|account| {
+                self.run(
+                    &account.account_uuid,
+                    &["account", "get", "--account", &account.account_uuid],
+                )
+            })
+            .collect()
+    }
+
+    /// Lists the vaults in the given account
+    fn list_vaults(&self, account: &id::Account) -> Result<Vec<types::VaultMetadata>> {
+        self.run(
+            account.account(),
+            &["vault", "list", "--account", account.account()],
+        )
+    }
+
     /// Lists secrets in the given vault
-    pub fn list_secrets(&self, vault: &id::Vault) -> Result<Vec<types::SecretMetadata>> {
-        self.run(&[
+    fn list_secrets(&self, vault: &id::Vault) -> Result<Vec<types::SecretMetadata>> {
+        self.run(
+            vault.account(),
+            &[
+                "item",
+                "list",
+                "--account",
+                vault.account(),
+                "--vault",
+                vault.vault(),
+            ],
+        )
+    }
+
+    /// Gets the secret with the given ID
+    fn get_secret(&self, secret: &id::Secret) -> Result<types::Secret> {
+        self.run(
+            secret.account(),
+            &[
+                "item",
+                "get",
+                "--account",
+                secret.account(),
+                "--vault",
+                secret.vault(),
+                secret.secret(),
+            ],
+        )
+    }
+
+    /// Gets the content of a file attached to the given secret
+    fn get_file(&self, secret: &id::Secret, file: &str) -> Result<Vec<u8>> {
+        let reference = format!("op://{}/{}/{}", secret.vault(), secret.secret(), file);
+        self.call(
+            secret.account(),
+            &[
+                "read",
+                "--no-newline",
+                "--account",
+                secret.account(),
+                &reference,
+            ],
+            None,
+        )
+    }
+
+    /// Gets the content of the given Document item
+    fn get_document(&self, secret: &id::Secret) -> Result<Vec<u8>> {
+        self.call(
+            secret.account(),
+            &[
+                "document",
+                "get",
+                "--account",
+                secret.account(),
+                "--vault",
+                secret.vault(),
+                secret.secret(),
+            ],
+            None,
+        )
+    }
+
+    /// Sets the value of a field of the given secret
+    ///
+    /// The value is written in a template read from the standard input, which
+    /// keeps it out of the command line.
+    fn edit_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        field: &str,
+        value: &str,
+    ) -> Result<types::Secret> {
+        let mut template = self.get_template(secret, version)?;
+        let Some(target) = template["fields"]
+            .as_array_mut()
+            .and_then(|fields| fields.iter_mut().find(|f| f["id"] == field))
+        else {
+            return Err(Error::NotFound(format!("field {field}")).into());
+        };
+        target["value"] = value.into();
+
+        self.save_template(secret, &template)
+    }
+
+    /// Creates an item in the given vault
+    ///
+    /// A template is read from the standard input, and carries the category
+    /// of the item.
+    fn create_item(
+        &self,
+        vault: &id::Vault,
+        title: &str,
+        category: &str,
+        template: Option<&serde_json::Value>,
+    ) -> Result<types::Secret> {
+        let mut args = vec![
             "item",
-            "list",
+            "create",
             "--account",
             vault.account(),
             "--vault",
             vault.vault(),
-        ])
+            "--title",
+            title,
+        ];
+        let Some(template) = template else {
+            // The CLI expects category names, e.g. `secure note` for `SECURE_NOTE`
+            let category = category.replace('_', " ").to_lowercase();
+            args.extend(["--category", &category]);
+            return self.run(vault.account(), &args);
+        };
+
+        let mut template = template.clone();
+        template["category"] = category.into();
+        args.extend(["--template", "/dev/stdin"]);
+        let output = self.exec(
+            vault.account(),
+            &args,
+            Some(&serde_json::to_vec(&template)?),
+        )?;
+        Ok(serde_json::from_slice(&output)
+            .inspect_err(|e| error!(err = %e, "Failed to decode OP response"))?)
     }
 
-    /// Gets the secret with the given ID
-    pub fn get_secret(&self, secret: &id::Secret) -> Result<types::Secret> {
-        self.run(&[
+    /// Adds an empty concealed field to the given secret
+    fn add_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        label: &str,
+    ) -> Result<types::Secret> {
+        let mut template = self.get_template(secret, version)?;
+        let field = serde_json::json!({ "type": "CONCEALED", "label": label, "value": "" });
+        match template["fields"].as_array_mut() {
+            Some(fields) => fields.push(field),
+            None => template["fields"] = serde_json::json!([field]),
+        }
+
+        self.save_template(secret, &template)
+    }
+
+    /// Removes a field from the given secret
+    fn delete_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        field: &str,
+    ) -> Result<types::Secret> {
+        let mut template = self.get_template(secret, version)?;
+        let Some(fields) = template["fields"].as_array_mut() else {
+            return Err(Error::NotFound(format!("field {field}")).into());
+        };
+        let count = fields.len();
+        fields.retain(|f| f["id"] != field);
+        if fields.len() == count {
+            return Err(Error::NotFound(format!("field {field}")).into());
+        }
+
+        self.save_template(secret, &template)
+    }
+
+    /// Deletes the given secret, or moves it to the archive
+    fn delete_item(&self, secret: &id::Secret, archive: bool) -> Result<()> {
+        let mut args = vec![
             "item",
-            "get",
+            "delete",
             "--account",
             secret.account(),
             "--vault",
             secret.vault(),
-            secret.secret(),
-        ])
+        ];
+        if archive {
+            args.push("--archive");
+        }
+        args.push(secret.secret());
+
+        self.exec(secret.account(), &args, None)?;
+        Ok(())
+    }
+
+    /// Gets every secret in the given vault
+    ///
+    /// The item list is piped into a single `op item get -` call instead of
+    /// fetching each item separately.
+    fn get_secrets(&self, vault: &id::Vault) -> Result<Vec<types::Secret>> {
+        let list = self.exec(
+            vault.account(),
+            &[
+                "item",
+                "list",
+                "--account",
+                vault.account(),
+                "--vault",
+                vault.vault(),
+            ],
+            None,
+        )?;
+
+        // `op item get -` expects at least one item on its input
+        let items: Vec<types::SecretMetadata> = serde_json::from_slice(&list)
+            .inspect_err(|e| error!(err = %e, "Failed to decode OP response"))?;
+        if items.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let output = self.exec(
+            vault.account(),
+            &["item", "get", "-", "--account", vault.account()],
+            Some(&list),
+        )?;
+
+        // The items are returned as a sequence of JSON objects
+        Ok(serde_json::Deserializer::from_slice(&output)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .inspect_err(|e| error!(err = %e, "Failed to decode OP response"))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, time::Duration};
+
+    use super::*;
+
+    /// Returns a client running the given shell script instead of the CLI,
+    /// along with the directory holding the script
+    fn fake_cli(script: &str, timeout: Duration) -> (tempfile::TempDir, OnePassword) {
+        let dir = tempfile::tempdir().unwrap();
+        let op = dir.path().join("op");
+        fs::write(&op, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&op, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config: Config = toml::from_str(r#"mountpoint = "/mnt/op""#).unwrap();
+        config.op.cmd = op.to_string_lossy().into_owned();
+        config.op.timeout = timeout;
+        (dir, OnePassword::new(&config))
+    }
+
+    #[test]
+    fn decodes_cli_output() {
+        let (_dir, op) = fake_cli(
+            r#"echo '[{ "id": "VAULTID", "name": "Private" }]'"#,
+            Duration::from_secs(5),
+        );
+        let vaults = op.list_vaults(&id::Account::new("ACCOUNTID")).unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(
+            (vaults[0].id.as_str(), vaults[0].name.as_str()),
+            ("VAULTID", "Private")
+        );
+    }
+
+    #[test]
+    fn times_out_hung_calls() {
+        let (_dir, op) = fake_cli("sleep 30", Duration::from_millis(200));
+        let err = op.list_accounts().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(Error::Timeout(timeout)) if *timeout == Duration::from_millis(200)
+        ));
     }
 }
@@ -0,0 +1,195 @@
+use anyhow::Result;
+
+use super::{id, types, Error};
+
+/// A source of 1Password data.
+///
+/// The filesystem tree only talks to this trait, which allows it to be backed
+/// by the 1Password CLI or by any other source of secrets. Backends are shared
+/// by the threads serving the filesystem, and can be moved to other threads,
+/// e.g. to serve the SSH agent.
+pub trait Backend: Send + Sync {
+    /// Lists the accounts available to the backend
+    fn list_accounts(&self) -> Result<Vec<types::AccountMetadata>>;
+
+    /// Lists the vaults in the given account
+    fn list_vaults(&self, account: &id::Account) -> Result<Vec<types::VaultMetadata>>;
+
+    /// Lists secrets in the given vault
+    fn list_secrets(&self, vault: &id::Vault) -> Result<Vec<types::SecretMetadata>>;
+
+    /// Gets the secret with the given ID
+    fn get_secret(&self, secret: &id::Secret) -> Result<types::Secret>;
+
+    /// Gets the content of a file attached to the given secret
+    fn get_file(&self, secret: &id::Secret, file: &str) -> Result<Vec<u8>>;
+
+    /// Gets the content of the given Document item
+    fn get_document(&self, secret: &id::Secret) -> Result<Vec<u8>>;
+
+    /// Sets the value of a field of the given secret, and returns the updated
+    /// secret
+    ///
+    /// The edit fails with [`Error::Conflict`] if the secret is no longer at the
+    /// given version, so that concurrent edits are not overwritten. Backends
+    /// that cannot modify items fail with [`Error::ReadOnly`].
+    fn edit_field(
+        &self,
+        secret: &id::Secret,
+        _version: types::SecretVersion,
+        _field: &str,
+        _value: &str,
+    ) -> Result<types::Secret> {
+        Err(Error::ReadOnly(format!("cannot edit item {}", secret.secret())).into())
+    }
+
+    /// Creates an item of the given category in the given vault, and returns it
+    ///
+    /// The category is the 1Password name of the category, e.g. `LOGIN`. The
+    /// item is created from the given template if any, as output by
+    /// `op item template get`. Backends that cannot modify items fail with
+    /// [`Error::ReadOnly`].
+    fn create_item(
+        &self,
+        vault: &id::Vault,
+        title: &str,
+        _category: &str,
+        _template: Option<&serde_json::Value>,
+    ) -> Result<types::Secret> {
+        Err(Error::ReadOnly(format!(
+            "cannot create item {title} in vault {}",
+            vault.vault()
+        ))
+        .into())
+    }
+
+    /// Adds an empty concealed field with the given label to the given secret,
+    /// and returns the updated secret
+    ///
+    /// As when editing a field, the secret must still be at the given version.
+    fn add_field(
+        &self,
+        secret: &id::Secret,
+        _version: types::SecretVersion,
+        _label: &str,
+    ) -> Result<types::Secret> {
+        Err(Error::ReadOnly(format!("cannot edit item {}", secret.secret())).into())
+    }
+
+    /// Removes a field from the given secret, and returns the updated secret
+    ///
+    /// As when editing a field, the secret must still be at the given version.
+    fn delete_field(
+        &self,
+        secret: &id::Secret,
+        _version: types::SecretVersion,
+        _field: &str,
+    ) -> Result<types::Secret> {
+        Err(Error::ReadOnly(format!("cannot edit item {}", secret.secret())).into())
+    }
+
+    /// Deletes the given secret, or moves it to the archive if `archive` is set
+    ///
+    /// Backends that cannot modify items fail with [`Error::ReadOnly`].
+    fn delete_item(&self, secret: &id::Secret, _archive: bool) -> Result<()> {
+        Err(Error::ReadOnly(format!("cannot delete item {}", secret.secret())).into())
+    }
+
+    /// Gets every secret in the given vault, including their fields
+    ///
+    /// The default implementation lists the vault and gets each secret one by
+    /// one. Backends should override it if they can do better.
+    fn get_secrets(&self, vault: &id::Vault) -> Result<Vec<types::Secret>> {
+        self.list_secrets(vault)?
+            .into_iter()
+            .map(|meta| self.get_secret(&id::Secret::new(vault, &meta.id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onepassword::Fixtures;
+
+    /// A backend relying on the default implementations of the trait, reading
+    /// from fixtures
+    struct Minimal(Fixtures);
+
+    impl Backend for Minimal {
+        fn list_accounts(&self) -> Result<Vec<types::AccountMetadata>> {
+            self.0.list_accounts()
+        }
+
+        fn list_vaults(&self, account: &id::Account) -> Result<Vec<types::VaultMetadata>> {
+            self.0.list_vaults(account)
+        }
+
+        fn list_secrets(&self, vault: &id::Vault) -> Result<Vec<types::SecretMetadata>> {
+            self.0.list_secrets(vault)
+        }
+
+        fn get_secret(&self, secret: &id::Secret) -> Result<types::Secret> {
+            self.0.get_secret(secret)
+        }
+
+        fn get_file(&self, secret: &id::Secret, file: &str) -> Result<Vec<u8>> {
+            self.0.get_file(secret, file)
+        }
+
+        fn get_document(&self, secret: &id::Secret) -> Result<Vec<u8>> {
+            self.0.get_document(secret)
+        }
+    }
+
+    /// Returns a backend serving a vault with two empty items
+    fn backend() -> (Minimal, id::Vault) {
+        let fixtures = Fixtures::from_json(
+            r#"{ "accounts": { "personal": { "vaults": { "private": {} } } } }"#,
+        )
+        .unwrap();
+        let vault = id::Vault::new(&id::Account::new("personal"), "private");
+        fixtures
+            .create_item(&vault, "First", "LOGIN", None)
+            .unwrap();
+        fixtures
+            .create_item(&vault, "Second", "LOGIN", None)
+            .unwrap();
+        (Minimal(fixtures), vault)
+    }
+
+    #[test]
+    fn gets_secrets_one_by_one() {
+        let (backend, vault) = backend();
+        let mut titles = backend
+            .get_secrets(&vault)
+            .unwrap()
+            .into_iter()
+            .map(|secret| secret.metadata.title)
+            .collect::<Vec<_>>();
+        titles.sort();
+        assert_eq!(titles, ["First", "Second"]);
+    }
+
+    #[test]
+    fn is_read_only_by_default() {
+        let (backend, vault) = backend();
+        let secret = id::Secret::new(&vault, "item");
+        let read_only = |result: Result<()>| {
+            matches!(result.unwrap_err().downcast_ref(), Some(Error::ReadOnly(_)))
+        };
+
+        assert!(read_only(
+            backend.edit_field(&secret, 1, "f", "v").map(drop)
+        ));
+        assert!(read_only(backend.add_field(&secret, 1, "f").map(drop)));
+        assert!(read_only(backend.delete_field(&secret, 1, "f").map(drop)));
+        assert!(read_only(
+            backend
+                .create_item(&vault, "Third", "LOGIN", None)
+                .map(drop)
+        ));
+        assert!(read_only(backend.delete_item(&secret, true)));
+        assert_eq!(backend.list_secrets(&vault).unwrap().len(), 2);
+    }
+}
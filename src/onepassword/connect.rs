@@ -0,0 +1,621 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use time::OffsetDateTime;
+
+use crate::config;
+
+use super::{id, types, Backend, Error};
+
+/// A client for a 1Password Connect server
+#[derive(Debug)]
+pub struct Connect {
+    /// The base URL of the server, without trailing slash
+    host: String,
+
+    /// The bearer token sent with every request
+    token: String,
+
+    /// The HTTP agent
+    agent: ureq::Agent,
+
+    /// The timeout of a single request
+    timeout: Duration,
+}
+
+impl Connect {
+    /// Creates a new Connect client from the given configuration
+    ///
+    /// Requests taking longer than `timeout` are aborted.
+    pub fn new(config: &config::Connect, timeout: Duration) -> Result<Connect> {
+        Ok(Connect {
+            host: config.host.trim_end_matches('/').to_string(),
+            token: config.token.read()?,
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            timeout,
+        })
+    }
+
+    /// Sends a GET request to the Connect server and returns the result
+    fn get<T>(&self, path: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        decode(self.request("GET", path, None)?)
+    }
+
+    /// Sends a PUT request with the given JSON body to the Connect server and
+    /// returns the result
+    fn put<T>(&self, path: &str, body: &serde_json::Value) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        decode(self.request("PUT", path, Some(body))?)
+    }
+
+    /// Sends a GET request to the Connect server and returns the raw body
+    fn get_bytes(&self, path: &str) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.request("GET", path, None)?
+            .into_reader()
+            .read_to_end(&mut body)
+            .inspect_err(|e| error!(err = %e, "Failed to read Connect response"))?;
+        Ok(body)
+    }
+
+    /// Sends a request to the Connect server, with an optional JSON body
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<ureq::Response> {
+        debug!(method, url = format!("{}{}", self.host, path));
+
+        let request = self
+            .agent
+            .request(method, &format!("{}{}", self.host, path))
+            .set("Authorization", &format!("Bearer {}", self.token));
+        match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        }
+        .map_err(|err| match err {
+            ureq::Error::Status(status, response) => {
+                Error::from_http(status, &response.into_string().unwrap_or_default()).into()
+            }
+            ureq::Error::Transport(transport) if is_timeout(&transport) => {
+                Error::Timeout(self.timeout).into()
+            }
+            err => anyhow::Error::from(err),
+        })
+        .inspect_err(|e| error!(err = %e, "Failed to call Connect"))
+    }
+}
+
+impl Backend for Connect {
+    /// Lists the accounts available to the backend.
+    /// Connect servers are not tied to an account, so there are none.
+    fn list_accounts(&self) -> Result<Vec<types::AccountMetadata>> {
+        Ok(Vec::new())
+    }
+
+    /// Lists the vaults the Connect server has access to
+    fn list_vaults(&self, _account: &id::Account) -> Result<Vec<types::VaultMetadata>> {
+        self.get("/v1/vaults")
+    }
+
+    /// Lists secrets in the given vault
+    fn list_secrets(&self, vault: &id::Vault) -> Result<Vec<types::SecretMetadata>> {
+        let items: Vec<Item> = self.get(&format!("/v1/vaults/{}/items", vault.vault()))?;
+        Ok(items.into_iter().map(Item::into_metadata).collect())
+    }
+
+    /// Gets the secret with the given ID
+    fn get_secret(&self, secret: &id::Secret) -> Result<types::Secret> {
+        let item: Item = self.get(&format!(
+            "/v1/vaults/{}/items/{}",
+            secret.vault(),
+            secret.secret()
+        ))?;
+        Ok(item.into_secret(secret.vault()))
+    }
+
+    /// Gets the content of a file attached to the given secret
+    fn get_file(&self, secret: &id::Secret, file: &str) -> Result<Vec<u8>> {
+        self.get_bytes(&format!(
+            "/v1/vaults/{}/items/{}/files/{}/content",
+            secret.vault(),
+            secret.secret(),
+            file
+        ))
+    }
+
+    /// Gets the content of the given Document item.
+    /// Connect exposes the document as the only file of the item.
+    fn get_document(&self, secret: &id::Secret) -> Result<Vec<u8>> {
+        let files: Vec<types::SecretFile> = self.get(&format!(
+            "/v1/vaults/{}/items/{}/files",
+            secret.vault(),
+            secret.secret()
+        ))?;
+        let Some(file) = files.first() else {
+            return Err(Error::NotFound(format!("document {}", secret.secret())).into());
+        };
+        self.get_file(secret, &file.id)
+    }
+
+    /// Sets the value of a field of the given secret
+    ///
+    /// Connect replaces whole items, so the item is fetched, checked to still
+    /// be at the given version, and sent back with the new field value.
+    fn edit_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        field: &str,
+        value: &str,
+    ) -> Result<types::Secret> {
+        let path = format!("/v1/vaults/{}/items/{}", secret.vault(), secret.secret());
+        let mut item: serde_json::Value = self.get(&path)?;
+
+        let current = item["version"].as_u64().unwrap_or_default();
+        if current != u64::from(version) {
+            return Err(Error::Conflict(format!(
+                "item {} is at version {current}, not {version}",
+                secret.secret()
+            ))
+            .into());
+        }
+
+        let Some(target) = item["fields"]
+            .as_array_mut()
+            .and_then(|fields| fields.iter_mut().find(|f| f["id"] == field))
+        else {
+            return Err(Error::NotFound(format!("field {field}")).into());
+        };
+        target["value"] = value.into();
+
+        let item: Item = self.put(&path, &item)?;
+        Ok(item.into_secret(secret.vault()))
+    }
+}
+
+/// Decodes the JSON body of a Connect response
+fn decode<T>(response: ureq::Response) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    Ok(response
+        .into_json()
+        .inspect_err(|e| error!(err = %e, "Failed to decode Connect response"))?)
+}
+
+/// Returns whether a transport error was caused by the request timeout
+fn is_timeout(err: &ureq::Transport) -> bool {
+    use std::error::Error as _;
+    err.source()
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .is_some_and(|err| {
+            matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            )
+        })
+}
+
+/// An item as returned by the Connect API
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    id: String,
+    title: String,
+    version: types::SecretVersion,
+    category: String,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+    #[serde(default)]
+    fields: Vec<Field>,
+    #[serde(default)]
+    sections: Vec<types::Section>,
+    #[serde(default)]
+    files: Vec<types::SecretFile>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    favorite: bool,
+    #[serde(default)]
+    urls: Vec<types::SecretUrl>,
+}
+
+/// A field as returned by the Connect API
+#[derive(Debug, Deserialize)]
+struct Field {
+    id: String,
+    #[serde(rename = "type", default)]
+    kind: types::FieldType,
+    purpose: Option<types::FieldPurpose>,
+    #[serde(default)]
+    label: String,
+    value: Option<String>,
+    section: Option<types::SectionRef>,
+    entropy: Option<f64>,
+}
+
+impl Item {
+    /// Converts the item into secret metadata
+    fn into_metadata(self) -> types::SecretMetadata {
+        types::SecretMetadata {
+            id: self.id,
+            title: self.title,
+            version: self.version,
+            category: self.category,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            tags: self.tags,
+            favorite: self.favorite,
+            urls: self.urls,
+        }
+    }
+
+    /// Converts the item into a secret
+    ///
+    /// Connect does not return op://-references, so they are rebuilt from the
+    /// vault ID, the item title and the section and field labels.
+    fn into_secret(mut self, vault: &str) -> types::Secret {
+        let sections = self
+            .sections
+            .iter()
+            .map(|section| (section.id.clone(), section.label.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let fields = self
+            .fields
+            .drain(..)
+            .map(|field| {
+                // Connect only returns the section ID in the field
+                let section = field.section.map(|section| types::SectionRef {
+                    label: sections.get(&section.id).cloned(),
+                    id: section.id,
+                });
+
+                let reference = match section.as_ref().and_then(|s| s.label.as_deref()) {
+                    Some(label) if !label.is_empty() => {
+                        format!("op://{vault}/{}/{label}/{}", self.title, field.label)
+                    }
+                    _ => format!("op://{vault}/{}/{}", self.title, field.label),
+                };
+
+                types::SecretField {
+                    id: field.id,
+                    kind: field.kind,
+                    purpose: field.purpose,
+                    label: field.label,
+                    section,
+                    entropy: field.entropy,
+                    reference,
+                    value: field.value,
+                }
+            })
+            .collect();
+
+        let sections = std::mem::take(&mut self.sections);
+        let files = std::mem::take(&mut self.files);
+        types::Secret {
+            metadata: self.into_metadata(),
+            fields,
+            sections,
+            files,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::mpsc,
+        thread,
+    };
+
+    use super::*;
+
+    /// A request received by the mock server
+    #[derive(Debug)]
+    struct Request {
+        method: String,
+        path: String,
+        authorization: Option<String>,
+        body: String,
+    }
+
+    /// Starts a mock Connect server answering the given responses in order,
+    /// one per connection, and returns a client for it and the requests it
+    /// received
+    fn serve(responses: Vec<(u16, String)>) -> (Connect, mpsc::Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}/", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+
+                let mut authorization = None;
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let Some((name, value)) = header.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    match name.to_lowercase().as_str() {
+                        "authorization" => authorization = Some(value.to_string()),
+                        "content-length" => length = value.parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).unwrap();
+
+                let mut stream = reader.into_inner();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+
+                let _ = tx.send(Request {
+                    method,
+                    path,
+                    authorization,
+                    body: String::from_utf8(request_body).unwrap(),
+                });
+            }
+        });
+
+        let client = Connect {
+            host: host.trim_end_matches('/').to_string(),
+            token: "t0ken".to_string(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(5))
+                .build(),
+            timeout: Duration::from_secs(5),
+        };
+        (client, rx)
+    }
+
+    /// Returns a Connect item at the given version
+    fn item(version: u16) -> serde_json::Value {
+        serde_json::json!({
+            "id": "dbitem",
+            "title": "Database",
+            "version": version,
+            "category": "DATABASE",
+            "vault": { "id": "private" },
+            "createdAt": "2024-01-01T10:00:00Z",
+            "updatedAt": "2024-02-01T10:00:00.123Z",
+            "tags": ["prod"],
+            "sections": [{ "id": "srv", "label": "Server" }],
+            "fields": [
+                {
+                    "id": "username",
+                    "type": "STRING",
+                    "purpose": "USERNAME",
+                    "label": "username",
+                    "value": "admin"
+                },
+                {
+                    "id": "pw",
+                    "type": "CONCEALED",
+                    "label": "password",
+                    "value": "hunter2",
+                    "section": { "id": "srv" },
+                    "entropy": 42.5
+                },
+                { "id": "totp", "type": "OTP", "label": "one-time password", "section": { "id": "srv" } }
+            ],
+            "files": [{ "id": "f1", "name": "cert.pem", "size": 12 }],
+            "someFutureField": true
+        })
+    }
+
+    fn secret() -> id::Secret {
+        id::Secret::new(&id::Vault::new(&id::Account::new(""), "private"), "dbitem")
+    }
+
+    #[test]
+    fn decodes_vaults() {
+        let (client, requests) = serve(vec![(
+            200,
+            r#"[{ "id": "private", "name": "Private", "type": "USER_CREATED" }]"#.to_string(),
+        )]);
+
+        let vaults = client.list_vaults(&id::Account::new("")).unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(
+            (vaults[0].id.as_str(), vaults[0].name.as_str()),
+            ("private", "Private")
+        );
+
+        let request = requests.recv().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("GET", "/v1/vaults")
+        );
+        assert_eq!(request.authorization.as_deref(), Some("Bearer t0ken"));
+    }
+
+    #[test]
+    fn decodes_items() {
+        let (client, requests) = serve(vec![
+            (200, serde_json::json!([item(3)]).to_string()),
+            (200, item(3).to_string()),
+        ]);
+        let vault = id::Vault::new(&id::Account::new(""), "private");
+
+        let list = client.list_secrets(&vault).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!((list[0].id.as_str(), list[0].version), ("dbitem", 3));
+        assert_eq!(list[0].tags, ["prod"]);
+        assert_eq!(list[0].updated_at.unix_timestamp(), 1_706_781_600);
+
+        let secret = client.get_secret(&secret()).unwrap();
+        assert_eq!(secret.metadata.category, "DATABASE");
+        assert_eq!(secret.sections.len(), 1);
+        assert_eq!(secret.files[0].name, "cert.pem");
+        assert_eq!(secret.fields.len(), 3);
+
+        let username = &secret.fields[0];
+        assert_eq!(username.purpose, Some(types::FieldPurpose::Username));
+        assert!(username.section.is_none());
+        assert_eq!(username.reference, "op://private/Database/username");
+
+        let password = &secret.fields[1];
+        assert_eq!(password.kind, types::FieldType::Concealed);
+        assert_eq!(password.value.as_deref(), Some("hunter2"));
+        assert_eq!(password.entropy, Some(42.5));
+        let section = password.section.as_ref().unwrap();
+        assert_eq!(section.label.as_deref(), Some("Server"));
+        assert_eq!(password.reference, "op://private/Database/Server/password");
+
+        let totp = &secret.fields[2];
+        assert_eq!(totp.kind, types::FieldType::Otp);
+        assert!(totp.value.is_none());
+
+        let paths = requests.iter().map(|r| r.path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/v1/vaults/private/items",
+                "/v1/vaults/private/items/dbitem"
+            ]
+        );
+    }
+
+    #[test]
+    fn gets_files() {
+        let (client, requests) = serve(vec![
+            (200, r#"[{ "id": "doc", "name": "doc.txt" }]"#.to_string()),
+            (200, "document".to_string()),
+        ]);
+
+        assert_eq!(client.get_document(&secret()).unwrap(), b"document");
+        let paths = requests.iter().map(|r| r.path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/v1/vaults/private/items/dbitem/files",
+                "/v1/vaults/private/items/dbitem/files/doc/content"
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_http_errors() {
+        let cases = [
+            (401, "NotSignedIn"),
+            (403, "AuthorizationDenied"),
+            (404, "NotFound"),
+            (409, "Conflict"),
+            (412, "Conflict"),
+            (429, "RateLimited"),
+            (500, "Other"),
+        ];
+        let (client, _requests) = serve(
+            cases
+                .iter()
+                .map(|&(status, _)| (status, r#"{"status":0,"message":"nope"}"#.to_string()))
+                .collect(),
+        );
+
+        for (status, expected) in cases {
+            let err = client.get_secret(&secret()).unwrap_err();
+            let err = err.downcast_ref::<Error>().unwrap();
+            let kind = match err {
+                Error::NotSignedIn(_) => "NotSignedIn",
+                Error::AuthorizationDenied(_) => "AuthorizationDenied",
+                Error::NotFound(_) => "NotFound",
+                Error::Conflict(_) => "Conflict",
+                Error::RateLimited(_) => "RateLimited",
+                _ => "Other",
+            };
+            assert_eq!(kind, expected, "HTTP {status}: {err}");
+            assert!(err.to_string().contains(&format!("HTTP {status}")));
+        }
+    }
+
+    #[test]
+    fn edits_fields_at_their_version() {
+        let mut edited = item(4);
+        edited["fields"][1]["value"] = "correct horse".into();
+        let (client, requests) = serve(vec![(200, item(3).to_string()), (200, edited.to_string())]);
+
+        let secret = client
+            .edit_field(&secret(), 3, "pw", "correct horse")
+            .unwrap();
+        assert_eq!(secret.metadata.version, 4);
+        assert_eq!(secret.fields[1].value.as_deref(), Some("correct horse"));
+
+        let get = requests.recv().unwrap();
+        assert_eq!(get.method, "GET");
+        let put = requests.recv().unwrap();
+        assert_eq!(
+            (put.method.as_str(), put.path.as_str()),
+            ("PUT", "/v1/vaults/private/items/dbitem")
+        );
+        assert_eq!(put.authorization.as_deref(), Some("Bearer t0ken"));
+
+        // The whole item is sent back at the version it was read at, including
+        // attributes op-fuse does not know about
+        let body: serde_json::Value = serde_json::from_str(&put.body).unwrap();
+        let mut expected = item(3);
+        expected["fields"][1]["value"] = "correct horse".into();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn rejects_edits_of_stale_items() {
+        let (client, requests) = serve(vec![
+            (200, item(4).to_string()),
+            (200, item(3).to_string()),
+            (409, "{}".to_string()),
+        ]);
+
+        // Changed before the edit
+        let err = client.edit_field(&secret(), 3, "pw", "x").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Conflict(_))));
+
+        // Changed between the read and the write
+        let err = client.edit_field(&secret(), 3, "pw", "x").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Conflict(_))));
+
+        let methods = requests.iter().map(|r| r.method).collect::<Vec<_>>();
+        assert_eq!(methods, ["GET", "GET", "PUT"]);
+    }
+
+    #[test]
+    fn rejects_edits_of_unknown_fields() {
+        let (client, requests) = serve(vec![(200, item(3).to_string())]);
+        let err = client.edit_field(&secret(), 3, "nope", "x").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+        assert_eq!(requests.iter().count(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{fmt, time::Duration};
+
+/// An error reported by a 1Password backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The backend is not signed in to the account.
+    NotSignedIn(String),
+
+    /// The authorization was denied, or the prompt was dismissed.
+    AuthorizationDenied(String),
+
+    /// The requested account, vault or item does not exist.
+    NotFound(String),
+
+    /// Too many requests were made to 1Password.
+    RateLimited(String),
+
+    /// The call did not complete in the configured time.
+    Timeout(Duration),
+
+    /// The item was modified since the version an edit applies to.
+    Conflict(String),
+
+    /// The backend cannot modify items.
+    ReadOnly(String),
+
+    /// Any other failure.
+    Other(String),
+}
+
+impl Error {
+    /// Parses an error from the exit code and error output of the 1Password CLI.
+    pub fn from_cli(code: Option<i32>, stderr: &str) -> Error {
+        let message = cli_message(stderr);
+        let lower = message.to_lowercase();
+
+        if lower.contains("not currently signed in")
+            || lower.contains("not signed in")
+            || lower.contains("no accounts configured")
+            || lower.contains("session expired")
+        {
+            Error::NotSignedIn(message)
+        } else if lower.contains("authorization")
+            || lower.contains("unauthorized")
+            || lower.contains("forbidden")
+            || lower.contains("permission")
+        {
+            Error::AuthorizationDenied(message)
+        } else if lower.contains("isn't an item")
+            || lower.contains("isn't a vault")
+            || lower.contains("not found")
+            || lower.contains("no account found")
+        {
+            Error::NotFound(message)
+        } else if lower.contains("rate limit") || lower.contains("too many requests") {
+            Error::RateLimited(message)
+        } else if lower.contains("(409)") || lower.contains("(412)") || lower.contains("conflict") {
+            Error::Conflict(message)
+        } else {
+            match code {
+                Some(code) => Error::Other(format!("exit code {code}: {message}")),
+                None => Error::Other(format!("terminated by signal: {message}")),
+            }
+        }
+    }
+
+    /// Creates an error from an HTTP status code and its response body.
+    pub fn from_http(status: u16, body: &str) -> Error {
+        let message = format!("HTTP {status}: {}", body.trim());
+        match status {
+            401 => Error::NotSignedIn(message),
+            403 => Error::AuthorizationDenied(message),
+            404 => Error::NotFound(message),
+            409 | 412 => Error::Conflict(message),
+            429 => Error::RateLimited(message),
+            _ => Error::Other(message),
+        }
+    }
+}
+
+/// Extracts the error message from the 1Password CLI output.
+///
+/// The CLI prefixes its errors with `[ERROR] <date> <time> `, which is
+/// stripped to keep the message readable.
+fn cli_message(stderr: &str) -> String {
+    let line = stderr
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .unwrap_or_default();
+
+    match line.strip_prefix("[ERROR] ") {
+        Some(rest) => rest.splitn(3, ' ').nth(2).unwrap_or(rest).to_string(),
+        None => line.to_string(),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotSignedIn(msg) => write!(f, "not signed in: {msg}"),
+            Error::AuthorizationDenied(msg) => write!(f, "authorization denied: {msg}"),
+            Error::NotFound(msg) => write!(f, "not found: {msg}"),
+            Error::RateLimited(msg) => write!(f, "rate limited: {msg}"),
+            Error::Timeout(timeout) => write!(f, "timed out after {timeout:?}"),
+            Error::Conflict(msg) => write!(f, "conflict: {msg}"),
+            Error::ReadOnly(msg) => write!(f, "read-only: {msg}"),
+            Error::Other(msg) => write!(f, "1Password error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cli_errors() {
+        type Kind = fn(String) -> Error;
+        let cases: [(&str, Kind); 7] = [
+            (
+                "You are not currently signed in. Please run `op signin --help` for instructions",
+                Error::NotSignedIn,
+            ),
+            ("account is not signed in", Error::NotSignedIn),
+            (
+                "authorization prompt dismissed, please try again",
+                Error::AuthorizationDenied,
+            ),
+            (
+                "\"nope\" isn't an item. Specify the item with its UUID, name, or domain.",
+                Error::NotFound,
+            ),
+            (
+                "\"nope\" isn't a vault in this account. Specify the vault with its ID or name.",
+                Error::NotFound,
+            ),
+            (
+                "(429) Too Many Requests: You've reached the maximum number of requests",
+                Error::RateLimited,
+            ),
+            ("(409) Conflict: Internal server conflict", Error::Conflict),
+        ];
+
+        for (message, expected) in cases {
+            let stderr = format!("[ERROR] 2024/03/01 10:00:00 {message}\n");
+            assert_eq!(
+                Error::from_cli(Some(1), &stderr),
+                expected(message.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_cli_errors() {
+        let stderr = "some warning\n[ERROR] 2024/03/01 10:00:00 something broke\n\n";
+        assert_eq!(
+            Error::from_cli(Some(1), stderr),
+            Error::Other("exit code 1: something broke".to_string())
+        );
+        assert_eq!(
+            Error::from_cli(None, "killed"),
+            Error::Other("terminated by signal: killed".to_string())
+        );
+    }
+
+    #[test]
+    fn maps_http_statuses() {
+        let message = |status| format!("HTTP {status}: {{\"message\":\"nope\"}}");
+        let cases = [
+            (401, Error::NotSignedIn(message(401))),
+            (403, Error::AuthorizationDenied(message(403))),
+            (404, Error::NotFound(message(404))),
+            (409, Error::Conflict(message(409))),
+            (412, Error::Conflict(message(412))),
+            (429, Error::RateLimited(message(429))),
+            (400, Error::Other(message(400))),
+            (500, Error::Other(message(500))),
+        ];
+
+        for (status, expected) in cases {
+            assert_eq!(
+                Error::from_http(status, " {\"message\":\"nope\"}\n"),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use time::OffsetDateTime;
+
+use super::{id, types, Backend, Error};
+
+/// A backend serving secrets from in-memory fixtures
+///
+/// The fixtures are seeded from JSON and can be mutated while the filesystem
+/// is mounted to simulate edits, deletions and failures. When read from a
+/// file, the fixtures are reloaded whenever the file is modified.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// The file the fixtures were read from, and its last modification time
+    source: Option<(PathBuf, SystemTime)>,
+
+    /// The current fixtures
+    set: FixtureSet,
+
+    /// The number of calls made to each backend method
+    calls: HashMap<&'static str, usize>,
+}
+
+/// The content of a fixture file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureSet {
+    /// The accounts, by account ID
+    #[serde(default)]
+    pub accounts: HashMap<String, FixtureAccount>,
+
+    /// An error returned when listing accounts and vaults instead of them
+    #[serde(skip)]
+    pub error: Option<Error>,
+}
+
+/// A fixture account
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureAccount {
+    /// The vaults of the account, by vault ID
+    #[serde(default)]
+    pub vaults: HashMap<String, FixtureVault>,
+}
+
+/// A fixture vault
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureVault {
+    /// The name of the vault, its ID if not set
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The items of the vault
+    #[serde(default)]
+    pub items: Vec<types::Secret>,
+
+    /// The archived items of the vault, which are not listed
+    #[serde(default)]
+    pub archived: Vec<types::Secret>,
+
+    /// The content of the attached files by file ID, and of the Document
+    /// items by item ID
+    #[serde(default)]
+    pub contents: HashMap<String, String>,
+
+    /// An error returned by every call on the vault instead of its content
+    #[serde(skip)]
+    pub error: Option<Error>,
+
+    /// A delay before every call on the vault returns
+    #[serde(skip)]
+    pub delay: Option<Duration>,
+}
+
+impl Fixtures {
+    /// Creates fixtures from the given set
+    #[cfg(test)]
+    pub fn new(set: FixtureSet) -> Fixtures {
+        Fixtures {
+            inner: Arc::new(Mutex::new(Inner {
+                source: None,
+                set,
+                calls: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Parses fixtures from a JSON document
+    #[cfg(test)]
+    pub fn from_json(json: &str) -> Result<Fixtures> {
+        Ok(Fixtures::new(serde_json::from_str(json)?))
+    }
+
+    /// Reads fixtures from a JSON file
+    pub fn read(path: &Path) -> Result<Fixtures> {
+        let (set, mtime) = read_file(path)?;
+        Ok(Fixtures {
+            inner: Arc::new(Mutex::new(Inner {
+                source: Some((path.to_path_buf(), mtime)),
+                set,
+                calls: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Mutates the fixtures
+    pub fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut FixtureSet) -> R,
+    {
+        f(&mut self.lock().set)
+    }
+
+    /// Inserts or replaces an item in the given vault
+    #[cfg(test)]
+    pub fn put_item(&self, vault: &id::Vault, item: types::Secret) {
+        self.update(|set| {
+            let items = &mut set.vault_mut(vault).items;
+            match items.iter_mut().find(|i| i.metadata.id == item.metadata.id) {
+                Some(existing) => *existing = item,
+                None => items.push(item),
+            }
+        });
+    }
+
+    /// Removes an item from the given vault
+    #[cfg(test)]
+    pub fn remove_item(&self, vault: &id::Vault, item: &str) {
+        self.update(|set| {
+            set.vault_mut(vault).items.retain(|i| i.metadata.id != item);
+        });
+    }
+
+    /// Makes every call on the given vault fail with the given error, or
+    /// succeed again if `None`
+    #[cfg(test)]
+    pub fn fail_vault(&self, vault: &id::Vault, error: Option<Error>) {
+        self.update(|set| set.vault_mut(vault).error = error);
+    }
+
+    /// Makes the listings of accounts and vaults fail with the given error, or
+    /// succeed again if `None`
+    #[cfg(test)]
+    pub fn fail_listings(&self, error: Option<Error>) {
+        self.update(|set| set.error = error);
+    }
+
+    /// Makes every call on the given vault wait for the given delay, or
+    /// return immediately again if `None`
+    #[cfg(test)]
+    pub fn delay_vault(&self, vault: &id::Vault, delay: Option<Duration>) {
+        self.update(|set| set.vault_mut(vault).delay = delay);
+    }
+
+    /// Returns the number of calls made to the given backend method
+    #[cfg(test)]
+    pub fn calls(&self, method: &str) -> usize {
+        self.lock().calls.get(method).copied().unwrap_or_default()
+    }
+
+    /// Records a call to the given backend method
+    fn record(&self, method: &'static str) {
+        *self.lock().calls.entry(method).or_default() += 1;
+    }
+
+    /// Locks the fixtures, reloading them first if their file was modified
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        let mut inner = self
+            .inner
+            .lock()
+            .expect("fixtures lock should not be poisoned");
+        if let Some((path, mtime)) = &inner.source {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            if modified.is_some_and(|modified| modified != *mtime) {
+                match read_file(path) {
+                    Ok((set, mtime)) => {
+                        debug!(path = %path.display(), "reloading fixtures");
+                        inner.source = Some((path.clone(), mtime));
+                        inner.set = set;
+                    }
+                    Err(err) => error!(err = %err, "Failed to reload fixtures"),
+                }
+            }
+        }
+        inner
+    }
+
+    /// Runs the given closure on the vault with the given ID
+    fn with_vault<F, R>(&self, vault: &id::Vault, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut FixtureVault) -> Result<R>,
+    {
+        // The fixtures are not locked while waiting, to serve other calls
+        let delay = self.update(|set| {
+            let account = set.accounts.get(vault.account())?;
+            account.vaults.get(vault.vault())?.delay
+        });
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+
+        let mut inner = self.lock();
+        let vault = inner
+            .set
+            .accounts
+            .get_mut(vault.account())
+            .and_then(|account| account.vaults.get_mut(vault.vault()))
+            .ok_or_else(|| Error::NotFound(format!("vault {}", vault.vault())))?;
+
+        match &vault.error {
+            Some(err) => Err(err.clone().into()),
+            None => f(vault),
+        }
+    }
+
+    /// Edits the given secret if it is still at the given version, and bumps
+    /// its version
+    ///
+    /// Edits are kept in memory, and lost if the fixtures are reloaded.
+    fn edit_item<F>(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        edit: F,
+    ) -> Result<types::Secret>
+    where
+        F: FnOnce(&mut types::Secret) -> Result<()>,
+    {
+        let vault = id::Vault::new(&id::Account::new(secret.account()), secret.vault());
+        self.with_vault(&vault, |vault| {
+            let item = vault
+                .items
+                .iter_mut()
+                .find(|i| i.metadata.id == secret.secret())
+                .ok_or_else(|| Error::NotFound(format!("item {}", secret.secret())))?;
+            if item.metadata.version != version {
+                return Err(Error::Conflict(format!(
+                    "item {} is at version {}, not {version}",
+                    secret.secret(),
+                    item.metadata.version
+                ))
+                .into());
+            }
+
+            edit(item)?;
+            item.metadata.version += 1;
+            item.metadata.updated_at = OffsetDateTime::now_utc();
+            Ok(item.clone())
+        })
+    }
+
+    /// Returns the content with the given key in the vault of a secret
+    fn with_content(&self, secret: &id::Secret, key: &str) -> Result<Vec<u8>> {
+        let vault = id::Vault::new(&id::Account::new(secret.account()), secret.vault());
+        self.with_vault(&vault, |vault| {
+            vault
+                .contents
+                .get(key)
+                .map(|content| content.clone().into_bytes())
+                .ok_or_else(|| Error::NotFound(format!("file {key}")).into())
+        })
+    }
+}
+
+impl FixtureSet {
+    /// Returns the vault with the given ID, creating it if needed
+    #[cfg(test)]
+    pub fn vault_mut(&mut self, vault: &id::Vault) -> &mut FixtureVault {
+        self.accounts
+            .entry(vault.account().to_string())
+            .or_default()
+            .vaults
+            .entry(vault.vault().to_string())
+            .or_default()
+    }
+}
+
+/// Returns the given ID, or the first ID made of it and a number, that is not
+/// among the existing IDs
+fn unique_id<'a, I>(existing: I, id: &str) -> String
+where
+    I: Iterator<Item = &'a String> + Clone,
+{
+    let taken = |candidate: &String| existing.clone().any(|id| id == candidate);
+    std::iter::once(id.to_string())
+        .chain((1..).map(|n| format!("{id}{n}")))
+        .find(|candidate| !taken(candidate))
+        .expect("an ID should be available")
+}
+
+/// Reads a fixture file and returns its content and modification time
+fn read_file(path: &Path) -> Result<(FixtureSet, SystemTime)> {
+    let context = || format!("failed to read fixtures {}", path.display());
+    let mtime = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .with_context(context)?;
+    let set = serde_json::from_str(&fs::read_to_string(path).with_context(context)?)
+        .with_context(context)?;
+    Ok((set, mtime))
+}
+
+impl Backend for Fixtures {
+    /// Lists the accounts of the fixtures, named after their IDs
+    fn list_accounts(&self) -> Result<Vec<types::AccountMetadata>> {
+        self.record("list_accounts");
+        let inner = self.lock();
+        if let Some(err) = &inner.set.error {
+            return Err(err.clone().into());
+        }
+        Ok(inner
+            .set
+            .accounts
+            .keys()
+            .map(|id| types::AccountMetadata {
+                id: id.clone(),
+                name: id.clone(),
+            })
+            .collect())
+    }
+
+    /// Lists the vaults of the given account, named after their IDs
+    fn list_vaults(&self, account: &id::Account) -> Result<Vec<types::VaultMetadata>> {
+        self.record("list_vaults");
+        let inner = self.lock();
+        if let Some(err) = &inner.set.error {
+            return Err(err.clone().into());
+        }
+        let account = inner
+            .set
+            .accounts
+            .get(account.account())
+            .ok_or_else(|| Error::NotFound(format!("account {}", account.account())))?;
+        Ok(account
+            .vaults
+            .iter()
+            .map(|(id, vault)| types::VaultMetadata {
+                id: id.clone(),
+                name: vault.name.clone().unwrap_or_else(|| id.clone()),
+            })
+            .collect())
+    }
+
+    /// Lists secrets in the given vault
+    fn list_secrets(&self, vault: &id::Vault) -> Result<Vec<types::SecretMetadata>> {
+        self.record("list_secrets");
+        self.with_vault(vault, |vault| {
+            Ok(vault.items.iter().map(|i| i.metadata.clone()).collect())
+        })
+    }
+
+    /// Gets the secret with the given ID
+    fn get_secret(&self, secret: &id::Secret) -> Result<types::Secret> {
+        self.record("get_secret");
+        let vault = id::Vault::new(&id::Account::new(secret.account()), secret.vault());
+        self.with_vault(&vault, |vault| {
+            vault
+                .items
+                .iter()
+                .find(|i| i.metadata.id == secret.secret())
+                .cloned()
+                .ok_or_else(|| Error::NotFound(format!("item {}", secret.secret())).into())
+        })
+    }
+
+    /// Gets the content of a file attached to the given secret
+    fn get_file(&self, secret: &id::Secret, file: &str) -> Result<Vec<u8>> {
+        self.record("get_file");
+        self.with_content(secret, file)
+    }
+
+    /// Gets the content of the given Document item
+    fn get_document(&self, secret: &id::Secret) -> Result<Vec<u8>> {
+        self.record("get_document");
+        self.with_content(secret, secret.secret())
+    }
+
+    /// Gets every secret in the given vault at once
+    fn get_secrets(&self, vault: &id::Vault) -> Result<Vec<types::Secret>> {
+        self.record("get_secrets");
+        self.with_vault(vault, |vault| Ok(vault.items.clone()))
+    }
+
+    /// Sets the value of a field of the given secret
+    fn edit_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        field: &str,
+        value: &str,
+    ) -> Result<types::Secret> {
+        self.record("edit_field");
+        self.edit_item(secret, version, |item| {
+            let target = item
+                .fields
+                .iter_mut()
+                .find(|f| f.id == field)
+                .ok_or_else(|| Error::NotFound(format!("field {field}")))?;
+            target.value = Some(value.to_string());
+            Ok(())
+        })
+    }
+
+    /// Creates an item in the given vault, with a unique ID and the fields of
+    /// the template if any
+    fn create_item(
+        &self,
+        vault: &id::Vault,
+        title: &str,
+        category: &str,
+        template: Option<&serde_json::Value>,
+    ) -> Result<types::Secret> {
+        self.record("create_item");
+        let fields = template
+            .and_then(|template| template["fields"].as_array())
+            .into_iter()
+            .flatten()
+            .map(|field| {
+                let mut field = field.clone();
+                let label = field["label"].as_str().unwrap_or_default();
+                field["reference"] = format!("op://{}/{title}/{label}", vault.vault()).into();
+                serde_json::from_value(field)
+            })
+            .collect::<Result<Vec<types::SecretField>, _>>()?;
+        self.with_vault(vault, |vault| {
+            let now = OffsetDateTime::now_utc();
+            let item = types::Secret {
+                metadata: types::SecretMetadata {
+                    id: unique_id(vault.items.iter().map(|i| &i.metadata.id), "item"),
+                    title: title.to_string(),
+                    version: 1,
+                    category: category.to_string(),
+                    created_at: now,
+                    updated_at: now,
+                    tags: Vec::new(),
+                    favorite: false,
+                    urls: Vec::new(),
+                },
+                fields,
+                sections: Vec::new(),
+                files: Vec::new(),
+            };
+            vault.items.push(item.clone());
+            Ok(item)
+        })
+    }
+
+    /// Adds an empty concealed field to the given secret, with its label as ID
+    /// if available
+    fn add_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        label: &str,
+    ) -> Result<types::Secret> {
+        self.record("add_field");
+        self.edit_item(secret, version, |item| {
+            let id = unique_id(item.fields.iter().map(|f| &f.id), label);
+            item.fields.push(types::SecretField {
+                id,
+                kind: types::FieldType::Concealed,
+                purpose: None,
+                label: label.to_string(),
+                section: None,
+                entropy: None,
+                reference: format!("op://{}/{}/{label}", secret.vault(), item.metadata.title),
+                value: Some(String::new()),
+            });
+            Ok(())
+        })
+    }
+
+    /// Removes a field from the given secret
+    fn delete_field(
+        &self,
+        secret: &id::Secret,
+        version: types::SecretVersion,
+        field: &str,
+    ) -> Result<types::Secret> {
+        self.record("delete_field");
+        self.edit_item(secret, version, |item| {
+            let count = item.fields.len();
+            item.fields.retain(|f| f.id != field);
+            if item.fields.len() == count {
+                return Err(Error::NotFound(format!("field {field}")).into());
+            }
+            Ok(())
+        })
+    }
+
+    /// Deletes the given secret, or moves it to the archived items of its vault
+    fn delete_item(&self, secret: &id::Secret, archive: bool) -> Result<()> {
+        self.record("delete_item");
+        let vault = id::Vault::new(&id::Account::new(secret.account()), secret.vault());
+        self.with_vault(&vault, |vault| {
+            let index = vault
+                .items
+                .iter()
+                .position(|i| i.metadata.id == secret.secret())
+                .ok_or_else(|| Error::NotFound(format!("item {}", secret.secret())))?;
+            let item = vault.items.remove(index);
+            if archive {
+                vault.archived.push(item);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns fixtures with an empty vault, and the ID of the vault
+    fn fixtures() -> (Fixtures, id::Vault) {
+        let vault = id::Vault::new(&id::Account::new("personal"), "private");
+        let fixtures = Fixtures::new(FixtureSet::default());
+        fixtures.update(|set| {
+            set.vault_mut(&vault);
+        });
+        (fixtures, vault)
+    }
+
+    #[test]
+    fn edits_items_at_their_version() {
+        let (fixtures, vault) = fixtures();
+        let item = fixtures
+            .create_item(&vault, "Database", "LOGIN", None)
+            .unwrap();
+        let secret = id::Secret::new(&vault, &item.metadata.id);
+
+        let item = fixtures.add_field(&secret, 1, "token").unwrap();
+        assert_eq!(item.metadata.version, 2);
+        let item = fixtures.edit_field(&secret, 2, "token", "s3cr3t").unwrap();
+        assert_eq!(item.fields[0].value.as_deref(), Some("s3cr3t"));
+
+        let err = fixtures.delete_field(&secret, 2, "token").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Conflict(_))));
+        let err = fixtures.delete_field(&secret, 3, "nope").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+        assert!(fixtures.delete_field(&secret, 3, "token").is_ok());
+    }
+
+    #[test]
+    fn creates_items_from_templates() {
+        let (fixtures, vault) = fixtures();
+        let template = serde_json::json!({
+            "category": "DATABASE",
+            "fields": [{ "id": "username", "type": "STRING", "label": "username", "value": "admin" }],
+        });
+        let item = fixtures
+            .create_item(&vault, "Staging", "DATABASE", Some(&template))
+            .unwrap();
+
+        let field = &item.fields[0];
+        assert_eq!(
+            (field.id.as_str(), field.value.as_deref()),
+            ("username", Some("admin"))
+        );
+        assert_eq!(field.reference, "op://private/Staging/username");
+    }
+
+    #[test]
+    fn counts_calls() {
+        let (fixtures, vault) = fixtures();
+        fixtures.create_item(&vault, "A", "LOGIN", None).unwrap();
+        fixtures.create_item(&vault, "B", "LOGIN", None).unwrap();
+
+        assert_eq!(fixtures.get_secrets(&vault).unwrap().len(), 2);
+        assert_eq!(fixtures.calls("create_item"), 2);
+        assert_eq!(fixtures.calls("get_secrets"), 1);
+        assert_eq!(fixtures.calls("get_secret"), 0);
+    }
+
+    #[test]
+    fn creates_unique_ids() {
+        let (fixtures, vault) = fixtures();
+        let first = fixtures.create_item(&vault, "A", "LOGIN", None).unwrap();
+        let second = fixtures.create_item(&vault, "B", "LOGIN", None).unwrap();
+        assert_eq!(
+            (first.metadata.id.as_str(), second.metadata.id.as_str()),
+            ("item", "item1")
+        );
+
+        let secret = id::Secret::new(&vault, "item");
+        fixtures.add_field(&secret, 1, "key").unwrap();
+        let item = fixtures.add_field(&secret, 2, "key").unwrap();
+        let ids = item
+            .fields
+            .iter()
+            .map(|f| f.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["key", "key1"]);
+    }
+
+    #[test]
+    fn archives_or_deletes_items() {
+        let (fixtures, vault) = fixtures();
+        fixtures.create_item(&vault, "A", "LOGIN", None).unwrap();
+        fixtures.create_item(&vault, "B", "LOGIN", None).unwrap();
+
+        fixtures
+            .delete_item(&id::Secret::new(&vault, "item"), true)
+            .unwrap();
+        fixtures
+            .delete_item(&id::Secret::new(&vault, "item1"), false)
+            .unwrap();
+        let vault = fixtures.update(|set| set.vault_mut(&vault).clone());
+        assert!(vault.items.is_empty());
+        assert_eq!(vault.archived.len(), 1);
+    }
+
+    #[test]
+    fn fails_vault_calls() {
+        let (fixtures, vault) = fixtures();
+        let denied = Error::AuthorizationDenied("denied".to_string());
+        fixtures.fail_vault(&vault, Some(denied));
+        let err = fixtures.list_secrets(&vault).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(Error::AuthorizationDenied(_))
+        ));
+
+        fixtures.fail_vault(&vault, None);
+        assert!(fixtures.list_secrets(&vault).unwrap().is_empty());
+        let other = id::Vault::new(&id::Account::new("personal"), "other");
+        let err = fixtures.list_secrets(&other).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+    }
+}
@@ -1,5 +1,25 @@
 use time::OffsetDateTime;
 
+/// Metadata about an account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountMetadata {
+    /// The ID of the account.
+    pub id: String,
+
+    /// The name of the account.
+    pub name: String,
+}
+
+/// Metadata about a vault.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultMetadata {
+    /// The ID of the vault.
+    pub id: String,
+
+    /// The name of the vault.
+    pub name: String,
+}
+
 /// The version of a secret.
 pub type SecretVersion = u16;
 
@@ -25,6 +45,25 @@ pub struct SecretMetadata {
     /// The update time of the secret.
     #[serde(with = "time::serde::iso8601")]
     pub updated_at: OffsetDateTime,
+
+    /// The tags of the secret.
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Whether the secret is marked as a favorite.
+    #[serde(default)]
+    pub favorite: bool,
+
+    /// The URLs of the secret.
+    #[serde(default)]
+    pub urls: Vec<SecretUrl>,
+}
+
+/// A URL of a secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretUrl {
+    /// The URL, not always including a scheme.
+    pub href: String,
 }
 
 /// A secret.
@@ -36,6 +75,14 @@ pub struct Secret {
 
     /// The fields in the secret.
     pub fields: Vec<SecretField>,
+
+    /// The sections of the secret.
+    #[serde(default)]
+    pub sections: Vec<Section>,
+
+    /// The files attached to the secret, or the file of a Document item.
+    #[serde(default)]
+    pub files: Vec<SecretFile>,
 }
 
 /// A field in a secret.
@@ -44,6 +91,26 @@ pub struct SecretField {
     /// The ID of the field.
     pub id: String,
 
+    /// The type of the field.
+    #[serde(rename = "type", default)]
+    pub kind: FieldType,
+
+    /// The purpose of the field, for the built-in fields of an item.
+    #[serde(default)]
+    pub purpose: Option<FieldPurpose>,
+
+    /// The label of the field.
+    #[serde(default)]
+    pub label: String,
+
+    /// The section the field belongs to, if any.
+    #[serde(default)]
+    pub section: Option<SectionRef>,
+
+    /// The entropy of the field value, for generated passwords.
+    #[serde(default)]
+    pub entropy: Option<f64>,
+
     /// The op://-reference of the field.
     /// Might be empty - or even broken - in some cases.
     pub reference: String,
@@ -52,3 +119,111 @@ pub struct SecretField {
     /// Sometimes not present in the output.
     pub value: Option<String>,
 }
+
+/// A file attached to a secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretFile {
+    /// The ID of the file.
+    pub id: String,
+
+    /// The name of the file.
+    pub name: String,
+
+    /// The size of the file, in bytes.
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// The type of a field.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FieldType {
+    #[default]
+    String,
+    Concealed,
+    Email,
+    Url,
+    Otp,
+    Date,
+    MonthYear,
+    Phone,
+    Address,
+    Menu,
+    Reference,
+    #[serde(rename = "SSHKEY")]
+    SshKey,
+    CreditCardType,
+    CreditCardNumber,
+    #[serde(other)]
+    Unknown,
+}
+
+impl FieldType {
+    /// Returns the name of the type, as used by 1Password.
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldType::String => "STRING",
+            FieldType::Concealed => "CONCEALED",
+            FieldType::Email => "EMAIL",
+            FieldType::Url => "URL",
+            FieldType::Otp => "OTP",
+            FieldType::Date => "DATE",
+            FieldType::MonthYear => "MONTH_YEAR",
+            FieldType::Phone => "PHONE",
+            FieldType::Address => "ADDRESS",
+            FieldType::Menu => "MENU",
+            FieldType::Reference => "REFERENCE",
+            FieldType::SshKey => "SSHKEY",
+            FieldType::CreditCardType => "CREDIT_CARD_TYPE",
+            FieldType::CreditCardNumber => "CREDIT_CARD_NUMBER",
+            FieldType::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// The purpose of a built-in field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FieldPurpose {
+    Username,
+    Password,
+    Notes,
+    #[serde(other)]
+    Unknown,
+}
+
+impl FieldPurpose {
+    /// Returns the name of the purpose, as used by 1Password.
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldPurpose::Username => "USERNAME",
+            FieldPurpose::Password => "PASSWORD",
+            FieldPurpose::Notes => "NOTES",
+            FieldPurpose::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// A section of a secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    /// The ID of the section.
+    pub id: String,
+
+    /// The label of the section.
+    /// Empty for the default section of some items.
+    #[serde(default)]
+    pub label: String,
+}
+
+/// A reference from a field to its section.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionRef {
+    /// The ID of the section.
+    pub id: String,
+
+    /// The label of the section.
+    /// Not always present in the output.
+    #[serde(default)]
+    pub label: Option<String>,
+}
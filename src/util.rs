@@ -1,7 +1,15 @@
 mod diff;
+mod lock;
+mod pool;
+mod process;
 mod sharedcell;
 mod throttle;
+mod totp;
 
 pub use diff::diff;
+pub use lock::Lock;
+pub use pool::Pool;
+pub use process::output_with_timeout;
 pub use sharedcell::SharedCell;
 pub use throttle::Throttle;
+pub use totp::Totp;
@@ -0,0 +1,21 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A mutex that ignores poisoning.
+///
+/// A panic while serving a request must not make the locked value unusable
+/// for the following requests, so the lock is released as if the panic did
+/// not happen.
+#[derive(Default)]
+pub struct Lock<T>(Mutex<T>);
+
+impl<T> Lock<T> {
+    /// Creates a new lock holding the given value.
+    pub fn new(value: T) -> Lock<T> {
+        Lock(Mutex::new(value))
+    }
+
+    /// Locks the value, blocking until it is available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
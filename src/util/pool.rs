@@ -0,0 +1,76 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc},
+    thread,
+};
+
+use super::Lock;
+
+/// A job run by a pool.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed-size pool of threads running jobs in submission order.
+///
+/// A panicking job does not stop its thread, so that the pool keeps its size.
+pub struct Pool {
+    /// The sender of the job queue, dropped to stop the threads.
+    sender: Option<mpsc::Sender<Job>>,
+
+    /// The threads of the pool.
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Pool {
+    /// Starts a pool with the given number of threads, named after the given
+    /// name.
+    pub fn new(name: &str, size: usize) -> Pool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Lock::new(receiver));
+
+        let threads = (0..size.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("{name}-{i}"))
+                    .spawn(move || loop {
+                        // The queue is only locked while waiting for a job
+                        let job = receiver.lock().recv();
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            error!("Job panicked");
+                        }
+                    })
+                    .expect("pool thread should be spawned")
+            })
+            .collect();
+
+        Pool {
+            sender: Some(sender),
+            threads,
+        }
+    }
+
+    /// Queues a job to run on the first available thread.
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender
+            .as_ref()
+            .expect("pool should be running")
+            .send(Box::new(job))
+            .expect("pool threads should be running");
+    }
+}
+
+impl Drop for Pool {
+    /// Waits for the queued jobs to complete.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    io::{self, Read, Write},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::Duration,
+};
+
+use wait_timeout::ChildExt;
+
+/// Runs a command to completion and collects its output, like
+/// `Command::output`, but gives up after the given timeout.
+///
+/// If `input` is given, it is written to the standard input of the command.
+///
+/// The command is started in its own process group. On timeout, the whole
+/// group is killed, so that helpers spawned by the command do not outlive it,
+/// and `None` is returned.
+pub fn output_with_timeout(
+    cmd: &mut Command,
+    input: Option<&[u8]>,
+    timeout: Duration,
+) -> io::Result<Option<Output>> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    thread::scope(|scope| {
+        // Pipes are fed and drained in the background to prevent the child
+        // from blocking on a full pipe while we wait for it.
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+            scope.spawn(move || stdin.write_all(input));
+        }
+        let stdout = drain(scope, child.stdout.take());
+        let stderr = drain(scope, child.stderr.take());
+
+        // The drain threads only return once the child is gone, so it must
+        // be killed before returning, whatever happens.
+        let status = match child.wait_timeout(timeout) {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                kill_group(&mut child);
+                return Ok(None);
+            }
+            Err(err) => {
+                kill_group(&mut child);
+                return Err(err);
+            }
+        };
+
+        Ok(Some(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        }))
+    })
+}
+
+/// Reads a pipe to its end on a background thread.
+fn drain<'scope, R>(
+    scope: &'scope thread::Scope<'scope, '_>,
+    pipe: Option<R>,
+) -> thread::ScopedJoinHandle<'scope, Vec<u8>>
+where
+    R: Read + Send + 'scope,
+{
+    scope.spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Kills the process group of the child and reaps the child.
+fn kill_group(child: &mut Child) {
+    if let Ok(pgid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: `kill` has no memory-safety requirements. The child has not
+        // been reaped yet, so its process group ID cannot have been reused.
+        unsafe {
+            libc::kill(-pgid, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Instant};
+
+    use super::*;
+
+    /// Returns whether the process with the given ID is still running
+    fn running(pid: &str) -> bool {
+        fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| !stat.contains(") Z "))
+    }
+
+    /// Waits for a process to exit, and returns whether it did in time
+    fn exits(pid: &str) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while running(pid) {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn collects_output() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "cat; echo oops >&2; exit 3"]);
+        let output = output_with_timeout(&mut cmd, Some(b"input"), Duration::from_secs(5))
+            .unwrap()
+            .expect("command should complete");
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"input");
+        assert_eq!(output.stderr, b"oops\n");
+    }
+
+    #[test]
+    fn kills_process_group_on_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid = dir.path().join("pid");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 30 & echo $! > \"$0\"; wait"])
+            .arg(&pid);
+
+        let start = Instant::now();
+        let output = output_with_timeout(&mut cmd, None, Duration::from_millis(500)).unwrap();
+        assert!(output.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let pid = fs::read_to_string(pid).unwrap();
+        assert!(exits(pid.trim()), "sleep should be killed");
+    }
+}
@@ -1,28 +1,28 @@
-use std::{
-    cell::{Ref, RefCell, RefMut},
-    rc::Rc,
-};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-/// A `Rc<RefCell<T>>` used to share a mutable value between multiple owners.
+/// An `Arc<RwLock<T>>` used to share a mutable value between multiple owners,
+/// possibly on different threads.
+///
+/// As with [`Lock`](super::Lock), poisoning is ignored.
 pub struct SharedCell<T> {
-    inner: Rc<RefCell<T>>,
+    inner: Arc<RwLock<T>>,
 }
 
 impl<T> SharedCell<T> {
     pub fn new(inner: T) -> SharedCell<T> {
         SharedCell {
-            inner: Rc::new(RefCell::new(inner)),
+            inner: Arc::new(RwLock::new(inner)),
         }
     }
 
-    /// Immutably borrows the value.
-    pub fn borrow(&self) -> Ref<T> {
-        (*self.inner).borrow()
+    /// Locks the value for reading.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
     }
 
-    /// Mutably borrows the value.
-    pub fn borrow_mut(&self) -> RefMut<T> {
-        (*self.inner).borrow_mut()
+    /// Locks the value for writing.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
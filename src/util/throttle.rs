@@ -1,16 +1,38 @@
 use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
     ops::Deref,
     time::{Duration, Instant},
 };
 
 use anyhow::Result;
 
+use crate::{config::Failures, onepassword::Error};
+
 /// A value that is only refreshed after a certain amount of time has passed.
+///
+/// Failed refreshes are retried with an exponential backoff, during which the
+/// last known value is served if allowed, or the last error is returned.
 pub struct Throttle<T> {
     last_update: Option<Instant>,
+    failure: Option<Failure>,
+    epoch: u64,
     value: T,
 }
 
+/// The consecutive failed refreshes of a value.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// The error of the last refresh.
+    pub error: Error,
+
+    /// The number of consecutive failed refreshes.
+    pub attempts: u32,
+
+    /// The time before which the refresh is not retried.
+    pub retry_at: Instant,
+}
+
 impl<T> Throttle<T> {
     /// Creates a new `Throttle` with the given value.
     /// The value will be considered fresh.
@@ -18,24 +40,145 @@ impl<T> Throttle<T> {
     pub fn new(value: T) -> Throttle<T> {
         Throttle {
             last_update: Some(Instant::now()),
+            failure: None,
+            epoch: 0,
             value,
         }
     }
 
+    /// Returns the time elapsed since the value was last refreshed, or `None`
+    /// if it never was.
+    pub fn age(&self) -> Option<Duration> {
+        self.last_update.map(|last_update| last_update.elapsed())
+    }
+
+    /// Returns the number of times the value was changed, to detect the
+    /// changes made while a refresh is fetched without holding the value.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns the failure of the last refreshes, if the last one failed.
+    pub fn failure(&self) -> Option<&Failure> {
+        self.failure.as_ref()
+    }
+
+    /// Returns whether the value is older than the given `max_age`, or will be
+    /// within `ahead`, and a failed refresh is not waiting to be retried.
+    pub fn is_due(&self, max_age: Duration, ahead: Duration) -> bool {
+        let backoff = self
+            .failure
+            .as_ref()
+            .is_some_and(|failure| Instant::now() < failure.retry_at);
+        !backoff
+            && self
+                .age()
+                .is_none_or(|age| age.saturating_add(ahead) >= max_age)
+    }
+
     /// Refreshes the value if it is older than the given `max_age`.
-    pub fn try_refresh<U>(&mut self, max_age: Duration, try_refresh: U) -> Result<()>
+    ///
+    /// If the refresh fails, or a failed refresh is waiting to be retried, the
+    /// value is served as is if allowed by `failures`, otherwise the error is
+    /// returned. Values that are never cached, i.e. with a zero `max_age`,
+    /// are never served after a failure either.
+    pub fn try_refresh<U>(
+        &mut self,
+        max_age: Duration,
+        failures: &Failures,
+        try_refresh: U,
+    ) -> Result<()>
     where
         U: FnOnce(&mut T) -> Result<()>,
     {
-        let should_update = match self.last_update {
-            None => true,
-            Some(last_update) => last_update.elapsed() >= max_age,
+        if self.age().is_some_and(|age| age < max_age) {
+            return Ok(());
+        }
+        if !self.is_due(max_age, Duration::ZERO) {
+            return self.fallback(max_age, failures);
+        }
+
+        match try_refresh(&mut self.value) {
+            Ok(()) => {
+                self.epoch += 1;
+                self.last_update = Some(Instant::now());
+                self.failure = None;
+                Ok(())
+            }
+            Err(err) => {
+                self.fail(failures, &err);
+                self.fallback(max_age, failures).map_err(|_| err)
+            }
+        }
+    }
+
+    /// Records a failed refresh, to be retried after a backoff.
+    pub fn fail(&mut self, failures: &Failures, err: &anyhow::Error) {
+        let attempts = self.failure.as_ref().map_or(0, |f| f.attempts) + 1;
+        let backoff = failures.backoff(attempts).mul_f64(1.0 - jitter() / 4.0);
+        warn!(err = %err, attempts, backoff = ?backoff, "Refresh failed");
+
+        self.failure = Some(Failure {
+            error: err
+                .downcast_ref::<Error>()
+                .cloned()
+                .unwrap_or_else(|| Error::Other(format!("{err:#}"))),
+            attempts,
+            retry_at: Instant::now() + backoff,
+        });
+    }
+
+    /// Serves the current value after a failed refresh, unless there is none
+    /// or it should not be served.
+    fn fallback(&self, max_age: Duration, failures: &Failures) -> Result<()> {
+        let Some(failure) = &self.failure else {
+            return Ok(());
         };
-        if should_update {
-            self.last_update = Some(Instant::now());
-            try_refresh(&mut self.value)?;
+
+        // A missing item or vault must not be served from the cache, nor a
+        // value that is not to be cached at all
+        let found = !matches!(failure.error, Error::NotFound(_));
+        let cached = !max_age.is_zero() && self.last_update.is_some();
+        if failures.serve_stale && found && cached {
+            Ok(())
+        } else {
+            Err(failure.error.clone().into())
+        }
+    }
+
+    /// Modifies the value without marking it as fresh, e.g. to apply a change
+    /// made locally, and returns the result of `modify`.
+    pub fn modify<U, R>(&mut self, modify: U) -> R
+    where
+        U: FnOnce(&mut T) -> R,
+    {
+        self.epoch += 1;
+        modify(&mut self.value)
+    }
+
+    /// Updates the value unconditionally and marks it as fresh.
+    pub fn update<U>(&mut self, update: U)
+    where
+        U: FnOnce(&mut T),
+    {
+        self.epoch += 1;
+        self.last_update = Some(Instant::now());
+        self.failure = None;
+        update(&mut self.value);
+    }
+
+    /// Updates the value and marks it as fresh, unless it was changed since
+    /// the given epoch. The update is then discarded, as it was fetched
+    /// before the change and would revert it.
+    pub fn update_since<U>(&mut self, epoch: u64, update: U)
+    where
+        U: FnOnce(&mut T),
+    {
+        if self.epoch == epoch {
+            self.update(update);
+        } else {
+            debug!("Discarding refresh fetched before a change");
         }
-        Ok(())
     }
 }
 
@@ -45,6 +188,8 @@ impl<T: Default> Default for Throttle<T> {
     fn default() -> Throttle<T> {
         Throttle {
             last_update: None,
+            failure: None,
+            epoch: 0,
             value: Default::default(),
         }
     }
@@ -57,3 +202,143 @@ impl<T> Deref for Throttle<T> {
         &self.value
     }
 }
+
+/// Returns a random number between 0 and 1, so that values failing together
+/// are not retried together.
+fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a failure policy retrying after a minute
+    fn failures(serve_stale: bool) -> Failures {
+        Failures {
+            serve_stale,
+            min_backoff: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    /// Returns a failed refresh with the given error
+    fn fail(err: Error) -> impl FnOnce(&mut u32) -> Result<()> {
+        move |_| Err(err.into())
+    }
+
+    #[test]
+    fn refreshes_expired_values_only() {
+        let mut throttle = Throttle::<u32>::default();
+        let max_age = Duration::from_secs(60);
+        assert!(throttle.is_due(max_age, Duration::ZERO));
+
+        let refresh = |value: &mut u32| {
+            *value += 1;
+            Ok(())
+        };
+        throttle
+            .try_refresh(max_age, &failures(true), refresh)
+            .unwrap();
+        throttle
+            .try_refresh(max_age, &failures(true), refresh)
+            .unwrap();
+        assert_eq!(*throttle, 1);
+        assert!(!throttle.is_due(max_age, Duration::ZERO));
+        assert!(throttle.is_due(max_age, max_age));
+
+        // A value that is never cached is always refreshed
+        throttle
+            .try_refresh(Duration::ZERO, &failures(true), refresh)
+            .unwrap();
+        assert_eq!(*throttle, 2);
+    }
+
+    #[test]
+    fn serves_last_value_on_failures() {
+        let mut throttle = Throttle::new(1u32);
+        let max_age = Duration::from_nanos(1); // Expired right away
+        let limited = Error::RateLimited("slow down".to_string());
+        throttle
+            .try_refresh(max_age, &failures(true), fail(limited.clone()))
+            .unwrap();
+        assert_eq!(throttle.failure().map(|f| f.attempts), Some(1));
+
+        // The refresh is not retried before the backoff elapses
+        let retried = throttle.try_refresh(max_age, &failures(true), |_| {
+            panic!("refresh should wait for the backoff")
+        });
+        assert!(retried.is_ok());
+        assert!(!throttle.is_due(max_age, Duration::ZERO));
+
+        let err = throttle
+            .try_refresh(max_age, &failures(false), |_| Ok(()))
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::RateLimited(_))));
+        assert_eq!(*throttle, 1);
+    }
+
+    #[test]
+    fn fails_without_value_or_when_not_found() {
+        let max_age = Duration::from_nanos(1);
+        let limited = Error::RateLimited("slow down".to_string());
+        let mut never_fetched = Throttle::<u32>::default();
+        assert!(never_fetched
+            .try_refresh(max_age, &failures(true), fail(limited.clone()))
+            .is_err());
+
+        let mut removed = Throttle::new(1u32);
+        let missing = Error::NotFound("item".to_string());
+        assert!(removed
+            .try_refresh(max_age, &failures(true), fail(missing))
+            .is_err());
+
+        // Values that are never cached are not served after a failure
+        let mut uncached = Throttle::new(1u32);
+        assert!(uncached
+            .try_refresh(Duration::ZERO, &failures(true), fail(limited))
+            .is_err());
+        assert!(uncached
+            .try_refresh(Duration::ZERO, &failures(true), |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn counts_consecutive_failures() {
+        let mut throttle = Throttle::new(0u32);
+        let err = anyhow::anyhow!("op crashed");
+        throttle.fail(&failures(true), &err);
+        throttle.fail(&failures(true), &err);
+        let failure = throttle.failure().expect("failure should be recorded");
+        assert_eq!(failure.attempts, 2);
+        assert!(matches!(&failure.error, Error::Other(message) if message == "op crashed"));
+
+        // A successful update resets the failures
+        throttle.update(|value| *value = 1);
+        assert!(throttle.failure().is_none());
+    }
+
+    #[test]
+    fn modifies_without_refreshing() {
+        let mut throttle = Throttle::<u32>::default();
+        throttle.modify(|value| *value = 1);
+        assert_eq!(*throttle, 1);
+        assert!(throttle.age().is_none());
+    }
+
+    #[test]
+    fn discards_updates_fetched_before_changes() {
+        let mut throttle = Throttle::<u32>::default();
+        let epoch = throttle.epoch();
+        throttle.modify(|value| *value = 1);
+        throttle.update_since(epoch, |value| *value = 2);
+        assert_eq!(*throttle, 1);
+        assert!(throttle.age().is_none());
+
+        let epoch = throttle.epoch();
+        throttle.update_since(epoch, |value| *value = 3);
+        assert_eq!(*throttle, 3);
+        assert!(throttle.age().is_some());
+    }
+}
@@ -0,0 +1,241 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{digest::KeyInit, Hmac, Mac};
+
+/// A time-based one-time password generator, as described in RFC 6238.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Totp {
+    /// The shared secret.
+    secret: Vec<u8>,
+
+    /// The HMAC algorithm.
+    algorithm: Algorithm,
+
+    /// The number of digits of the codes.
+    digits: u32,
+
+    /// The validity period of a code, in seconds.
+    period: u64,
+}
+
+/// The HMAC algorithm used to compute the codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Totp {
+    /// Parses a TOTP generator from an `otpauth://totp/` URI, or from a bare
+    /// base32 secret using the default parameters.
+    ///
+    /// Returns `None` if the value is not a valid TOTP definition.
+    pub fn parse(value: &str) -> Option<Totp> {
+        let value = value.trim();
+        let Some(uri) = value.strip_prefix("otpauth://") else {
+            return Some(Totp {
+                secret: decode_secret(value)?,
+                algorithm: Algorithm::Sha1,
+                digits: 6,
+                period: 30,
+            });
+        };
+
+        let (kind, query) = uri.split_once('?')?;
+        if !kind.to_lowercase().starts_with("totp/") {
+            return None;
+        }
+
+        let mut totp = Totp {
+            secret: Vec::new(),
+            algorithm: Algorithm::Sha1,
+            digits: 6,
+            period: 30,
+        };
+        for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+            match key.to_lowercase().as_str() {
+                "secret" => totp.secret = decode_secret(value)?,
+                "algorithm" => {
+                    totp.algorithm = match value.to_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => return None,
+                    }
+                }
+                "digits" => totp.digits = value.parse().ok().filter(|d| (1..=10).contains(d))?,
+                "period" => totp.period = value.parse().ok().filter(|p| *p > 0)?,
+                _ => {}
+            }
+        }
+
+        (!totp.secret.is_empty()).then_some(totp)
+    }
+
+    /// Returns the number of digits of the codes.
+    pub fn digits(&self) -> u32 {
+        self.digits
+    }
+
+    /// Returns the validity period of a code, in seconds.
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Returns the code valid at the given Unix time.
+    pub fn code(&self, time: u64) -> String {
+        let counter = (time / self.period).to_be_bytes();
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => hmac::<Hmac<sha1::Sha1>>(&self.secret, &counter),
+            Algorithm::Sha256 => hmac::<Hmac<sha2::Sha256>>(&self.secret, &counter),
+            Algorithm::Sha512 => hmac::<Hmac<sha2::Sha512>>(&self.secret, &counter),
+        };
+
+        // Dynamic truncation, as described in RFC 4226
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+
+        let code = u64::from(binary) % 10u64.pow(self.digits);
+        format!("{code:0width$}", width = self.digits as usize)
+    }
+}
+
+/// Computes the HMAC of a message with the given MAC type.
+fn hmac<M>(key: &[u8], message: &[u8]) -> Vec<u8>
+where
+    M: Mac + KeyInit,
+{
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC should accept any key");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Decodes a base32 secret, ignoring case, spaces and padding.
+fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    let secret = secret
+        .replace("%3D", "")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .collect::<String>()
+        .to_uppercase();
+    BASE32_NOPAD
+        .decode(secret.as_bytes())
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 4226 and RFC 6238 test secret, repeated to the size of the hash
+    fn secret(len: usize) -> Vec<u8> {
+        b"1234567890".iter().copied().cycle().take(len).collect()
+    }
+
+    fn totp(algorithm: Algorithm, len: usize, digits: u32, period: u64) -> Totp {
+        Totp {
+            secret: secret(len),
+            algorithm,
+            digits,
+            period,
+        }
+    }
+
+    #[test]
+    fn computes_hotp_vectors() {
+        // RFC 4226 appendix D, with one counter step per second
+        let hotp = totp(Algorithm::Sha1, 20, 6, 1);
+        let codes = (0..10)
+            .map(|counter| hotp.code(counter))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+                "399871", "520489"
+            ]
+        );
+    }
+
+    #[test]
+    fn computes_totp_vectors() {
+        // RFC 6238 appendix B
+        let sha1 = totp(Algorithm::Sha1, 20, 8, 30);
+        let sha256 = totp(Algorithm::Sha256, 32, 8, 30);
+        let sha512 = totp(Algorithm::Sha512, 64, 8, 30);
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1_111_111_109, "07081804", "68084774", "25091201"),
+            (1_111_111_111, "14050471", "67062674", "99943326"),
+            (1_234_567_890, "89005924", "91819424", "93441116"),
+            (2_000_000_000, "69279037", "90698825", "38618901"),
+            (20_000_000_000, "65353130", "77737706", "47863826"),
+        ];
+
+        for (time, expected1, expected256, expected512) in vectors {
+            assert_eq!(sha1.code(time), expected1, "SHA1 at {time}");
+            assert_eq!(sha256.code(time), expected256, "SHA256 at {time}");
+            assert_eq!(sha512.code(time), expected512, "SHA512 at {time}");
+        }
+    }
+
+    #[test]
+    fn pads_codes_with_zeros() {
+        let totp = totp(Algorithm::Sha1, 20, 10, 30);
+        assert_eq!(totp.code(59), "1094287082");
+        assert_eq!(totp.code(1_111_111_109), "0907081804");
+    }
+
+    #[test]
+    fn parses_otpauth_uris() {
+        let totp = Totp::parse(
+            "otpauth://totp/ACME:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=ACME\
+             &algorithm=sha256&digits=8&period=60",
+        )
+        .unwrap();
+        assert_eq!(totp.secret, secret(20));
+        assert_eq!(totp.algorithm, Algorithm::Sha256);
+        assert_eq!((totp.digits(), totp.period()), (8, 60));
+
+        let defaults = Totp::parse("otpauth://TOTP/alice?secret=gezdgnbvgy3tqojq%3D").unwrap();
+        assert_eq!(defaults.secret, b"1234567890");
+        assert_eq!(defaults.algorithm, Algorithm::Sha1);
+        assert_eq!((defaults.digits(), defaults.period()), (6, 30));
+    }
+
+    #[test]
+    fn parses_bare_secrets() {
+        let totp = Totp::parse(" gezd gnbv gy3t qojq ").unwrap();
+        assert_eq!(totp.secret, b"1234567890");
+        assert_eq!((totp.digits(), totp.period()), (6, 30));
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let base = "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ";
+        assert!(Totp::parse(&format!("{base}&digits=10")).is_some());
+
+        for invalid in [
+            format!("{base}&digits=0"),
+            format!("{base}&digits=11"),
+            format!("{base}&digits=six"),
+            format!("{base}&period=0"),
+            format!("{base}&algorithm=MD5"),
+            "otpauth://totp/alice?secret=GEZD1!".to_string(),
+            "otpauth://totp/alice?secret=".to_string(),
+            "otpauth://totp/alice?issuer=ACME".to_string(),
+            "otpauth://totp/alice".to_string(),
+            "otpauth://hotp/alice?secret=GEZDGNBVGY3TQOJQ&counter=0".to_string(),
+            "not base32!".to_string(),
+            String::new(),
+        ] {
+            assert_eq!(Totp::parse(&invalid), None, "{invalid}");
+        }
+    }
+}